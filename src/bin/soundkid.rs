@@ -1,19 +1,62 @@
 use anyhow::{Context, Result, anyhow};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use soundkid::{
-    config::Config,
-    player::{PlayerControl, SpotifyPlayer},
-    reader::{Input, setup_gpio_line, spawn_evdev_reader, spawn_gpio_reader},
+    cards::CardStore,
+    config::{Config, RfidDriver},
+    control,
+    hotplug::{self, DeviceStates},
+    http::{self, Api},
+    mqtt,
+    player::SpotifyPlayer,
+    reader::{
+        setup_gpio_line, setup_mfrc522, setup_pn532, setup_rdm6300, setup_rotary,
+        spawn_evdev_reader, spawn_gpio_reader, spawn_mfrc522_reader, spawn_pn532_reader,
+        spawn_pseudo_reader, spawn_rdm6300_reader, spawn_rotary_reader,
+    },
     runtime::handle_input,
+    status::StatusBoard,
 };
+use std::path::Path;
 use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::mpsc;
-use tracing::{debug, info};
+use tracing::info;
 use tracing_subscriber::{EnvFilter, fmt};
 
 #[derive(Parser, Debug)]
 #[command(name = "soundkid", version, about = "Sound player for kids")]
-struct Cli {}
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Put the running soundkid in learn mode: the next new card plays URI,
+    /// or the next one from learn.queue, or what was last played remotely.
+    /// Needs control.socket in the config.
+    Learn {
+        /// A spotify: URI or open.spotify.com link.
+        uri: Option<String>,
+    },
+}
+
+/// Pass a subcommand to the running soundkid over its control socket.
+async fn send(conf: &Config, line: &str) -> Result<()> {
+    let control = conf
+        .control
+        .as_ref()
+        .ok_or_else(|| anyhow!("no control socket configured; add control.socket to the config"))?;
+    let reply = control::request(&control.socket, line)
+        .await
+        .with_context(|| format!("talking to soundkid at {}", control.socket.display()))?;
+    match reply.strip_prefix("error: ") {
+        Some(e) => Err(anyhow!("{e}")),
+        None => {
+            println!("{reply}");
+            Ok(())
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -23,42 +66,121 @@ async fn main() -> Result<()> {
         )
         .with_target(false)
         .init();
-    let _cli = Cli::parse();
-    info!("Starting soundkid ...");
-
+    let cli = Cli::parse();
     let conf = Config::load().await.context("loading configuration")?;
+    if let Some(Command::Learn { uri }) = cli.command {
+        let line = match uri {
+            Some(uri) => format!("learn {uri}"),
+            None => "learn".to_string(),
+        };
+        return send(&conf, &line).await;
+    }
+
+    info!("Starting soundkid ...");
 
     let (events_tx, events_rx) = mpsc::channel(100);
+    let status = StatusBoard::default();
+    let devices = DeviceStates::new(status.clone());
+    let mut evdev_readers = Vec::new();
 
     if conf.input.is_empty() {
         info!("No input config found, skipping evdev handling");
     } else {
-        for device_desc in conf.input.keys() {
-            let reader = Input::new(device_desc)
-                .with_context(|| format!("opening input device {device_desc:?}"))?;
-            debug!("Got input reader {:?}", reader.device_desc);
-            spawn_evdev_reader(reader, events_tx.clone());
+        // Devices that aren't plugged in yet are attached when they show up.
+        let hotplug = hotplug::spawn_watcher(Path::new(hotplug::INPUT_DIR));
+        for (device_desc, device) in &conf.input {
+            if conf
+                .pseudo
+                .as_ref()
+                .is_some_and(|p| p.device == *device_desc)
+            {
+                info!("Input device {device_desc:?} is fed by the pseudo input instead");
+                continue;
+            }
+            evdev_readers.push(spawn_evdev_reader(
+                device_desc.clone(),
+                device,
+                events_tx.clone(),
+                hotplug.clone(),
+                devices.clone(),
+            ));
         }
     }
 
-    for (device, lines) in conf.gpio.clone() {
+    for (device, chip) in &conf.gpio {
         info!("Found config for GPIO device {device}");
-        for line in lines.keys() {
-            let events = setup_gpio_line(&device, *line)
+        for (line, line_conf) in &chip.lines {
+            let edges = setup_gpio_line(device, *line, &line_conf.into())
                 .with_context(|| format!("setting up GPIO {device:?}/{line} from config"))?;
-            spawn_gpio_reader(device.clone(), *line, events, events_tx.clone());
+            spawn_gpio_reader(device.clone(), *line, line_conf, edges, events_tx.clone());
         }
     }
 
-    let (player, mut player_join) = SpotifyPlayer::new(&conf.spotify)
+    for (encoder, rotary) in &conf.rotary {
+        let lines = setup_rotary(rotary)
+            .with_context(|| format!("setting up rotary encoder {encoder:?} from config"))?;
+        spawn_rotary_reader(encoder.clone(), rotary, lines, events_tx.clone());
+    }
+
+    for (reader, rfid) in &conf.rfid {
+        match rfid.driver {
+            RfidDriver::Mfrc522 => {
+                let chip = setup_mfrc522(rfid)
+                    .with_context(|| format!("setting up RFID reader {reader:?} from config"))?;
+                spawn_mfrc522_reader(reader.clone(), rfid, chip, events_tx.clone());
+            }
+            RfidDriver::Pn532 => {
+                let chip = setup_pn532(rfid)
+                    .with_context(|| format!("setting up RFID reader {reader:?} from config"))?;
+                spawn_pn532_reader(reader.clone(), rfid, chip, events_tx.clone());
+            }
+            RfidDriver::Rdm6300 => {
+                let port = setup_rdm6300(rfid)
+                    .with_context(|| format!("setting up RFID reader {reader:?} from config"))?;
+                spawn_rdm6300_reader(reader.clone(), rfid, port, events_tx.clone());
+            }
+        }
+    }
+
+    if let Some(pseudo) = &conf.pseudo {
+        spawn_pseudo_reader(pseudo, events_tx.clone());
+    }
+
+    let cards = CardStore::load(&conf.spotify.cache_dir).await;
+    if let Some(control) = &conf.control {
+        let listener = control::bind(&control.socket)
+            .with_context(|| format!("binding control socket {}", control.socket.display()))?;
+        control::spawn_control_socket(listener, events_tx.clone(), status.clone());
+    }
+    if let Some(conf_http) = &conf.http {
+        let listener = http::setup_http(conf_http)
+            .await
+            .with_context(|| format!("binding HTTP API to {}", conf_http.bind))?;
+        let api = Api::new(&conf, events_tx.clone(), status.clone(), cards.clone());
+        http::spawn_http_server(listener, api);
+    }
+    if let Some(conf_mqtt) = &conf.mqtt {
+        mqtt::spawn_mqtt(conf_mqtt, events_tx.clone(), status.clone());
+    }
+
+    let (player, mut player_join) = SpotifyPlayer::new(&conf.spotify, &conf.player, status.clone())
         .await
         .context("setting up Spotify player")?;
 
     let mut sigterm = signal(SignalKind::terminate()).context("install SIGTERM handler")?;
     let mut sigint = signal(SignalKind::interrupt()).context("install SIGINT handler")?;
+    let mut sigusr1 = signal(SignalKind::user_defined1()).context("install SIGUSR1 handler")?;
+    // `kill -USR1` logs where every input device stands.
+    tokio::spawn(async move {
+        while sigusr1.recv().await.is_some() {
+            for (device, state) in devices.snapshot() {
+                info!("Status: input device {device:?} {state}");
+            }
+        }
+    });
 
     let result: Result<()> = tokio::select! {
-        result = handle_input(conf, events_rx, player.clone()) => result.map_err(Into::into),
+        result = handle_input(conf, events_rx, player.clone(), status, cards) => result.map_err(Into::into),
         join = &mut player_join => match join {
             Ok(()) => Err(anyhow!("player task exited unexpectedly")),
             Err(e) => Err(anyhow!("player task panicked: {e}")),
@@ -73,8 +195,14 @@ async fn main() -> Result<()> {
         }
     };
 
-    // Best-effort: silence the speaker before tearing down the runtime so we
-    // don't leave a half-decoded buffer in the audio pipeline.
-    let _ = player.stop().await;
+    // Best-effort: save the playback position and silence the speaker before
+    // tearing down the runtime so we don't leave a half-decoded buffer in the
+    // audio pipeline, and the next start can resume where we stopped.
+    let _ = player.shutdown().await;
+    // Close the input devices, so grabbed ones go back to the console.
+    for reader in evdev_readers {
+        reader.abort();
+        let _ = reader.await;
+    }
     result
 }
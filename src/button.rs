@@ -0,0 +1,358 @@
+use std::time::Duration;
+
+use crate::config::ConfigButton;
+use crate::input::Press;
+
+/// Turns a button's press and release edges into short, long and double
+/// presses, plus auto-repeat while a long press is held.
+///
+/// Times are offsets on a single monotonic clock (the kernel's GPIO event
+/// timestamps); the caller converts `deadline` into a timer and calls `tick`
+/// when it fires. Gestures that aren't configured cost no latency: a line
+/// with only a short action fires on the press edge, and short presses only
+/// wait out the double-press window when a double action exists.
+#[derive(Debug)]
+pub struct PressDetector {
+    long: Option<Duration>,
+    double: Option<Duration>,
+    repeat: Option<Duration>,
+    state: ButtonState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ButtonState {
+    Released,
+    /// Down since `since`. `second` marks the second press of a possible
+    /// double press; `handled` means this press already produced its gesture
+    /// and the release is just noise. `next` is when the long press (or the
+    /// next auto-repeat) is due.
+    Held {
+        since: Duration,
+        second: bool,
+        handled: bool,
+        next: Option<Duration>,
+    },
+    /// A short press ended; a second press before `until` makes it a double.
+    AwaitingSecond {
+        until: Duration,
+    },
+}
+
+impl PressDetector {
+    pub fn new(button: &ConfigButton) -> Self {
+        Self {
+            long: button
+                .long
+                .as_ref()
+                .map(|_| Duration::from_millis(button.long_ms)),
+            double: button
+                .double
+                .as_ref()
+                .map(|_| Duration::from_millis(button.double_ms)),
+            repeat: button.repeat_ms.map(Duration::from_millis),
+            state: ButtonState::Released,
+        }
+    }
+
+    /// A detector for a button without gestures: every press is short.
+    pub fn immediate() -> Self {
+        Self {
+            long: None,
+            double: None,
+            repeat: None,
+            state: ButtonState::Released,
+        }
+    }
+
+    /// Feed one edge: `pressed` is true when the button went down.
+    pub fn edge(&mut self, pressed: bool, at: Duration) -> Vec<Press> {
+        match (pressed, self.state) {
+            (true, ButtonState::Released) if self.long.is_none() && self.double.is_none() => {
+                self.state = ButtonState::Held {
+                    since: at,
+                    second: false,
+                    handled: true,
+                    next: None,
+                };
+                vec![Press::Short]
+            }
+            (true, ButtonState::Released) => {
+                self.hold(at, false);
+                vec![]
+            }
+            (true, ButtonState::AwaitingSecond { .. }) => {
+                self.hold(at, true);
+                vec![]
+            }
+            (
+                false,
+                ButtonState::Held {
+                    since,
+                    second,
+                    handled,
+                    ..
+                },
+            ) => {
+                self.state = ButtonState::Released;
+                if handled {
+                    return vec![];
+                }
+                if self
+                    .long
+                    .is_some_and(|long| at.saturating_sub(since) >= long)
+                {
+                    // Released after the threshold but before our timer ran.
+                    return if second {
+                        vec![Press::Short, Press::Long]
+                    } else {
+                        vec![Press::Long]
+                    };
+                }
+                if second {
+                    return vec![Press::Double];
+                }
+                match self.double {
+                    Some(window) => {
+                        self.state = ButtonState::AwaitingSecond { until: at + window };
+                        vec![]
+                    }
+                    None => vec![Press::Short],
+                }
+            }
+            // A repeated press edge while held, or a release we never saw
+            // the press for: nothing to do.
+            _ => vec![],
+        }
+    }
+
+    /// When `tick` next has something to do, if ever.
+    pub fn deadline(&self) -> Option<Duration> {
+        match self.state {
+            ButtonState::Held { next, .. } => next,
+            ButtonState::AwaitingSecond { until } => Some(until),
+            ButtonState::Released => None,
+        }
+    }
+
+    /// Advance the clock to `now` and return any gestures that became due.
+    pub fn tick(&mut self, now: Duration) -> Vec<Press> {
+        match self.state {
+            ButtonState::AwaitingSecond { until } if now >= until => {
+                self.state = ButtonState::Released;
+                vec![Press::Short]
+            }
+            ButtonState::Held {
+                since,
+                second,
+                handled,
+                next: Some(due),
+            } if now >= due => {
+                self.state = ButtonState::Held {
+                    since,
+                    second: false,
+                    handled: true,
+                    next: self.repeat.map(|r| due + r),
+                };
+                match (handled, second) {
+                    (true, _) => vec![Press::Repeat],
+                    // The first press of the pair was short on its own.
+                    (false, true) => vec![Press::Short, Press::Long],
+                    (false, false) => vec![Press::Long],
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    fn hold(&mut self, at: Duration, second: bool) {
+        self.state = ButtonState::Held {
+            since: at,
+            second,
+            handled: false,
+            next: self.long.map(|long| at + long),
+        };
+    }
+}
+
+/// Software debounce for a line's edges, for kernels that can't do it.
+///
+/// The first edge that changes the level passes straight through, and the
+/// line is then ignored for the debounce window. If the raw level at the end
+/// of the window differs from what was reported, `tick` reports that change
+/// too, so a release inside the window isn't lost.
+#[derive(Debug)]
+pub struct EdgeFilter {
+    window: Duration,
+    /// Last level passed on.
+    level: bool,
+    /// Level of the latest raw edge.
+    raw: bool,
+    quiet_until: Option<Duration>,
+}
+
+impl EdgeFilter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            level: false,
+            raw: false,
+            quiet_until: None,
+        }
+    }
+
+    /// Feed one raw edge. Returns the new level if it should be passed on.
+    pub fn edge(&mut self, level: bool, at: Duration) -> Option<bool> {
+        self.raw = level;
+        if self.quiet_until.is_some_and(|until| at < until) || level == self.level {
+            return None;
+        }
+        self.level = level;
+        self.quiet_until = Some(at + self.window);
+        Some(level)
+    }
+
+    /// When the window ends with the line settled on a different level.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.quiet_until.filter(|_| self.raw != self.level)
+    }
+
+    /// At the deadline, returns the settled level and the time it counts at.
+    pub fn tick(&mut self, now: Duration) -> Option<(bool, Duration)> {
+        let until = self.deadline().filter(|until| now >= *until)?;
+        self.level = self.raw;
+        self.quiet_until = Some(until + self.window);
+        Some((self.level, until))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(yaml: &str) -> ConfigButton {
+        serde_yaml_ng::from_str(yaml).expect("test button config must parse")
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    const GESTURES: &str =
+        "{ short: PAUSE, long: VOLUME_DECREASE, double: RESUME, long_ms: 800, double_ms: 300 }";
+
+    #[test]
+    fn short_only_fires_on_press_edge() {
+        let mut d = PressDetector::new(&line("{ short: PAUSE }"));
+        assert_eq!(d.edge(true, ms(0)), vec![Press::Short]);
+        assert_eq!(d.deadline(), None);
+        assert_eq!(d.edge(false, ms(5000)), vec![]);
+    }
+
+    #[test]
+    fn short_press_without_double_fires_on_release() {
+        let mut d = PressDetector::new(&line("{ short: PAUSE, long: RESUME }"));
+        assert_eq!(d.edge(true, ms(0)), vec![]);
+        assert_eq!(d.edge(false, ms(100)), vec![Press::Short]);
+        assert_eq!(d.deadline(), None);
+    }
+
+    #[test]
+    fn short_press_waits_out_double_window() {
+        let mut d = PressDetector::new(&line(GESTURES));
+        d.edge(true, ms(0));
+        assert_eq!(d.edge(false, ms(100)), vec![]);
+        assert_eq!(d.deadline(), Some(ms(400)));
+        assert_eq!(d.tick(ms(399)), vec![]);
+        assert_eq!(d.tick(ms(400)), vec![Press::Short]);
+        assert_eq!(d.deadline(), None);
+    }
+
+    #[test]
+    fn two_short_presses_in_window_are_double() {
+        let mut d = PressDetector::new(&line(GESTURES));
+        d.edge(true, ms(0));
+        d.edge(false, ms(100));
+        assert_eq!(d.edge(true, ms(250)), vec![]);
+        assert_eq!(d.edge(false, ms(330)), vec![Press::Double]);
+        assert_eq!(d.deadline(), None);
+    }
+
+    #[test]
+    fn long_press_fires_when_threshold_passes_while_held() {
+        let mut d = PressDetector::new(&line(GESTURES));
+        d.edge(true, ms(0));
+        assert_eq!(d.deadline(), Some(ms(800)));
+        assert_eq!(d.tick(ms(800)), vec![Press::Long]);
+        assert_eq!(d.deadline(), None);
+        assert_eq!(d.edge(false, ms(1500)), vec![]);
+    }
+
+    #[test]
+    fn long_press_detected_on_release_if_timer_was_late() {
+        let mut d = PressDetector::new(&line(GESTURES));
+        d.edge(true, ms(0));
+        assert_eq!(d.edge(false, ms(900)), vec![Press::Long]);
+    }
+
+    #[test]
+    fn long_press_auto_repeats_while_held() {
+        let mut d = PressDetector::new(&line(
+            "{ short: NEXT, long: VOLUME_DECREASE, long_ms: 600, repeat_ms: 200 }",
+        ));
+        d.edge(true, ms(0));
+        assert_eq!(d.tick(ms(600)), vec![Press::Long]);
+        assert_eq!(d.deadline(), Some(ms(800)));
+        assert_eq!(d.tick(ms(800)), vec![Press::Repeat]);
+        assert_eq!(d.tick(ms(1000)), vec![Press::Repeat]);
+        assert_eq!(d.edge(false, ms(1100)), vec![]);
+        assert_eq!(d.deadline(), None);
+    }
+
+    #[test]
+    fn short_then_long_hold_is_short_plus_long() {
+        let mut d = PressDetector::new(&line(GESTURES));
+        d.edge(true, ms(0));
+        d.edge(false, ms(100));
+        d.edge(true, ms(200));
+        assert_eq!(d.tick(ms(1000)), vec![Press::Short, Press::Long]);
+    }
+
+    #[test]
+    fn edge_filter_swallows_bounce() {
+        let mut f = EdgeFilter::new(ms(10));
+        assert_eq!(f.edge(true, ms(0)), Some(true));
+        assert_eq!(f.edge(false, ms(1)), None);
+        assert_eq!(f.edge(true, ms(2)), None);
+        assert_eq!(f.deadline(), None);
+        assert_eq!(f.edge(false, ms(200)), Some(false));
+        assert_eq!(f.edge(true, ms(203)), None);
+        assert_eq!(f.edge(false, ms(204)), None);
+        assert_eq!(f.tick(ms(300)), None);
+    }
+
+    #[test]
+    fn edge_filter_reports_change_settled_inside_window() {
+        let mut f = EdgeFilter::new(ms(10));
+        assert_eq!(f.edge(true, ms(0)), Some(true));
+        assert_eq!(f.edge(false, ms(5)), None);
+        assert_eq!(f.deadline(), Some(ms(10)));
+        assert_eq!(f.tick(ms(9)), None);
+        assert_eq!(f.tick(ms(12)), Some((false, ms(10))));
+        assert_eq!(f.deadline(), None);
+    }
+
+    #[test]
+    fn edge_filter_ignores_repeated_level() {
+        let mut f = EdgeFilter::new(ms(10));
+        assert_eq!(f.edge(false, ms(0)), None);
+        assert_eq!(f.edge(true, ms(50)), Some(true));
+        assert_eq!(f.edge(true, ms(100)), None);
+    }
+
+    #[test]
+    fn release_without_press_is_ignored() {
+        let mut d = PressDetector::new(&line(GESTURES));
+        assert_eq!(d.edge(false, ms(10)), vec![]);
+        assert_eq!(d.deadline(), None);
+    }
+}
@@ -0,0 +1,176 @@
+//! Cards assigned at runtime, from the web UI or learn mode, saved as
+//! `cards.yaml` in the cache dir next to the playback snapshot.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::config::Action;
+use crate::state::replace_file;
+
+/// File name of the assigned cards inside the cache dir.
+const CARDS_FILE: &str = "cards.yaml";
+
+/// Card IDs and their actions, per device (`input:` or `rfid:` key).
+pub type Assigned = BTreeMap<String, BTreeMap<String, Action>>;
+
+#[derive(Debug, Error)]
+pub enum CardsError {
+    #[error("could not serialise cards: {0}")]
+    Serialize(#[from] serde_yaml_ng::Error),
+    #[error("could not write cards to {path:?}: {source}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Cards given an action while soundkid runs (from the web UI), kept in
+/// the cache dir rather than the hand-written config. The config wins
+/// where both know a card. Clones share the same cards.
+#[derive(Debug, Clone)]
+pub struct CardStore {
+    path: PathBuf,
+    cards: Arc<Mutex<Assigned>>,
+}
+
+impl CardStore {
+    /// An empty store that saves to `cache_dir`.
+    pub fn new(cache_dir: &Path) -> Self {
+        Self {
+            path: cache_dir.join(CARDS_FILE),
+            cards: Arc::default(),
+        }
+    }
+
+    /// The store saved in `cache_dir`. A missing file is an empty store; a
+    /// corrupt one is moved aside to `cards.yaml.bad`, so the next
+    /// assignment doesn't overwrite cards that might still be recovered.
+    pub async fn load(cache_dir: &Path) -> Self {
+        let store = Self::new(cache_dir);
+        match tokio::fs::read_to_string(&store.path).await {
+            Ok(contents) => match serde_yaml_ng::from_str(&contents) {
+                Ok(cards) => *store.cards.lock().await = cards,
+                Err(e) => {
+                    let bad = store.path.with_extension("yaml.bad");
+                    warn!(
+                        "unreadable cards {:?}, moving them to {bad:?}: {e}",
+                        store.path
+                    );
+                    if let Err(e) = tokio::fs::rename(&store.path, &bad).await {
+                        warn!("could not move {:?} aside: {e}", store.path);
+                    }
+                }
+            },
+            Err(e) => debug!("no assigned cards at {:?}: {e}", store.path),
+        }
+        store
+    }
+
+    pub async fn get(&self, device: &str, id: &str) -> Option<Action> {
+        self.cards.lock().await.get(device)?.get(id).cloned()
+    }
+
+    pub async fn all(&self) -> Assigned {
+        self.cards.lock().await.clone()
+    }
+
+    /// Give a card an action and save. The file is replaced the way the
+    /// playback snapshot is, so a power cut mid-write leaves the old cards or
+    /// the new ones, never half of them.
+    pub async fn assign(&self, device: &str, id: &str, action: Action) -> Result<(), CardsError> {
+        let mut cards = self.cards.lock().await;
+        let mut updated = cards.clone();
+        updated
+            .entry(device.to_string())
+            .or_default()
+            .insert(id.to_string(), action.clone());
+        let contents = serde_yaml_ng::to_string(&updated)?;
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|source| CardsError::Write {
+                    path: dir.to_path_buf(),
+                    source,
+                })?;
+        }
+        replace_file(&self.path, &contents)
+            .await
+            .map_err(|(path, source)| CardsError::Write { path, source })?;
+        // Only now, so a failed save doesn't leave a card that's gone after
+        // a restart.
+        *cards = updated;
+        info!("Card {id} on {device:?} now does {action}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn album() -> Action {
+        Action::Play("spotify:album:1DFixLWuPkv3KT3TnV35m3".into())
+    }
+
+    #[tokio::test]
+    async fn assign_then_load_roundtrips() {
+        let dir = tempdir().unwrap();
+        let store = CardStore::load(dir.path()).await;
+        assert_eq!(store.get("lid", "04A1").await, None);
+        store.assign("lid", "04A1", album()).await.unwrap();
+        store.assign("lid", "04B2", Action::Pause).await.unwrap();
+        assert_eq!(store.get("lid", "04A1").await, Some(album()));
+
+        let loaded = CardStore::load(dir.path()).await;
+        assert_eq!(loaded.all().await, store.all().await);
+        assert_eq!(loaded.get("lid", "04B2").await, Some(Action::Pause));
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![CARDS_FILE]);
+    }
+
+    #[tokio::test]
+    async fn saves_in_config_spelling() {
+        let dir = tempdir().unwrap();
+        let store = CardStore::new(dir.path());
+        store.assign("lid", "04A1", album()).await.unwrap();
+        let saved = std::fs::read_to_string(dir.path().join(CARDS_FILE)).unwrap();
+        assert_eq!(
+            saved,
+            "lid:\n  04A1: spotify:album:1DFixLWuPkv3KT3TnV35m3\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_save_keeps_the_old_cards() {
+        let dir = tempdir().unwrap();
+        // The cache dir is taken by a file.
+        let cache_dir = dir.path().join("soundkid");
+        std::fs::write(&cache_dir, "").unwrap();
+        let store = CardStore::new(&cache_dir);
+        assert!(matches!(
+            store.assign("lid", "04A1", album()).await,
+            Err(CardsError::Write { .. })
+        ));
+        assert_eq!(store.get("lid", "04A1").await, None);
+    }
+
+    #[tokio::test]
+    async fn corrupt_file_is_moved_aside() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join(CARDS_FILE), ": not :: yaml").unwrap();
+        let store = CardStore::load(dir.path()).await;
+        assert!(store.all().await.is_empty());
+        store.assign("lid", "04A1", album()).await.unwrap();
+        let bad = std::fs::read_to_string(dir.path().join("cards.yaml.bad")).unwrap();
+        assert_eq!(bad, ": not :: yaml");
+    }
+}
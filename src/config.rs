@@ -1,16 +1,23 @@
-use serde::Deserialize;
+use evdev::KeyCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 use thiserror::Error;
 use tracing::{info, warn};
 
+use crate::input::Press;
+use crate::selector::DeviceSelector;
 use crate::uri::{UriError, canonicalize_uri};
 
 #[derive(Debug, Error)]
 pub enum ActionParseError {
     #[error(
         "unknown action {0:?}: expected VOLUME_INCREASE, VOLUME_DECREASE, PAUSE, RESUME, \
+         PLAY_PAUSE, STOP, NEXT, PREVIOUS, LEARN, VOLUME <percent>, VOLUME <+/-percent>, \
          or a spotify: URI / open.spotify.com URL"
     )]
     UnknownKeyword(String),
@@ -18,6 +25,14 @@ pub enum ActionParseError {
     InvalidUri(#[from] UriError),
 }
 
+#[derive(Debug, Error)]
+pub enum TerminatorParseError {
+    #[error(
+        "unknown terminator {0:?}: expected \"enter\", \"none\" or an evdev key name like KEY_TAB"
+    )]
+    UnknownKey(String),
+}
+
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("read error for {path}: {source}")]
@@ -32,6 +47,8 @@ pub enum ConfigError {
         #[source]
         source: serde_yaml_ng::Error,
     },
+    #[error("invalid config in {path}: {message}")]
+    Invalid { path: PathBuf, message: String },
     #[error(
         "no readable config file (tried ~/.soundkid.conf and /etc/soundkid.conf, or the paths \
          passed to load_from)"
@@ -43,6 +60,58 @@ fn default_alsa_control() -> String {
     "Master".to_string()
 }
 
+fn default_scan_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_scan_max_length() -> usize {
+    64
+}
+
+fn default_long_press_ms() -> u64 {
+    800
+}
+
+fn default_double_press_ms() -> u64 {
+    300
+}
+
+fn default_line_debounce_ms() -> u64 {
+    10
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_steps_per_detent() -> u8 {
+    4
+}
+
+fn default_rfid_poll_ms() -> u64 {
+    100
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_prefix() -> String {
+    "soundkid".to_string()
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_learn_timeout_secs() -> u64 {
+    60
+}
+
+fn default_control_socket() -> PathBuf {
+    PathBuf::from("/run/soundkid.sock")
+}
+
 fn default_cache_dir() -> PathBuf {
     dirs::cache_dir()
         .unwrap_or_else(|| PathBuf::from("/var/cache"))
@@ -59,6 +128,25 @@ pub enum Action {
     VolumeDecrease,
     Pause,
     Resume,
+    /// Pause if something is playing, resume if it is paused. Meant for
+    /// boxes with a single big button.
+    PlayPause,
+    /// Stop playback and forget the current queue; RESUME won't bring it
+    /// back.
+    Stop,
+    /// Skip to the next track of the current album or playlist.
+    Next,
+    /// Go back one track. On the first track, restart it.
+    Previous,
+    /// Change the volume by this many percent, written `VOLUME +5`.
+    Volume(i32),
+    /// Set the volume to this many percent, written `VOLUME 40`.
+    SetVolume(u8),
+    /// Learn mode: the next card nothing is configured for gets to play
+    /// this URI, or without one the next unassigned URI in `learn.queue`,
+    /// or else the URI last played through a control interface. LEARN
+    /// again while waiting for the card leaves learn mode.
+    Learn(Option<String>),
     /// A Spotify URI in canonical `spotify:<type>:<id>` form. URLs of the
     /// form `https://open.spotify.com/...` are normalised to this shape at
     /// parse time, so by the time the player sees this it is already valid.
@@ -69,11 +157,23 @@ impl FromStr for Action {
     type Err = ActionParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(uri) = s.strip_prefix("LEARN ") {
+            return Ok(Action::Learn(Some(canonicalize_uri(uri.trim())?)));
+        }
+        if let Some(percent) = s.strip_prefix("VOLUME ") {
+            return volume_action(percent.trim())
+                .ok_or_else(|| ActionParseError::UnknownKeyword(s.to_string()));
+        }
         match s {
             "VOLUME_INCREASE" => Ok(Action::VolumeIncrease),
             "VOLUME_DECREASE" => Ok(Action::VolumeDecrease),
             "PAUSE" => Ok(Action::Pause),
             "RESUME" => Ok(Action::Resume),
+            "PLAY_PAUSE" => Ok(Action::PlayPause),
+            "STOP" => Ok(Action::Stop),
+            "NEXT" => Ok(Action::Next),
+            "PREVIOUS" => Ok(Action::Previous),
+            "LEARN" => Ok(Action::Learn(None)),
             other
                 if other.starts_with("spotify:")
                     || other.starts_with("https://open.spotify.com/")
@@ -86,6 +186,44 @@ impl FromStr for Action {
     }
 }
 
+/// `+5` or `-5` changes the volume, a bare `40` sets it.
+pub(crate) fn volume_action(percent: &str) -> Option<Action> {
+    if percent.starts_with(['+', '-']) {
+        let change = percent.parse().ok().filter(|c| (-100..=100).contains(c))?;
+        Some(Action::Volume(change))
+    } else {
+        let level = percent.parse().ok().filter(|l| *l <= 100)?;
+        Some(Action::SetVolume(level))
+    }
+}
+
+/// The config spelling, so actions read back the way they were written.
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::VolumeIncrease => f.write_str("VOLUME_INCREASE"),
+            Action::VolumeDecrease => f.write_str("VOLUME_DECREASE"),
+            Action::Pause => f.write_str("PAUSE"),
+            Action::Resume => f.write_str("RESUME"),
+            Action::PlayPause => f.write_str("PLAY_PAUSE"),
+            Action::Stop => f.write_str("STOP"),
+            Action::Next => f.write_str("NEXT"),
+            Action::Previous => f.write_str("PREVIOUS"),
+            Action::Volume(percent) => write!(f, "VOLUME {percent:+}"),
+            Action::SetVolume(percent) => write!(f, "VOLUME {percent}"),
+            Action::Learn(None) => f.write_str("LEARN"),
+            Action::Learn(Some(uri)) => write!(f, "LEARN {uri}"),
+            Action::Play(uri) => f.write_str(uri),
+        }
+    }
+}
+
+impl Serialize for Action {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl TryFrom<String> for Action {
     type Error = ActionParseError;
     fn try_from(value: String) -> Result<Self, Self::Error> {
@@ -95,12 +233,32 @@ impl TryFrom<String> for Action {
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
-    #[serde(default)]
-    pub gpio: HashMap<String, HashMap<u32, Action>>,
-    #[serde(default)]
-    pub input: HashMap<String, HashMap<String, Action>>,
+    #[serde(default, deserialize_with = "deserialize_devices")]
+    pub gpio: HashMap<String, ConfigGpioChip>,
+    #[serde(default, deserialize_with = "deserialize_devices")]
+    pub input: HashMap<String, ConfigInputDevice>,
     pub alsa: ConfigAlsa,
     pub spotify: ConfigSpotify,
+    #[serde(default)]
+    pub rotary: HashMap<String, ConfigRotary>,
+    #[serde(default)]
+    pub rfid: HashMap<String, ConfigRfid>,
+    #[serde(default)]
+    pub player: ConfigPlayer,
+    /// The local control socket; off unless configured.
+    #[serde(default)]
+    pub control: Option<ConfigControl>,
+    /// The HTTP API; off unless configured.
+    #[serde(default)]
+    pub http: Option<ConfigHttp>,
+    /// The MQTT client; off unless configured.
+    #[serde(default)]
+    pub mqtt: Option<ConfigMqtt>,
+    #[serde(default)]
+    pub learn: ConfigLearn,
+    /// IDs from stdin or a FIFO; off unless configured.
+    #[serde(default)]
+    pub pseudo: Option<ConfigPseudo>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -114,6 +272,712 @@ pub struct ConfigSpotify {
     pub client_id: Option<String>,
 }
 
+/// Which characters the evdev reader keeps from a HID card reader's
+/// keystrokes.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Charset {
+    /// Letters (with shift), digits, keypad digits and common punctuation.
+    /// Needed for readers that send hex UIDs and for barcode/QR scanners.
+    #[default]
+    Alphanumeric,
+    /// Digits only, as soundkid did originally. Every other key is dropped.
+    Digits,
+}
+
+/// Keyboard layout a HID card reader assumes the host has. The reader sends
+/// whatever key codes produce its ID's characters on that layout.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    #[default]
+    Us,
+    Gb,
+    De,
+    Ch,
+    Fr,
+    Be,
+    Es,
+    It,
+}
+
+/// The key that ends a scanned ID.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Terminator {
+    /// `KEY_ENTER` or keypad `KEY_KPENTER`, which is what most readers send.
+    #[default]
+    Enter,
+    /// Any other key, by evdev name (`KEY_TAB`).
+    Key(KeyCode),
+    /// No terminator; IDs are cut at a fixed `length`.
+    None,
+}
+
+impl Terminator {
+    pub(crate) fn matches(self, code: KeyCode) -> bool {
+        match self {
+            Terminator::Enter => matches!(code, KeyCode::KEY_ENTER | KeyCode::KEY_KPENTER),
+            Terminator::Key(key) => key == code,
+            Terminator::None => false,
+        }
+    }
+}
+
+impl FromStr for Terminator {
+    type Err = TerminatorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enter" => Ok(Terminator::Enter),
+            "none" => Ok(Terminator::None),
+            other => KeyCode::from_str(other)
+                .map(Terminator::Key)
+                .map_err(|_| TerminatorParseError::UnknownKey(other.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for Terminator {
+    type Error = TerminatorParseError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Terminator::from_str(&value)
+    }
+}
+
+/// What happens to a partial ID once the inter-key timeout passes.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnTimeout {
+    /// Drop it; the reader lost its terminator and the ID is suspect.
+    #[default]
+    Discard,
+    /// Treat it as complete, for readers that never send a terminator.
+    Flush,
+}
+
+/// What taking a card off its reader does to the playback it started.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnRemove {
+    /// Keep playing.
+    #[default]
+    Nothing,
+    /// Pause; putting the same card back resumes.
+    Pause,
+    Stop,
+}
+
+/// One `input:` entry. In YAML this is either the plain `scanned-id: action`
+/// map, or a map with the cards under `cards:` next to per-device options
+/// (see [`DeviceEntry`]):
+///
+/// ```yaml
+/// input:
+///   "HXGCoLtd Keyboard":
+///     charset: digits
+///     cards:
+///       "0000012345": PAUSE
+/// ```
+///
+/// Devices that are buttons rather than card readers (media remotes, USB
+/// arcade encoders) map keys instead, each key like a GPIO button:
+///
+/// ```yaml
+/// input:
+///   "Media Remote":
+///     keys:
+///       KEY_PLAYPAUSE: PLAY_PAUSE
+///       KEY_NEXTSONG: { short: NEXT, long: STOP }
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigInputDevice {
+    #[serde(default)]
+    pub cards: HashMap<String, Action>,
+    /// Key mode: every press of a key is an event of its own, looked up
+    /// here by evdev key name. The scan options don't apply.
+    #[serde(default, deserialize_with = "deserialize_keys")]
+    pub keys: HashMap<KeyCode, ConfigButton>,
+    #[serde(default)]
+    pub charset: Charset,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub terminator: Terminator,
+    /// Fixed ID length. An ID is complete once this many characters are in,
+    /// terminator or not. Required with `terminator: none`.
+    #[serde(default)]
+    pub length: Option<usize>,
+    /// A partial ID with no key for this long is stale. 0 disables.
+    #[serde(default = "default_scan_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub on_timeout: OnTimeout,
+    /// Partial IDs growing past this are dropped instead of growing forever.
+    #[serde(default = "default_scan_max_length")]
+    pub max_length: usize,
+    /// Drop an event identical to the previous one from this device if it
+    /// comes within this many milliseconds. The window restarts with every
+    /// repeat, so a card left on a reader that keeps re-sending it stays
+    /// suppressed. 0 disables.
+    #[serde(default)]
+    pub debounce_ms: u64,
+    /// Take the device for ourselves (EVIOCGRAB), so its keystrokes don't
+    /// also reach the console or a desktop session.
+    #[serde(default)]
+    pub grab: bool,
+    /// For readers that keep re-sending a card while it lies on them: once
+    /// they have been quiet this long, the card counts as taken off. 0
+    /// disables.
+    #[serde(default)]
+    pub removal_ms: u64,
+    /// What taking the card off does. Needs `removal_ms`.
+    #[serde(default)]
+    pub on_remove: OnRemove,
+}
+
+impl Default for ConfigInputDevice {
+    fn default() -> Self {
+        Self {
+            cards: HashMap::new(),
+            keys: HashMap::new(),
+            charset: Charset::default(),
+            layout: Layout::default(),
+            terminator: Terminator::default(),
+            length: None,
+            timeout_ms: default_scan_timeout_ms(),
+            on_timeout: OnTimeout::default(),
+            max_length: default_scan_max_length(),
+            debounce_ms: 0,
+            grab: false,
+            removal_ms: 0,
+            on_remove: OnRemove::default(),
+        }
+    }
+}
+
+/// One `gpio:` entry: a chip, in the plain `line: action` form or with the
+/// lines under `lines:` next to per-chip options.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigGpioChip {
+    #[serde(default)]
+    pub lines: HashMap<u32, ConfigGpioLine>,
+    /// See [`ConfigInputDevice::debounce_ms`].
+    #[serde(default)]
+    pub debounce_ms: u64,
+}
+
+/// What a button (a GPIO line or an evdev key) does. In YAML either a plain
+/// action, fired as soon as the button goes down, or a map of gestures:
+///
+/// ```yaml
+/// 17: { short: NEXT, long: VOLUME_DECREASE, repeat_ms: 250 }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "serde_yaml_ng::Value")]
+pub struct ConfigButton {
+    pub short: Option<Action>,
+    pub long: Option<Action>,
+    pub double: Option<Action>,
+    /// Held at least this long, it is a long press.
+    pub long_ms: u64,
+    /// Two short presses with at most this gap are a double press.
+    pub double_ms: u64,
+    /// Keep repeating the long action at this interval while held.
+    pub repeat_ms: Option<u64>,
+}
+
+/// The map form of [`ConfigButton`], before validation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ButtonOptions {
+    #[serde(default)]
+    short: Option<Action>,
+    #[serde(default)]
+    long: Option<Action>,
+    #[serde(default)]
+    double: Option<Action>,
+    #[serde(default = "default_long_press_ms")]
+    long_ms: u64,
+    #[serde(default = "default_double_press_ms")]
+    double_ms: u64,
+    #[serde(default)]
+    repeat_ms: Option<u64>,
+}
+
+impl ConfigButton {
+    /// The action for a detected gesture. Auto-repeat repeats the long action.
+    pub fn action(&self, press: Press) -> Option<&Action> {
+        match press {
+            Press::Short => self.short.as_ref(),
+            Press::Long | Press::Repeat => self.long.as_ref(),
+            Press::Double => self.double.as_ref(),
+        }
+    }
+}
+
+impl From<Action> for ConfigButton {
+    fn from(action: Action) -> Self {
+        Self {
+            short: Some(action),
+            long: None,
+            double: None,
+            long_ms: default_long_press_ms(),
+            double_ms: default_double_press_ms(),
+            repeat_ms: None,
+        }
+    }
+}
+
+impl TryFrom<serde_yaml_ng::Value> for ConfigButton {
+    type Error = String;
+
+    fn try_from(value: serde_yaml_ng::Value) -> Result<Self, Self::Error> {
+        if let Some(s) = value.as_str() {
+            return Action::from_str(s)
+                .map(ConfigButton::from)
+                .map_err(|e| e.to_string());
+        }
+        let o: ButtonOptions = serde_yaml_ng::from_value(value).map_err(|e| e.to_string())?;
+        if o.short.is_none() && o.long.is_none() && o.double.is_none() {
+            return Err("a button needs at least one of short, long or double".to_string());
+        }
+        if o.repeat_ms.is_some() && o.long.is_none() {
+            return Err("repeat_ms repeats the long action, but none is set".to_string());
+        }
+        if o.repeat_ms == Some(0) {
+            return Err("repeat_ms must be greater than 0".to_string());
+        }
+        Ok(Self {
+            short: o.short,
+            long: o.long,
+            double: o.double,
+            long_ms: o.long_ms,
+            double_ms: o.double_ms,
+            repeat_ms: o.repeat_ms,
+        })
+    }
+}
+
+/// One GPIO line: a [`ConfigButton`], plus in the map form how the line is
+/// wired (`debounce_ms`, `active_low`, `bias`).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "serde_yaml_ng::Value")]
+pub struct ConfigGpioLine {
+    pub button: ConfigButton,
+    /// Edges closer together than this are contact bounce. 0 disables.
+    pub debounce_ms: u64,
+    /// The button pulls the line low when pressed.
+    pub active_low: bool,
+    pub bias: Bias,
+}
+
+/// Pull resistor to enable on a GPIO line.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bias {
+    /// Leave whatever the board or device tree configured.
+    #[default]
+    AsIs,
+    PullUp,
+    PullDown,
+    Disabled,
+}
+
+/// The wiring keys of a GPIO line's map form.
+#[derive(Deserialize)]
+struct LineWiring {
+    #[serde(default = "default_line_debounce_ms")]
+    debounce_ms: u64,
+    #[serde(default = "default_true")]
+    active_low: bool,
+    #[serde(default)]
+    bias: Bias,
+}
+
+impl ConfigGpioLine {
+    pub fn action(&self, press: Press) -> Option<&Action> {
+        self.button.action(press)
+    }
+}
+
+impl From<Action> for ConfigGpioLine {
+    fn from(action: Action) -> Self {
+        Self {
+            button: action.into(),
+            debounce_ms: default_line_debounce_ms(),
+            active_low: true,
+            bias: Bias::default(),
+        }
+    }
+}
+
+impl TryFrom<serde_yaml_ng::Value> for ConfigGpioLine {
+    type Error = String;
+
+    fn try_from(mut value: serde_yaml_ng::Value) -> Result<Self, Self::Error> {
+        // Split the wiring keys off; everything left describes the button.
+        let mut wiring = serde_yaml_ng::Mapping::new();
+        if let Some(map) = value.as_mapping_mut() {
+            for key in ["debounce_ms", "active_low", "bias"] {
+                if let Some(v) = map.remove(key) {
+                    wiring.insert(key.into(), v);
+                }
+            }
+        }
+        let wiring: LineWiring =
+            serde_yaml_ng::from_value(wiring.into()).map_err(|e| e.to_string())?;
+        Ok(Self {
+            button: ConfigButton::try_from(value)?,
+            debounce_ms: wiring.debounce_ms,
+            active_low: wiring.active_low,
+            bias: wiring.bias,
+        })
+    }
+}
+
+/// One `rotary:` entry: a quadrature encoder on two lines of a GPIO chip,
+/// optionally with a push switch.
+///
+/// ```yaml
+/// rotary:
+///   volume:
+///     chip: /dev/gpiochip0
+///     a: 17
+///     b: 18
+///     cw: VOLUME_INCREASE
+///     ccw: VOLUME_DECREASE
+///     switch: { line: 27, short: PLAY_PAUSE }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigRotary {
+    pub chip: String,
+    pub a: u32,
+    pub b: u32,
+    pub cw: Action,
+    pub ccw: Action,
+    /// Quadrature transitions per click: 4 for most detented encoders, 2 or
+    /// 1 for ones that click more often.
+    #[serde(default = "default_steps_per_detent")]
+    pub steps_per_detent: u8,
+    /// Applied to both A and B; most bare encoders need `pull_up`.
+    #[serde(default)]
+    pub bias: Bias,
+    #[serde(default)]
+    pub switch: Option<ConfigRotarySwitch>,
+}
+
+/// The push switch of a rotary encoder: a line, and the same gestures and
+/// line options as a `gpio:` line in map form.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigRotarySwitch {
+    pub line: u32,
+    #[serde(flatten)]
+    pub button: ConfigGpioLine,
+}
+
+/// One `rfid:` entry: a card reader soundkid talks to itself, rather than
+/// one that types IDs like a keyboard. Such readers also notice when a card
+/// is taken away.
+///
+/// ```yaml
+/// rfid:
+///   lid:
+///     driver: mfrc522
+///     path: /dev/spidev0.0
+///     cards:
+///       "04A1B2C3D4E5F6": "spotify:album:1DFixLWuPkv3KT3TnV35m3"
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigRfid {
+    pub driver: RfidDriver,
+    /// The device node the reader hangs off.
+    pub path: String,
+    /// Card UIDs, in uppercase hex as the log shows them, → action.
+    #[serde(default)]
+    pub cards: HashMap<String, Action>,
+    /// How often to look for a card.
+    #[serde(default = "default_rfid_poll_ms")]
+    pub poll_ms: u64,
+    /// Read the URI written on Type 2 tags (NTAG21x stickers) as NDEF, and
+    /// play it for cards without an entry in `cards`.
+    #[serde(default)]
+    pub ndef: bool,
+    /// What taking a card off the reader does.
+    #[serde(default)]
+    pub on_remove: OnRemove,
+    /// UART speed, for readers on a serial port that can be set to one
+    /// (`rdm6300`, 9600 unless given).
+    #[serde(default)]
+    pub baud: Option<u32>,
+}
+
+/// The chip behind an `rfid:` entry.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RfidDriver {
+    /// NXP MFRC522 ("RC522" modules) on a `/dev/spidev*` device.
+    Mfrc522,
+    /// NXP PN532 on `/dev/i2c-*`, or on a serial tty in HSU mode.
+    Pn532,
+    /// RDM6300 125 kHz reader for EM4100 tags, on a serial tty.
+    Rdm6300,
+}
+
+/// A device entry that can be written either as its bare mapping table, or
+/// in a long form with the table under one of `TABLE_KEYS` next to options.
+trait DeviceEntry: DeserializeOwned {
+    /// Keys that mark the long form. The bare form's table is the first.
+    const TABLE_KEYS: &'static [&'static str];
+    /// The options next to the table. They mark the long form too, so an
+    /// entry with only options isn't read as a table of odd card IDs.
+    const OPTION_KEYS: &'static [&'static str];
+    type Table: DeserializeOwned;
+
+    fn from_table(table: Self::Table) -> Self;
+
+    /// Cross-field checks that serde attributes can't express.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Checks on the key the entry is listed under.
+    fn validate_key(_device: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl DeviceEntry for ConfigInputDevice {
+    const TABLE_KEYS: &'static [&'static str] = &["cards", "keys"];
+    const OPTION_KEYS: &'static [&'static str] = &[
+        "charset",
+        "layout",
+        "terminator",
+        "length",
+        "timeout_ms",
+        "on_timeout",
+        "max_length",
+        "debounce_ms",
+        "grab",
+        "removal_ms",
+        "on_remove",
+    ];
+    type Table = HashMap<String, Action>;
+
+    fn from_table(cards: Self::Table) -> Self {
+        Self {
+            cards,
+            ..Default::default()
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.terminator == Terminator::None && self.length.is_none() {
+            return Err("terminator: none needs a length".to_string());
+        }
+        if self.length == Some(0) {
+            return Err("length must be at least 1".to_string());
+        }
+        if self.max_length == 0 {
+            return Err("max_length must be at least 1".to_string());
+        }
+        if !self.cards.is_empty() && !self.keys.is_empty() {
+            return Err("a device maps either cards or keys, not both".to_string());
+        }
+        if self.on_remove != OnRemove::Nothing && self.removal_ms == 0 {
+            return Err("on_remove needs a removal_ms".to_string());
+        }
+        Ok(())
+    }
+
+    fn validate_key(device: &str) -> Result<(), String> {
+        device.parse::<DeviceSelector>().map(|_| ())
+    }
+}
+
+impl DeviceEntry for ConfigGpioChip {
+    const TABLE_KEYS: &'static [&'static str] = &["lines"];
+    const OPTION_KEYS: &'static [&'static str] = &["debounce_ms"];
+    type Table = HashMap<u32, ConfigGpioLine>;
+
+    fn from_table(lines: Self::Table) -> Self {
+        Self {
+            lines,
+            ..Default::default()
+        }
+    }
+}
+
+/// Accept both shapes of an `input:` or `gpio:` entry (see [`DeviceEntry`]).
+fn deserialize_devices<'de, D, T>(deserializer: D) -> Result<HashMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeviceEntry,
+{
+    use serde::de::Error;
+    use serde_yaml_ng::Value;
+
+    let raw = HashMap::<String, Value>::deserialize(deserializer)?;
+    raw.into_iter()
+        .map(|(device, value)| {
+            T::validate_key(&device).map_err(D::Error::custom)?;
+            let long_form = value.as_mapping().is_some_and(|m| {
+                T::TABLE_KEYS
+                    .iter()
+                    .chain(T::OPTION_KEYS)
+                    .any(|k| m.contains_key(*k))
+            });
+            let parsed = if long_form {
+                serde_yaml_ng::from_value(value)
+            } else {
+                serde_yaml_ng::from_value(value).map(T::from_table)
+            };
+            let parsed = parsed.map_err(|e| D::Error::custom(format!("device {device:?}: {e}")))?;
+            parsed
+                .validate()
+                .map_err(|e| D::Error::custom(format!("device {device:?}: {e}")))?;
+            Ok((device, parsed))
+        })
+        .collect()
+}
+
+/// Read a `keys:` table, turning evdev key names into key codes.
+fn deserialize_keys<'de, D>(deserializer: D) -> Result<HashMap<KeyCode, ConfigButton>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    HashMap::<String, ConfigButton>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, button)| {
+            let code = KeyCode::from_str(&name).map_err(|_| {
+                D::Error::custom(format!(
+                    "unknown key {name:?}: expected an evdev key name like KEY_PLAYPAUSE"
+                ))
+            })?;
+            Ok((code, button))
+        })
+        .collect()
+}
+
+/// What to do when a card for the URI that is already playing is scanned
+/// again. Kids love to re-scan the card that is on.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RescanPolicy {
+    /// Stop and start again from the first track.
+    #[default]
+    Restart,
+    /// Pause if playing, resume if paused.
+    TogglePause,
+    /// Keep playing as if nothing happened.
+    Ignore,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ConfigPlayer {
+    #[serde(default)]
+    pub on_rescan: RescanPolicy,
+    /// Release the audio device once playback has been paused for this many
+    /// minutes, keeping the queue and position for RESUME. Unset means hold
+    /// on to it forever.
+    #[serde(default)]
+    pub stop_on_idle_minutes: Option<u64>,
+}
+
+/// Card IDs typed or piped in, one per line, for trying out mappings
+/// without a reader.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigPseudo {
+    /// The `input:` device the IDs count as scanned by; it must be one. It
+    /// isn't opened.
+    pub device: String,
+    /// A named pipe to read instead of stdin.
+    #[serde(default)]
+    pub fifo: Option<PathBuf>,
+}
+
+/// Learn mode, entered through a LEARN action.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLearn {
+    /// URIs for LEARN without one to hand out in order, each to the first
+    /// card learned after nothing plays it yet.
+    #[serde(default, deserialize_with = "deserialize_uris")]
+    pub queue: Vec<String>,
+    /// How long learn mode waits for a card.
+    #[serde(default = "default_learn_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for ConfigLearn {
+    fn default() -> Self {
+        Self {
+            queue: Vec::new(),
+            timeout_secs: default_learn_timeout_secs(),
+        }
+    }
+}
+
+fn deserialize_uris<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|uri| canonicalize_uri(uri).map_err(D::Error::custom))
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigControl {
+    #[serde(default = "default_control_socket")]
+    pub socket: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigHttp {
+    /// Address and port to listen on, e.g. `0.0.0.0:8080`.
+    pub bind: SocketAddr,
+    /// If set, every request needs an `Authorization: Bearer <token>`
+    /// header, or the token as the password of HTTP basic auth.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Also serve the card management pages at `/`.
+    #[serde(default)]
+    pub ui: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigMqtt {
+    /// The broker's host name or address.
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Topics are `<prefix>/state`, `<prefix>/command` and so on. Also the
+    /// MQTT client ID, so two boxes on one broker need different prefixes.
+    #[serde(default = "default_mqtt_prefix")]
+    pub prefix: String,
+    /// Where Home Assistant looks for discovery messages.
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct ConfigAlsa {
     #[serde(default = "default_alsa_control")]
@@ -143,7 +1007,13 @@ impl Config {
             info!("Trying to read config file {path:?}");
             match tokio::fs::read_to_string(&path).await {
                 Ok(contents) => match serde_yaml_ng::from_str::<Config>(&contents) {
-                    Ok(cfg) => return Ok(cfg),
+                    Ok(cfg) => match cfg.validate() {
+                        Ok(()) => return Ok(cfg),
+                        Err(message) => {
+                            warn!("Invalid config in {path:?}: {message}");
+                            last_err = Some(ConfigError::Invalid { path, message });
+                        }
+                    },
                     Err(source) => {
                         warn!("Unable to parse yaml from {path:?}: {source}");
                         last_err = Some(ConfigError::Parse { path, source });
@@ -157,6 +1027,20 @@ impl Config {
         }
         Err(last_err.unwrap_or(ConfigError::NoCandidate))
     }
+
+    /// Checks across sections, which the per-field ones during parsing
+    /// can't make.
+    fn validate(&self) -> Result<(), String> {
+        if let Some(pseudo) = &self.pseudo {
+            if !self.input.contains_key(&pseudo.device) {
+                return Err(format!(
+                    "pseudo.device {:?} is not a device under input:",
+                    pseudo.device
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 fn default_candidates() -> Vec<PathBuf> {
@@ -198,6 +1082,33 @@ mod tests {
         assert_eq!(Action::from_str("RESUME").unwrap(), Action::Resume);
     }
 
+    #[test]
+    fn action_play_pause() {
+        assert_eq!(Action::from_str("PLAY_PAUSE").unwrap(), Action::PlayPause);
+    }
+
+    #[test]
+    fn action_stop() {
+        assert_eq!(Action::from_str("STOP").unwrap(), Action::Stop);
+    }
+
+    #[test]
+    fn action_learn() {
+        assert_eq!(Action::from_str("LEARN").unwrap(), Action::Learn(None));
+        assert_eq!(
+            Action::from_str("LEARN https://open.spotify.com/album/1DFixLWuPkv3KT3TnV35m3")
+                .unwrap(),
+            Action::Learn(Some("spotify:album:1DFixLWuPkv3KT3TnV35m3".into()))
+        );
+        assert!(Action::from_str("LEARN PAUSE").is_err());
+    }
+
+    #[test]
+    fn action_next_previous() {
+        assert_eq!(Action::from_str("NEXT").unwrap(), Action::Next);
+        assert_eq!(Action::from_str("PREVIOUS").unwrap(), Action::Previous);
+    }
+
     // Real-shape IDs (22 base62 chars). librespot validates length strictly.
     const TRACK_ID: &str = "6rqhFgbbKwnb9MLmUQDhG6";
     const ALBUM_ID: &str = "7LQhG0xSDjFiKJnziyB3Zj";
@@ -302,6 +1213,34 @@ spotify: {}
         assert!(cfg.spotify.client_id.is_none());
         assert!(cfg.input.is_empty());
         assert!(cfg.gpio.is_empty());
+        assert_eq!(cfg.player.on_rescan, RescanPolicy::Restart);
+        assert!(cfg.player.stop_on_idle_minutes.is_none());
+    }
+
+    #[test]
+    fn config_player_stop_on_idle_minutes_parses() {
+        let cfg = parse("alsa: {}\nspotify: {}\nplayer: { stop_on_idle_minutes: 30 }\n").unwrap();
+        assert_eq!(cfg.player.stop_on_idle_minutes, Some(30));
+    }
+
+    #[test]
+    fn config_player_on_rescan_parses() {
+        for (raw, expected) in [
+            ("restart", RescanPolicy::Restart),
+            ("toggle_pause", RescanPolicy::TogglePause),
+            ("ignore", RescanPolicy::Ignore),
+        ] {
+            let cfg = parse(&format!(
+                "alsa: {{}}\nspotify: {{}}\nplayer: {{ on_rescan: {raw} }}\n"
+            ))
+            .unwrap();
+            assert_eq!(cfg.player.on_rescan, expected, "on_rescan: {raw}");
+        }
+    }
+
+    #[test]
+    fn config_player_unknown_on_rescan_fails() {
+        assert!(parse("alsa: {}\nspotify: {}\nplayer: { on_rescan: replay }\n").is_err());
     }
 
     #[test]
@@ -324,15 +1263,15 @@ gpio:
         let cfg = parse(&yaml).unwrap();
         assert_eq!(cfg.alsa.control, "SoftMaster");
         assert_eq!(cfg.spotify.client_id.as_deref(), Some("my-id"));
-        let evdev = &cfg.input["/dev/input/event0"];
+        let evdev = &cfg.input["/dev/input/event0"].cards;
         assert_eq!(
             evdev["12345"],
             Action::Play(format!("spotify:track:{TRACK_ID}"))
         );
         assert_eq!(evdev["VOL"], Action::VolumeIncrease);
-        let gpio = &cfg.gpio["/dev/gpiochip0"];
-        assert_eq!(gpio[&17u32], Action::Pause);
-        assert_eq!(gpio[&27u32], Action::Resume);
+        let gpio = &cfg.gpio["/dev/gpiochip0"].lines;
+        assert_eq!(gpio[&17u32], Action::Pause.into());
+        assert_eq!(gpio[&27u32], Action::Resume.into());
     }
 
     #[test]
@@ -353,6 +1292,593 @@ input:
         );
     }
 
+    #[test]
+    fn config_input_long_form_parses() {
+        let yaml = format!(
+            r#"
+alsa: {{}}
+spotify: {{}}
+input:
+  /dev/input/event0:
+    charset: digits
+    grab: true
+    cards:
+      "12345": "spotify:track:{TRACK_ID}"
+  /dev/input/event1:
+    "ABCDEF": PAUSE
+"#
+        );
+        let cfg = parse(&yaml).unwrap();
+        let long = &cfg.input["/dev/input/event0"];
+        assert_eq!(long.charset, Charset::Digits);
+        assert!(long.grab);
+        assert_eq!(
+            long.cards["12345"],
+            Action::Play(format!("spotify:track:{TRACK_ID}"))
+        );
+        let short = &cfg.input["/dev/input/event1"];
+        assert_eq!(short.charset, Charset::Alphanumeric);
+        assert_eq!(short.layout, Layout::Us);
+        assert_eq!(short.terminator, Terminator::Enter);
+        assert_eq!(short.timeout_ms, 1000);
+        assert_eq!(short.max_length, 64);
+        assert!(!short.grab);
+        assert_eq!(short.cards["ABCDEF"], Action::Pause);
+    }
+
+    #[test]
+    fn config_input_layout_parses() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    layout: fr
+    cards: {}
+"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.input["/dev/input/event0"].layout, Layout::Fr);
+    }
+
+    #[test]
+    fn terminator_parses() {
+        assert_eq!(Terminator::from_str("enter").unwrap(), Terminator::Enter);
+        assert_eq!(Terminator::from_str("none").unwrap(), Terminator::None);
+        assert_eq!(
+            Terminator::from_str("KEY_TAB").unwrap(),
+            Terminator::Key(KeyCode::KEY_TAB)
+        );
+        assert!(Terminator::from_str("TAB").is_err());
+    }
+
+    #[test]
+    fn config_input_scan_options_parse() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    terminator: none
+    length: 10
+    timeout_ms: 300
+    on_timeout: flush
+    max_length: 20
+    cards: {}
+"#,
+        )
+        .unwrap();
+        let dev = &cfg.input["/dev/input/event0"];
+        assert_eq!(dev.terminator, Terminator::None);
+        assert_eq!(dev.length, Some(10));
+        assert_eq!(dev.timeout_ms, 300);
+        assert_eq!(dev.on_timeout, OnTimeout::Flush);
+        assert_eq!(dev.max_length, 20);
+    }
+
+    #[test]
+    fn config_on_remove_parses_and_needs_removal_detection() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    removal_ms: 500
+    on_remove: pause
+    cards: {}
+rfid:
+  lid:
+    driver: mfrc522
+    path: /dev/spidev0.0
+    on_remove: stop
+"#,
+        )
+        .unwrap();
+        let dev = &cfg.input["/dev/input/event0"];
+        assert_eq!(dev.removal_ms, 500);
+        assert_eq!(dev.on_remove, OnRemove::Pause);
+        assert_eq!(cfg.rfid["lid"].on_remove, OnRemove::Stop);
+
+        let err = parse(
+            "alsa: {}\nspotify: {}\ninput:\n  /dev/input/event0:\n    on_remove: pause\n    cards: {}\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("removal_ms"), "{err}");
+    }
+
+    #[test]
+    fn config_rejects_zero_lengths() {
+        for option in ["length: 0", "max_length: 0"] {
+            let err = parse(&format!(
+                "alsa: {{}}\nspotify: {{}}\ninput:\n  /dev/input/event0:\n    {option}\n    cards: {{}}\n"
+            ))
+            .unwrap_err()
+            .to_string();
+            assert!(err.contains("\"/dev/input/event0\""), "{err}");
+            assert!(err.contains("must be at least 1"), "{err}");
+        }
+    }
+
+    #[test]
+    fn config_control_socket_is_off_unless_configured() {
+        let cfg = parse("alsa: {}\nspotify: {}\n").unwrap();
+        assert!(cfg.control.is_none());
+        let cfg = parse("alsa: {}\nspotify: {}\ncontrol: {}\n").unwrap();
+        assert_eq!(
+            cfg.control.unwrap().socket,
+            PathBuf::from("/run/soundkid.sock")
+        );
+        let cfg = parse("alsa: {}\nspotify: {}\ncontrol: { socket: /tmp/sk.sock }\n").unwrap();
+        assert_eq!(cfg.control.unwrap().socket, PathBuf::from("/tmp/sk.sock"));
+    }
+
+    #[test]
+    fn config_http_needs_a_bind_address() {
+        let cfg =
+            parse("alsa: {}\nspotify: {}\nhttp: { bind: \"127.0.0.1:8080\", token: s3cret }\n")
+                .unwrap();
+        let http = cfg.http.unwrap();
+        assert_eq!(http.bind, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(http.token.as_deref(), Some("s3cret"));
+        assert!(!http.ui);
+        assert!(parse("alsa: {}\nspotify: {}\nhttp: {}\n").is_err());
+    }
+
+    #[test]
+    fn config_mqtt_defaults() {
+        let cfg = parse("alsa: {}\nspotify: {}\nmqtt: { host: broker.lan }\n").unwrap();
+        let mqtt = cfg.mqtt.unwrap();
+        assert_eq!(mqtt.host, "broker.lan");
+        assert_eq!(mqtt.port, 1883);
+        assert_eq!(mqtt.username, None);
+        assert_eq!(mqtt.prefix, "soundkid");
+        assert_eq!(mqtt.discovery_prefix, "homeassistant");
+
+        let cfg = parse(
+            "alsa: {}\nspotify: {}\nmqtt: { host: broker.lan, port: 8883, username: box, password: pw, prefix: soundkid-kitchen }\n",
+        )
+        .unwrap();
+        let mqtt = cfg.mqtt.unwrap();
+        assert_eq!((mqtt.port, mqtt.username.as_deref()), (8883, Some("box")));
+        assert_eq!(mqtt.prefix, "soundkid-kitchen");
+    }
+
+    #[test]
+    fn config_pseudo_reads_stdin_unless_given_a_fifo() {
+        let cfg = parse("alsa: {}\nspotify: {}\n").unwrap();
+        assert!(cfg.pseudo.is_none());
+        let input = "input:\n  /dev/input/event0: { \"1\": PAUSE }\n";
+        let cfg = parse(&format!(
+            "alsa: {{}}\nspotify: {{}}\n{input}pseudo: {{ device: /dev/input/event0 }}\n"
+        ))
+        .unwrap();
+        cfg.validate().unwrap();
+        let pseudo = cfg.pseudo.unwrap();
+        assert_eq!(pseudo.device, "/dev/input/event0");
+        assert_eq!(pseudo.fifo, None);
+        let cfg = parse(&format!(
+            "alsa: {{}}\nspotify: {{}}\n{input}\
+             pseudo: {{ device: /dev/input/event0, fifo: /tmp/soundkid.fifo }}\n"
+        ))
+        .unwrap();
+        assert_eq!(
+            cfg.pseudo.unwrap().fifo,
+            Some(PathBuf::from("/tmp/soundkid.fifo"))
+        );
+        assert!(parse("alsa: {}\nspotify: {}\npseudo: {}\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn config_pseudo_device_must_be_an_input_device() {
+        let f = write_temp(
+            "alsa: {}\nspotify: {}\ninput:\n  lid: { \"1\": PAUSE }\npseudo: { device: lit }\n",
+        );
+        let err = Config::load_from([f.path().to_path_buf()])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid { .. }), "{err}");
+        assert!(err.to_string().contains("\"lit\""), "{err}");
+    }
+
+    #[test]
+    fn config_learn_canonicalizes_its_queue() {
+        let cfg = parse("alsa: {}\nspotify: {}\n").unwrap();
+        assert!(cfg.learn.queue.is_empty());
+        assert_eq!(cfg.learn.timeout_secs, 60);
+
+        let cfg = parse(
+            "alsa: {}\nspotify: {}\nlearn:\n  timeout_secs: 30\n  queue:\n    - https://open.spotify.com/album/1DFixLWuPkv3KT3TnV35m3?si=x\n    - spotify:track:6rqhFgbbKwnb9MLmUQDhG6\n",
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.learn.queue,
+            vec![
+                "spotify:album:1DFixLWuPkv3KT3TnV35m3",
+                "spotify:track:6rqhFgbbKwnb9MLmUQDhG6",
+            ]
+        );
+        assert_eq!(cfg.learn.timeout_secs, 30);
+        assert!(parse("alsa: {}\nspotify: {}\nlearn: { queue: [PAUSE] }\n").is_err());
+    }
+
+    #[test]
+    fn actions_display_as_written_in_the_config() {
+        for written in [
+            "VOLUME_INCREASE",
+            "PLAY_PAUSE",
+            "VOLUME +5",
+            "VOLUME -5",
+            "VOLUME 40",
+            "LEARN spotify:album:1DFixLWuPkv3KT3TnV35m3",
+            "spotify:album:1DFixLWuPkv3KT3TnV35m3",
+        ] {
+            assert_eq!(written.parse::<Action>().unwrap().to_string(), written);
+        }
+        assert_eq!(
+            "VOLUME 40".parse::<Action>().unwrap(),
+            Action::SetVolume(40)
+        );
+        assert_eq!("VOLUME -5".parse::<Action>().unwrap(), Action::Volume(-5));
+        for bad in ["VOLUME", "VOLUME 101", "VOLUME +200", "VOLUME loud"] {
+            assert!(bad.parse::<Action>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn config_input_terminator_none_without_length_fails() {
+        let err = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    terminator: none
+    cards: {}
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("length"), "{err}");
+    }
+
+    #[test]
+    fn config_debounce_parses_for_input_and_gpio() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    debounce_ms: 1500
+    cards: {}
+gpio:
+  /dev/gpiochip0:
+    debounce_ms: 300
+    lines:
+      17: PAUSE
+  /dev/gpiochip1:
+    4: RESUME
+"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.input["/dev/input/event0"].debounce_ms, 1500);
+        let long = &cfg.gpio["/dev/gpiochip0"];
+        assert_eq!(long.debounce_ms, 300);
+        assert_eq!(long.lines[&17], Action::Pause.into());
+        let short = &cfg.gpio["/dev/gpiochip1"];
+        assert_eq!(short.debounce_ms, 0);
+        assert_eq!(short.lines[&4], Action::Resume.into());
+    }
+
+    #[test]
+    fn config_gpio_gestures_parse() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+gpio:
+  /dev/gpiochip0:
+    17: { short: NEXT, long: VOLUME_DECREASE, repeat_ms: 250 }
+    27: { double: STOP, double_ms: 400 }
+    22: PAUSE
+"#,
+        )
+        .unwrap();
+        let lines = &cfg.gpio["/dev/gpiochip0"].lines;
+        let held = &lines[&17];
+        assert_eq!(held.action(Press::Short), Some(&Action::Next));
+        assert_eq!(held.action(Press::Long), Some(&Action::VolumeDecrease));
+        assert_eq!(held.action(Press::Repeat), Some(&Action::VolumeDecrease));
+        assert_eq!(held.action(Press::Double), None);
+        assert_eq!(held.button.long_ms, 800);
+        assert_eq!(held.button.repeat_ms, Some(250));
+        assert_eq!(lines[&27].button.double_ms, 400);
+        assert_eq!(lines[&27].action(Press::Double), Some(&Action::Stop));
+        assert_eq!(lines[&22].action(Press::Short), Some(&Action::Pause));
+        assert_eq!(lines[&22].debounce_ms, 10);
+        assert!(lines[&22].active_low);
+        assert_eq!(lines[&22].bias, Bias::AsIs);
+    }
+
+    #[test]
+    fn config_gpio_line_electrical_options_parse() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+gpio:
+  /dev/gpiochip0:
+    17: { short: NEXT, debounce_ms: 0, active_low: false, bias: pull_down }
+    27: { short: PAUSE, bias: pull_up }
+"#,
+        )
+        .unwrap();
+        let lines = &cfg.gpio["/dev/gpiochip0"].lines;
+        assert_eq!(lines[&17].debounce_ms, 0);
+        assert!(!lines[&17].active_low);
+        assert_eq!(lines[&17].bias, Bias::PullDown);
+        assert_eq!(lines[&27].bias, Bias::PullUp);
+        assert!(lines[&27].active_low);
+    }
+
+    #[test]
+    fn config_gpio_line_errors() {
+        for (line, needle) in [
+            ("{ long_ms: 500 }", "at least one"),
+            ("{ short: NEXT, repeat_ms: 200 }", "repeat_ms"),
+            ("{ short: NEXT, shrot: PAUSE }", "shrot"),
+            ("{ long: NXET }", "NXET"),
+            ("PAUSD", "PAUSD"),
+        ] {
+            let err = parse(&format!(
+                "alsa: {{}}\nspotify: {{}}\ngpio:\n  /dev/gpiochip0:\n    17: {line}\n"
+            ))
+            .unwrap_err();
+            assert!(err.to_string().contains(needle), "{line}: {err}");
+        }
+    }
+
+    #[test]
+    fn config_rotary_parses() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+rotary:
+  volume:
+    chip: /dev/gpiochip0
+    a: 17
+    b: 18
+    cw: VOLUME_INCREASE
+    ccw: VOLUME_DECREASE
+    bias: pull_up
+    switch: { line: 27, short: PLAY_PAUSE, long: STOP }
+  tracks:
+    chip: /dev/gpiochip0
+    a: 5
+    b: 6
+    cw: NEXT
+    ccw: PREVIOUS
+    steps_per_detent: 2
+"#,
+        )
+        .unwrap();
+        let volume = &cfg.rotary["volume"];
+        assert_eq!((volume.a, volume.b), (17, 18));
+        assert_eq!(volume.cw, Action::VolumeIncrease);
+        assert_eq!(volume.ccw, Action::VolumeDecrease);
+        assert_eq!(volume.steps_per_detent, 4);
+        assert_eq!(volume.bias, Bias::PullUp);
+        let switch = volume.switch.as_ref().unwrap();
+        assert_eq!(switch.line, 27);
+        assert_eq!(switch.button.action(Press::Short), Some(&Action::PlayPause));
+        assert_eq!(switch.button.action(Press::Long), Some(&Action::Stop));
+        let tracks = &cfg.rotary["tracks"];
+        assert_eq!(tracks.steps_per_detent, 2);
+        assert!(tracks.switch.is_none());
+    }
+
+    #[test]
+    fn config_rotary_errors() {
+        for (entry, needle) in [
+            ("{ chip: c, a: 1, b: 2, cw: NEXT }", "ccw"),
+            ("{ chip: c, a: 1, b: 2, cw: NEXT, ccw: PREVIOS }", "PREVIOS"),
+            (
+                "{ chip: c, a: 1, b: 2, cw: NEXT, ccw: PREVIOUS, c: 3 }",
+                "c",
+            ),
+            (
+                "{ chip: c, a: 1, b: 2, cw: NEXT, ccw: PREVIOUS, switch: { line: 3 } }",
+                "at least one",
+            ),
+        ] {
+            let err = parse(&format!(
+                "alsa: {{}}\nspotify: {{}}\nrotary:\n  knob: {entry}\n"
+            ))
+            .unwrap_err();
+            assert!(err.to_string().contains(needle), "{entry}: {err}");
+        }
+    }
+
+    #[test]
+    fn config_rfid_parses() {
+        let cfg = parse(&format!(
+            r#"
+alsa: {{}}
+spotify: {{}}
+rfid:
+  lid:
+    driver: mfrc522
+    path: /dev/spidev0.0
+    cards:
+      "04A1B2C3D4E5F6": "spotify:track:{TRACK_ID}"
+  shelf:
+    driver: pn532
+    path: /dev/i2c-1
+    poll_ms: 250
+    ndef: true
+  door:
+    driver: rdm6300
+    path: /dev/ttyAMA0
+    baud: 19200
+"#
+        ))
+        .unwrap();
+        let shelf = &cfg.rfid["shelf"];
+        assert_eq!(shelf.driver, RfidDriver::Pn532);
+        assert_eq!(shelf.poll_ms, 250);
+        assert_eq!(shelf.baud, None);
+        assert!(shelf.ndef);
+        let door = &cfg.rfid["door"];
+        assert_eq!(door.driver, RfidDriver::Rdm6300);
+        assert_eq!(door.baud, Some(19200));
+        let lid = &cfg.rfid["lid"];
+        assert_eq!(lid.driver, RfidDriver::Mfrc522);
+        assert_eq!(lid.path, "/dev/spidev0.0");
+        assert_eq!(lid.poll_ms, 100);
+        assert!(!lid.ndef);
+        assert_eq!(
+            lid.cards["04A1B2C3D4E5F6"],
+            Action::Play(format!("spotify:track:{TRACK_ID}"))
+        );
+
+        let err =
+            parse("alsa: {}\nspotify: {}\nrfid:\n  lid: { driver: rc522, path: /dev/spidev0.0 }\n")
+                .unwrap_err();
+        assert!(err.to_string().contains("rc522"), "{err}");
+    }
+
+    #[test]
+    fn config_input_keys_parse() {
+        let cfg = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  "Media Remote":
+    keys:
+      KEY_PLAYPAUSE: PLAY_PAUSE
+      KEY_VOLUMEUP: { short: VOLUME_INCREASE, long: VOLUME_INCREASE, repeat_ms: 200 }
+"#,
+        )
+        .unwrap();
+        let keys = &cfg.input["Media Remote"].keys;
+        assert_eq!(
+            keys[&KeyCode::KEY_PLAYPAUSE].action(Press::Short),
+            Some(&Action::PlayPause)
+        );
+        let vol = &keys[&KeyCode::KEY_VOLUMEUP];
+        assert_eq!(vol.action(Press::Repeat), Some(&Action::VolumeIncrease));
+        assert_eq!(vol.repeat_ms, Some(200));
+        assert!(cfg.input["Media Remote"].cards.is_empty());
+    }
+
+    #[test]
+    fn config_input_keys_errors() {
+        for (entry, needle) in [
+            ("{ keys: { KEY_PLAYPAUS: PAUSE } }", "KEY_PLAYPAUS"),
+            ("{ keys: { KEY_PLAYPAUSE: PAUS } }", "PAUS"),
+            (
+                "{ keys: { KEY_PLAYPAUSE: PAUSE }, cards: { \"1\": PAUSE } }",
+                "not both",
+            ),
+        ] {
+            let err = parse(&format!(
+                "alsa: {{}}\nspotify: {{}}\ninput:\n  remote: {entry}\n"
+            ))
+            .unwrap_err();
+            assert!(err.to_string().contains(needle), "{entry}: {err}");
+        }
+    }
+
+    #[test]
+    fn config_input_selectors() {
+        let conf = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  "usb:1a86:e026": { "1": PAUSE }
+  "phys:usb-0000:00:14.0-1/input0": { "2": PAUSE }
+  /dev/input/by-id/usb-HXGCoLtd_Keyboard-event-kbd: { "3": PAUSE }
+"#,
+        )
+        .unwrap();
+        assert_eq!(conf.input.len(), 3);
+
+        let err =
+            parse("alsa: {}\nspotify: {}\ninput:\n  \"usb:1a86\": { \"1\": PAUSE }\n").unwrap_err();
+        assert!(err.to_string().contains("usb:VVVV:PPPP"), "{err}");
+    }
+
+    #[test]
+    fn config_input_long_form_unknown_option_fails() {
+        let err = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    charsett: digits
+    cards: {}
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("charsett"), "{err}");
+    }
+
+    #[test]
+    fn config_input_entry_with_only_options_is_the_long_form() {
+        let cfg = parse(
+            "alsa: {}\nspotify: {}\ninput:\n  /dev/input/event0: { grab: true, removal_ms: 500 }\n\
+             gpio:\n  gpiochip0: { debounce_ms: 30 }\n",
+        )
+        .unwrap();
+        let device = &cfg.input["/dev/input/event0"];
+        assert!(device.grab);
+        assert_eq!(device.removal_ms, 500);
+        assert!(device.cards.is_empty());
+        assert_eq!(cfg.gpio["gpiochip0"].debounce_ms, 30);
+    }
+
+    #[test]
+    fn config_input_long_form_invalid_action_fails() {
+        let err = parse(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    cards:
+      "12345": "PAUSED"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("PAUSED"), "{err}");
+    }
+
     #[test]
     fn config_missing_alsa_section_fails() {
         // alsa is required (no #[serde(default)] on the field).
@@ -0,0 +1,393 @@
+//! The local control socket: a Unix stream socket speaking one request per
+//! line, for scripts and `socat`. Actions and made-up card reads become
+//! `InputEvent`s on the same channel the readers feed; every request is
+//! answered with one line.
+//!
+//! ```text
+//! play spotify:album:1DFixLWuPkv3KT3TnV35m3     ok
+//! volume -10                                    ok
+//! learn                                         ok
+//! card lid 04A1B2C3D4E5F6                       ok
+//! status                                        {"state":"playing",...}
+//! paws                                          error: unknown request "paws" ...
+//! ```
+
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::{Action, ActionParseError, volume_action};
+use crate::input::InputEvent;
+use crate::status::StatusBoard;
+use crate::uri::canonicalize_uri;
+
+/// What control events from the socket name as their source.
+const CLIENT: &str = "socket";
+
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("empty request")]
+    Empty,
+    #[error(
+        "unknown request {0:?}: expected play <uri>, pause, resume, toggle, stop, next, \
+         previous, volume <percent> or <+/-percent>, card <reader> <uid>, remove <reader> <uid>, \
+         scan <device> <id>, learn [<uri>], status, or an action like PLAY_PAUSE"
+    )]
+    Unknown(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error(transparent)]
+    Action(#[from] ActionParseError),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Something for the dispatch loop.
+    Event(InputEvent),
+    Status,
+}
+
+/// The playback commands the control interfaces share: `pause`, `resume`,
+/// `toggle`, `stop`, `next` and `previous`.
+pub fn playback_action(command: &str) -> Option<Action> {
+    Some(match command {
+        "pause" => Action::Pause,
+        "resume" => Action::Resume,
+        "toggle" => Action::PlayPause,
+        "stop" => Action::Stop,
+        "next" => Action::Next,
+        "previous" => Action::Previous,
+        _ => return None,
+    })
+}
+
+const VOLUME_USAGE: &str = "volume <percent> or volume <+/-percent>, e.g. volume 40 or volume +5";
+
+/// Parse one request line; control events name `client` as their source.
+pub fn parse_request(line: &str, client: &str) -> Result<Request, RequestError> {
+    let line = line.trim();
+    let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+    let action = match word {
+        "" => return Err(RequestError::Empty),
+        "status" => return Ok(Request::Status),
+        "play" if rest.is_empty() => return Err(RequestError::Usage("play <uri>")),
+        "play" => Action::Play(canonicalize_uri(rest).map_err(ActionParseError::from)?),
+        "learn" if rest.is_empty() => Action::Learn(None),
+        "learn" => Action::Learn(Some(
+            canonicalize_uri(rest).map_err(ActionParseError::from)?,
+        )),
+        "volume" => match volume_action(rest) {
+            Some(action) => action,
+            None => return Err(RequestError::Usage(VOLUME_USAGE)),
+        },
+        "card" | "remove" | "scan" => return card_event(word, rest).map(Request::Event),
+        other => match playback_action(other) {
+            Some(action) => action,
+            None => other
+                .parse::<Action>()
+                .map_err(|_| RequestError::Unknown(line.to_string()))?,
+        },
+    };
+    Ok(Request::Event(InputEvent::Control {
+        client: client.to_string(),
+        action,
+    }))
+}
+
+/// A card read as if it came from a reader. The ID is the last word, so
+/// device names with spaces work.
+fn card_event(word: &str, rest: &str) -> Result<InputEvent, RequestError> {
+    let Some((source, id)) = rest.rsplit_once(' ') else {
+        return Err(RequestError::Usage(match word {
+            "scan" => "scan <device> <id>",
+            _ => "card|remove <reader> <uid>",
+        }));
+    };
+    let (source, id) = (source.trim().to_string(), id.to_string());
+    Ok(match word {
+        "card" => InputEvent::Card {
+            reader: source,
+            uid: id,
+            uri: None,
+        },
+        "remove" => InputEvent::CardRemoved {
+            reader: source,
+            uid: id,
+        },
+        _ => InputEvent::Evdev {
+            device: source,
+            scanned: id,
+        },
+    })
+}
+
+/// Listen on `path`, replacing the socket an earlier run left behind. A
+/// socket someone still answers on belongs to another running soundkid and
+/// is left alone. Like the other setup functions, this fails at startup.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another soundkid is already serving this socket",
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+    }
+    UnixListener::bind(path)
+}
+
+/// Send one request to the control socket at `path` and return the reply,
+/// for the `soundkid` subcommands.
+pub async fn request(path: &Path, line: &str) -> io::Result<String> {
+    let stream = UnixStream::connect(path).await?;
+    let (read, mut write) = stream.into_split();
+    write.write_all(format!("{line}\n").as_bytes()).await?;
+    BufReader::new(read)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no reply"))
+}
+
+/// Spawn a task that serves clients of the control socket, each on a task
+/// of its own.
+pub fn spawn_control_socket(
+    listener: UnixListener,
+    tx: Sender<InputEvent>,
+    status: StatusBoard,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!("Control socket ready");
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(stream, tx.clone(), status.clone()));
+                }
+                Err(e) => {
+                    warn!("control socket accept failed: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    })
+}
+
+async fn serve(stream: UnixStream, tx: Sender<InputEvent>, status: StatusBoard) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!("Control request {line:?}");
+        let reply = match parse_request(&line, CLIENT) {
+            Ok(Request::Status) => serde_json::to_string(&status.get()).expect("status serializes"),
+            Ok(Request::Event(event)) => match tx.send(event).await {
+                Ok(()) => "ok".to_string(),
+                Err(_) => "error: shutting down".to_string(),
+            },
+            Err(e) => format!("error: {e}"),
+        };
+        if write
+            .write_all(format!("{reply}\n").as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::PlayState;
+    use tokio::sync::mpsc;
+
+    const ALBUM: &str = "1DFixLWuPkv3KT3TnV35m3";
+
+    fn control(action: Action) -> Request {
+        Request::Event(InputEvent::Control {
+            client: CLIENT.into(),
+            action,
+        })
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert_eq!(
+            parse_request("pause", CLIENT).unwrap(),
+            control(Action::Pause)
+        );
+        assert_eq!(
+            parse_request(" toggle \n", CLIENT).unwrap(),
+            control(Action::PlayPause)
+        );
+        assert_eq!(
+            parse_request("NEXT", CLIENT).unwrap(),
+            control(Action::Next)
+        );
+        assert_eq!(
+            parse_request("volume +5", CLIENT).unwrap(),
+            control(Action::Volume(5))
+        );
+        assert_eq!(
+            parse_request("volume -10", CLIENT).unwrap(),
+            control(Action::Volume(-10))
+        );
+        assert_eq!(
+            parse_request(
+                &format!("play https://open.spotify.com/album/{ALBUM}?si=x"),
+                CLIENT
+            )
+            .unwrap(),
+            control(Action::Play(format!("spotify:album:{ALBUM}")))
+        );
+        assert_eq!(
+            parse_request("volume 40", CLIENT).unwrap(),
+            control(Action::SetVolume(40))
+        );
+        assert_eq!(
+            parse_request("learn", CLIENT).unwrap(),
+            control(Action::Learn(None))
+        );
+        assert_eq!(
+            parse_request(&format!("learn spotify:album:{ALBUM}"), CLIENT).unwrap(),
+            control(Action::Learn(Some(format!("spotify:album:{ALBUM}"))))
+        );
+        assert_eq!(parse_request("status", CLIENT).unwrap(), Request::Status);
+    }
+
+    #[test]
+    fn parses_card_events_with_spaced_device_names() {
+        assert_eq!(
+            parse_request("card lid 04A1B2", CLIENT).unwrap(),
+            Request::Event(InputEvent::Card {
+                reader: "lid".into(),
+                uid: "04A1B2".into(),
+                uri: None,
+            })
+        );
+        assert_eq!(
+            parse_request("scan name:HXGCoLtd Keyboard 0012345678", CLIENT).unwrap(),
+            Request::Event(InputEvent::Evdev {
+                device: "name:HXGCoLtd Keyboard".into(),
+                scanned: "0012345678".into(),
+            })
+        );
+        assert_eq!(
+            parse_request("remove lid 04A1B2", CLIENT).unwrap(),
+            Request::Event(InputEvent::CardRemoved {
+                reader: "lid".into(),
+                uid: "04A1B2".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        assert!(matches!(
+            parse_request("", CLIENT),
+            Err(RequestError::Empty)
+        ));
+        assert!(matches!(
+            parse_request("paws", CLIENT),
+            Err(RequestError::Unknown(_))
+        ));
+        assert!(matches!(
+            parse_request("volume loud", CLIENT),
+            Err(RequestError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_request("volume +500", CLIENT),
+            Err(RequestError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_request("volume 101", CLIENT),
+            Err(RequestError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_request("card 04A1B2", CLIENT),
+            Err(RequestError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_request("play", CLIENT),
+            Err(RequestError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_request("play https://example.com/album/x", CLIENT),
+            Err(RequestError::Action(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_socket_in_use_is_not_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("soundkid.sock");
+        let _running = bind(&path).unwrap();
+        let err = bind(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn serves_requests_over_the_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("soundkid.sock");
+        // A socket left over from an earlier run is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = bind(&path).unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let status = StatusBoard::default();
+        status.update(|s| s.state = PlayState::Paused);
+        let server = spawn_control_socket(listener, tx, status);
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut replies = BufReader::new(read).lines();
+        write
+            .write_all(b"pause\ncard lid 04A1\npaws\nstatus\n")
+            .await
+            .unwrap();
+        let mut reply = Vec::new();
+        for _ in 0..4 {
+            reply.push(replies.next_line().await.unwrap().unwrap());
+        }
+        assert_eq!(reply[..2], ["ok", "ok"]);
+        assert!(reply[2].starts_with("error: unknown request \"paws\""));
+        assert!(reply[3].starts_with(r#"{"state":"paused","#));
+
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            InputEvent::Control {
+                client: CLIENT.into(),
+                action: Action::Pause,
+            }
+        );
+        assert!(matches!(rx.recv().await, Some(InputEvent::Card { .. })));
+
+        assert_eq!(request(&path, "learn").await.unwrap(), "ok");
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            InputEvent::Control {
+                client: CLIENT.into(),
+                action: Action::Learn(None),
+            }
+        );
+        assert!(
+            request(&path, "learn me")
+                .await
+                .unwrap()
+                .starts_with("error: ")
+        );
+        server.abort();
+    }
+}
@@ -0,0 +1,522 @@
+//! GPIO line requests through the v2 character-device uAPI (Linux 5.10+),
+//! which gpio-cdev doesn't speak. v2 lets the kernel debounce the line and
+//! set its bias; on older kernels `reader::setup_gpio_line` falls back to
+//! gpio-cdev's v1 requests and debounces in software.
+
+use futures::FutureExt;
+use futures::stream::StreamExt;
+use gpio_cdev::{AsyncLineEventHandle, EventType};
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+
+use crate::config::{Bias, ConfigGpioLine};
+
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+const GPIO_MAX_NAME_SIZE: usize = 32;
+
+const GPIO_V2_LINE_FLAG_ACTIVE_LOW: u64 = 1 << 1;
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+const GPIO_V2_LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+const GPIO_V2_LINE_FLAG_BIAS_DISABLED: u64 = 1 << 10;
+
+const GPIO_V2_LINE_ATTR_ID_DEBOUNCE: u32 = 3;
+const GPIO_V2_LINE_EVENT_RISING_EDGE: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union LineAttributeValue {
+    values: u64,
+    debounce_period_us: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    value: LineAttributeValue,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct LineEvent {
+    timestamp_ns: u64,
+    id: u32,
+    offset: u32,
+    seqno: u32,
+    line_seqno: u32,
+    padding: [u32; 6],
+}
+
+// Sizes fixed by the kernel ABI (include/uapi/linux/gpio.h).
+const _: () = assert!(size_of::<LineRequest>() == 592);
+const _: () = assert!(size_of::<LineEvent>() == 48);
+
+/// `_IOWR(0xB4, 0x07, struct gpio_v2_line_request)`
+const GPIO_V2_GET_LINE_IOCTL: u32 =
+    (3 << 30) | ((size_of::<LineRequest>() as u32) << 16) | (0xB4 << 8) | 0x07;
+
+/// How a line is requested from the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    /// 0 disables.
+    pub debounce_ms: u64,
+    pub active_low: bool,
+    pub bias: Bias,
+}
+
+impl From<&ConfigGpioLine> for LineSettings {
+    fn from(conf: &ConfigGpioLine) -> Self {
+        Self {
+            debounce_ms: conf.debounce_ms,
+            active_low: conf.active_low,
+            bias: conf.bias,
+        }
+    }
+}
+
+/// One debounced edge. `pressed` is the logical level after the edge, so
+/// active-low is already accounted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    /// The line the edge is on.
+    pub line: u32,
+    pub pressed: bool,
+    pub timestamp: Duration,
+}
+
+/// Edge events of requested lines, from either uAPI version.
+pub enum GpioEdges {
+    /// v2 request: debounce and bias are done by the kernel.
+    Kernel(AsyncFd<OwnedFd>),
+    /// v1 request via gpio-cdev, always of one line: debounce is up to us.
+    Legacy {
+        events: AsyncLineEventHandle,
+        line: u32,
+    },
+}
+
+impl GpioEdges {
+    /// Request `line` on `chip_path` for both edges with the line's
+    /// active-low, bias and debounce settings.
+    pub fn request(chip_path: &str, line: u32, settings: &LineSettings) -> io::Result<Self> {
+        Self::request_lines(chip_path, &[line], settings)
+    }
+
+    /// Request several lines at once, with the same settings. Their edges
+    /// come in the order the kernel saw them.
+    pub fn request_lines(
+        chip_path: &str,
+        lines: &[u32],
+        settings: &LineSettings,
+    ) -> io::Result<Self> {
+        let path = CString::new(Path::new(chip_path).as_os_str().as_bytes())?;
+        // SAFETY: plain open(2) of a NUL-terminated path; the result is
+        // checked before it is wrapped.
+        let chip = unsafe { libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        if chip < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `chip` was just opened and is owned by nobody else.
+        let chip = unsafe { OwnedFd::from_raw_fd(chip) };
+
+        let mut req = line_request(lines, settings);
+        // SAFETY: `req` has the kernel's gpio_v2_line_request layout (the
+        // size is asserted above) and outlives the call.
+        let rc = unsafe {
+            libc::ioctl(
+                chip.as_raw_fd(),
+                GPIO_V2_GET_LINE_IOCTL as libc::Ioctl,
+                &mut req as *mut LineRequest,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: on success the kernel hands us a fresh line fd.
+        let fd = unsafe { OwnedFd::from_raw_fd(req.fd) };
+        set_nonblocking(&fd)?;
+        Ok(GpioEdges::Kernel(AsyncFd::new(fd)?))
+    }
+
+    /// Whether the kernel already debounces these edges.
+    pub fn kernel_debounced(&self) -> bool {
+        matches!(self, GpioEdges::Kernel(_))
+    }
+
+    /// Wait for the next edge.
+    pub async fn next(&mut self) -> io::Result<Edge> {
+        match self {
+            GpioEdges::Kernel(fd) => loop {
+                let mut guard = fd.readable().await?;
+                match guard.try_io(|fd| read_event(fd.get_ref())) {
+                    Ok(result) => return result,
+                    Err(_would_block) => continue,
+                }
+            },
+            GpioEdges::Legacy { events, line } => {
+                let ev = events
+                    .next()
+                    .await
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?
+                    .map_err(io::Error::other)?;
+                Ok(Edge {
+                    line: *line,
+                    pressed: ev.event_type() == EventType::RisingEdge,
+                    timestamp: Duration::from_nanos(ev.timestamp()),
+                })
+            }
+        }
+    }
+}
+
+/// The edges of two lines, such as an encoder's A and B, in the order they
+/// happened.
+pub enum PairedEdges {
+    /// One v2 request for both lines: the kernel queues their edges in
+    /// order.
+    Shared(GpioEdges),
+    /// A v1 request per line. Edges queued on both by the time one is read
+    /// are put in order by their timestamps.
+    Separate {
+        lines: [GpioEdges; 2],
+        pending: [Option<Edge>; 2],
+    },
+}
+
+impl PairedEdges {
+    pub fn separate(first: GpioEdges, second: GpioEdges) -> Self {
+        PairedEdges::Separate {
+            lines: [first, second],
+            pending: [None, None],
+        }
+    }
+
+    /// Wait for the next edge on either line.
+    pub async fn next(&mut self) -> io::Result<Edge> {
+        let (lines, pending) = match self {
+            PairedEdges::Shared(edges) => return edges.next().await,
+            PairedEdges::Separate { lines, pending } => (lines, pending),
+        };
+        let [first, second] = lines;
+        if pending.iter().all(Option::is_none) {
+            tokio::select! {
+                edge = first.next() => pending[0] = Some(edge?),
+                edge = second.next() => pending[1] = Some(edge?),
+            }
+        }
+        // The other line may have an earlier edge queued already.
+        if pending[0].is_none() {
+            if let Some(edge) = first.next().now_or_never() {
+                pending[0] = Some(edge?);
+            }
+        }
+        if pending[1].is_none() {
+            if let Some(edge) = second.next().now_or_never() {
+                pending[1] = Some(edge?);
+            }
+        }
+        let earlier = match pending {
+            [Some(a), Some(b)] if b.timestamp < a.timestamp => 1,
+            [Some(_), _] => 0,
+            _ => 1,
+        };
+        Ok(pending[earlier].take().expect("an edge is pending"))
+    }
+}
+
+/// Kernels before 5.10 don't know the v2 ioctl and answer ENOTTY. EINVAL is
+/// a v2 kernel refusing the request itself (say, a bias the chip can't do),
+/// which the legacy API would only hide.
+pub fn is_unsupported(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ENOTTY)
+}
+
+fn line_request(lines: &[u32], settings: &LineSettings) -> LineRequest {
+    let mut flags =
+        GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_EDGE_RISING | GPIO_V2_LINE_FLAG_EDGE_FALLING;
+    if settings.active_low {
+        flags |= GPIO_V2_LINE_FLAG_ACTIVE_LOW;
+    }
+    flags |= match settings.bias {
+        Bias::AsIs => 0,
+        Bias::PullUp => GPIO_V2_LINE_FLAG_BIAS_PULL_UP,
+        Bias::PullDown => GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN,
+        Bias::Disabled => GPIO_V2_LINE_FLAG_BIAS_DISABLED,
+    };
+
+    let unused = LineConfigAttribute {
+        attr: LineAttribute {
+            id: 0,
+            padding: 0,
+            value: LineAttributeValue { values: 0 },
+        },
+        mask: 0,
+    };
+    let mut config = LineConfig {
+        flags,
+        num_attrs: 0,
+        padding: [0; 5],
+        attrs: [unused; GPIO_V2_LINE_NUM_ATTRS_MAX],
+    };
+    if settings.debounce_ms > 0 {
+        let us = settings.debounce_ms.saturating_mul(1000);
+        config.attrs[0] = LineConfigAttribute {
+            attr: LineAttribute {
+                id: GPIO_V2_LINE_ATTR_ID_DEBOUNCE,
+                padding: 0,
+                value: LineAttributeValue {
+                    debounce_period_us: us.try_into().unwrap_or(u32::MAX),
+                },
+            },
+            // One bit per line of the request.
+            mask: u64::MAX >> (64 - lines.len()),
+        };
+        config.num_attrs = 1;
+    }
+
+    let mut offsets = [0; GPIO_V2_LINES_MAX];
+    offsets[..lines.len()].copy_from_slice(lines);
+    let mut consumer = [0; GPIO_MAX_NAME_SIZE];
+    consumer[..8].copy_from_slice(b"soundkid");
+    LineRequest {
+        offsets,
+        consumer,
+        config,
+        num_lines: lines.len() as u32,
+        event_buffer_size: 0,
+        padding: [0; 5],
+        fd: 0,
+    }
+}
+
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    // SAFETY: fcntl on a valid, owned fd.
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn read_event(fd: &OwnedFd) -> io::Result<Edge> {
+    let mut ev = LineEvent::default();
+    // SAFETY: reads at most size_of::<LineEvent>() bytes into `ev`, which
+    // has the kernel's gpio_v2_line_event layout.
+    let n = unsafe {
+        libc::read(
+            fd.as_raw_fd(),
+            &mut ev as *mut LineEvent as *mut libc::c_void,
+            size_of::<LineEvent>(),
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if n as usize != size_of::<LineEvent>() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("short GPIO event read of {n} bytes"),
+        ));
+    }
+    Ok(Edge {
+        line: ev.offset,
+        pressed: ev.id == GPIO_V2_LINE_EVENT_RISING_EDGE,
+        timestamp: Duration::from_nanos(ev.timestamp_ns),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(yaml: &str) -> LineSettings {
+        let conf: ConfigGpioLine =
+            serde_yaml_ng::from_str(yaml).expect("test line config must parse");
+        LineSettings::from(&conf)
+    }
+
+    #[test]
+    fn ioctl_number_matches_kernel_header() {
+        assert_eq!(GPIO_V2_GET_LINE_IOCTL, 0xC250_B407);
+    }
+
+    #[test]
+    fn request_carries_flags_and_debounce() {
+        let req = line_request(
+            &[17],
+            &line("{ short: PAUSE, bias: pull_up, debounce_ms: 15 }"),
+        );
+        assert_eq!(req.offsets[0], 17);
+        assert_eq!(req.num_lines, 1);
+        assert_eq!(&req.consumer[..9], b"soundkid\0");
+        assert_eq!(
+            req.config.flags,
+            GPIO_V2_LINE_FLAG_INPUT
+                | GPIO_V2_LINE_FLAG_EDGE_RISING
+                | GPIO_V2_LINE_FLAG_EDGE_FALLING
+                | GPIO_V2_LINE_FLAG_ACTIVE_LOW
+                | GPIO_V2_LINE_FLAG_BIAS_PULL_UP
+        );
+        assert_eq!(req.config.num_attrs, 1);
+        let attr = req.config.attrs[0];
+        assert_eq!(attr.attr.id, GPIO_V2_LINE_ATTR_ID_DEBOUNCE);
+        // SAFETY: the debounce attribute stores debounce_period_us.
+        assert_eq!(unsafe { attr.attr.value.debounce_period_us }, 15_000);
+        assert_eq!(attr.mask, 1);
+    }
+
+    #[test]
+    fn request_without_debounce_or_active_low() {
+        let req = line_request(
+            &[4],
+            &line("{ short: PAUSE, active_low: false, debounce_ms: 0 }"),
+        );
+        assert_eq!(req.config.flags & GPIO_V2_LINE_FLAG_ACTIVE_LOW, 0);
+        assert_eq!(req.config.num_attrs, 0);
+    }
+
+    #[test]
+    fn one_request_carries_several_lines() {
+        let req = line_request(&[5, 6], &line("{ short: PAUSE, debounce_ms: 2 }"));
+        assert_eq!(req.num_lines, 2);
+        assert_eq!(req.offsets[..3], [5, 6, 0]);
+        assert_eq!(req.config.attrs[0].mask, 0b11);
+    }
+
+    /// Edges read from a pipe, written by the second fd the way the kernel
+    /// writes them to a line fd.
+    fn pipe_edges() -> (GpioEdges, OwnedFd) {
+        let mut fds = [0; 2];
+        // SAFETY: pipe2 fills in two fresh fds, checked before use.
+        assert_eq!(
+            unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) },
+            0
+        );
+        // SAFETY: both fds were just created and are owned by nobody else.
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        (GpioEdges::Kernel(AsyncFd::new(read).unwrap()), write)
+    }
+
+    fn write_event(fd: &OwnedFd, line: u32, rising: bool, timestamp_ns: u64) {
+        let ev = LineEvent {
+            timestamp_ns,
+            id: if rising {
+                GPIO_V2_LINE_EVENT_RISING_EDGE
+            } else {
+                2
+            },
+            offset: line,
+            ..LineEvent::default()
+        };
+        // SAFETY: writes the bytes of a plain repr(C) struct.
+        let n = unsafe {
+            libc::write(
+                fd.as_raw_fd(),
+                &ev as *const LineEvent as *const libc::c_void,
+                size_of::<LineEvent>(),
+            )
+        };
+        assert_eq!(n as usize, size_of::<LineEvent>());
+    }
+
+    fn edge(line: u32, pressed: bool, ns: u64) -> Edge {
+        Edge {
+            line,
+            pressed,
+            timestamp: Duration::from_nanos(ns),
+        }
+    }
+
+    #[tokio::test]
+    async fn shared_edges_keep_the_kernel_order() {
+        let (edges, kernel) = pipe_edges();
+        let mut paired = PairedEdges::Shared(edges);
+        write_event(&kernel, 6, true, 10);
+        write_event(&kernel, 5, true, 20);
+        write_event(&kernel, 6, false, 30);
+        assert_eq!(paired.next().await.unwrap(), edge(6, true, 10));
+        assert_eq!(paired.next().await.unwrap(), edge(5, true, 20));
+        assert_eq!(paired.next().await.unwrap(), edge(6, false, 30));
+    }
+
+    #[tokio::test]
+    async fn separate_edges_are_put_in_order() {
+        for _ in 0..20 {
+            let (a, a_kernel) = pipe_edges();
+            let (b, b_kernel) = pipe_edges();
+            let mut paired = PairedEdges::separate(a, b);
+            write_event(&b_kernel, 6, true, 10);
+            write_event(&a_kernel, 5, true, 20);
+            write_event(&b_kernel, 6, false, 30);
+            write_event(&a_kernel, 5, false, 40);
+            let mut seen = Vec::new();
+            for _ in 0..4 {
+                seen.push(paired.next().await.unwrap());
+            }
+            assert_eq!(
+                seen,
+                [
+                    edge(6, true, 10),
+                    edge(5, true, 20),
+                    edge(6, false, 30),
+                    edge(5, false, 40),
+                ]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn request_on_missing_chip_fails() {
+        let err = GpioEdges::request("/dev/gpiochip-does-not-exist", 0, &line("PAUSE"))
+            .err()
+            .expect("request must fail");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!is_unsupported(&err));
+    }
+
+    #[test]
+    fn only_enotty_means_unsupported() {
+        assert!(is_unsupported(&io::Error::from_raw_os_error(libc::ENOTTY)));
+        assert!(!is_unsupported(&io::Error::from_raw_os_error(libc::EINVAL)));
+        assert!(!is_unsupported(&io::Error::from_raw_os_error(libc::EBUSY)));
+    }
+}
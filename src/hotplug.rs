@@ -0,0 +1,257 @@
+use serde::{Serialize, Serializer};
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::status::StatusBoard;
+
+/// Where evdev device nodes come and go.
+pub const INPUT_DIR: &str = "/dev/input";
+
+/// How often to look for devices when inotify isn't available.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Wakes up whenever something in a directory is created, deleted, renamed
+/// or has its permissions changed. udev creates a node first and fixes its
+/// permissions after, so attribute changes matter as much as new files.
+pub struct DirWatcher {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        // SAFETY: inotify_init1 takes only flags; the result is checked.
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just created and is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let path = CString::new(dir.as_os_str().as_bytes())?;
+        let mask = libc::IN_CREATE | libc::IN_DELETE | libc::IN_ATTRIB | libc::IN_MOVED_TO;
+        // SAFETY: valid inotify fd and NUL-terminated path.
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Wait for the next batch of changes. Which files changed doesn't
+    /// matter to us, so the events are drained and dropped.
+    pub async fn changed(&mut self) -> io::Result<()> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            match guard.try_io(|fd| drain(fd.get_ref())) {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+fn drain(fd: &OwnedFd) -> io::Result<()> {
+    let mut buf = [0u8; 4096];
+    let mut read_any = false;
+    loop {
+        // SAFETY: reads into a stack buffer of the given length.
+        let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+        if n > 0 {
+            read_any = true;
+            continue;
+        }
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::WouldBlock if read_any => Ok(()),
+            _ if n == 0 => Ok(()),
+            _ => Err(err),
+        };
+    }
+}
+
+/// Watch `dir` and return a receiver that sees a new value after every
+/// change. Without inotify it falls back to waking every few seconds, so
+/// readers still come back, just more slowly.
+pub fn spawn_watcher(dir: &Path) -> watch::Receiver<()> {
+    let (tx, rx) = watch::channel(());
+    let watcher = DirWatcher::new(dir);
+    let dir = dir.to_path_buf();
+    tokio::spawn(async move {
+        let mut watcher = match watcher {
+            Ok(w) => Some(w),
+            Err(e) => {
+                warn!(
+                    "cannot watch {dir:?} for devices ({e}), checking every {}s instead",
+                    POLL_INTERVAL.as_secs()
+                );
+                None
+            }
+        };
+        loop {
+            match &mut watcher {
+                Some(w) => {
+                    if let Err(e) = w.changed().await {
+                        warn!("watching {dir:?} failed ({e}), polling instead");
+                        watcher = None;
+                    }
+                }
+                None => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+            if tx.send(()).is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// Where a configured device stands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceState {
+    /// Not found (yet); attached as soon as it shows up.
+    Waiting,
+    /// Open and being read, at this `/dev/input/event*` path.
+    Attached(String),
+    /// Was attached, then went away; waiting for it to come back.
+    Disconnected,
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceState::Waiting => f.write_str("waiting"),
+            DeviceState::Attached(path) => write!(f, "attached at {path}"),
+            DeviceState::Disconnected => f.write_str("disconnected"),
+        }
+    }
+}
+
+/// Reported the way it is logged.
+impl Serialize for DeviceState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The state of every configured input device, kept in the [`Status`] the
+/// control interfaces report. Clones share the same states.
+///
+/// [`Status`]: crate::status::Status
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStates(StatusBoard);
+
+impl DeviceStates {
+    pub fn new(status: StatusBoard) -> Self {
+        Self(status)
+    }
+
+    /// Record `state` for `device`, logging it if it changed.
+    pub fn set(&self, device: &str, state: DeviceState) {
+        if self.get(device).as_ref() == Some(&state) {
+            return;
+        }
+        match &state {
+            DeviceState::Attached(_) => info!("Input device {device:?} {state}"),
+            _ => warn!("Input device {device:?} {state}"),
+        }
+        self.0.update(|s| {
+            s.devices.insert(device.to_string(), state);
+        });
+    }
+
+    pub fn get(&self, device: &str) -> Option<DeviceState> {
+        self.0.get().devices.get(device).cloned()
+    }
+
+    /// All devices and their states, sorted by device.
+    pub fn snapshot(&self) -> Vec<(String, DeviceState)> {
+        self.0.get().devices.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn watcher_wakes_on_new_file() {
+        let dir = tempdir().unwrap();
+        let mut watcher = DirWatcher::new(dir.path()).unwrap();
+        std::fs::write(dir.path().join("event7"), b"").unwrap();
+        timeout(Duration::from_secs(5), watcher.changed())
+            .await
+            .expect("no change seen")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn spawned_watcher_notifies_receivers() {
+        let dir = tempdir().unwrap();
+        let mut rx = spawn_watcher(dir.path());
+        rx.borrow_and_update();
+        std::fs::write(dir.path().join("event3"), b"").unwrap();
+        timeout(Duration::from_secs(5), rx.changed())
+            .await
+            .expect("no change seen")
+            .unwrap();
+    }
+
+    #[test]
+    fn watcher_on_missing_dir_fails() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter();
+        assert!(DirWatcher::new(Path::new("/does/not/exist")).is_err());
+    }
+
+    #[test]
+    fn states_record_and_snapshot_sorted() {
+        let states = DeviceStates::default();
+        states.set("reader-b", DeviceState::Waiting);
+        states.set(
+            "reader-a",
+            DeviceState::Attached("/dev/input/event3".into()),
+        );
+        states.set("reader-b", DeviceState::Disconnected);
+        assert_eq!(
+            states.snapshot(),
+            vec![
+                (
+                    "reader-a".into(),
+                    DeviceState::Attached("/dev/input/event3".into())
+                ),
+                ("reader-b".into(), DeviceState::Disconnected),
+            ]
+        );
+        assert_eq!(states.get("reader-c"), None);
+    }
+
+    #[test]
+    fn states_show_in_the_status() {
+        let status = StatusBoard::default();
+        let states = DeviceStates::new(status.clone());
+        states.set("reader-a", DeviceState::Waiting);
+        assert_eq!(
+            status.get().devices.get("reader-a"),
+            Some(&DeviceState::Waiting)
+        );
+    }
+
+    #[test]
+    fn state_display() {
+        assert_eq!(
+            DeviceState::Attached("/dev/input/event3".into()).to_string(),
+            "attached at /dev/input/event3"
+        );
+        assert_eq!(DeviceState::Waiting.to_string(), "waiting");
+    }
+}
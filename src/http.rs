@@ -0,0 +1,690 @@
+//! The HTTP API, for phones and home automation on the network. Like the
+//! control socket, requests become `InputEvent`s on the channel the readers
+//! feed.
+//!
+//! ```text
+//! GET  /api/status              {"state":"playing","uri":"spotify:album:...",...}
+//! POST /api/control/<command>   pause, resume, toggle, stop, next or previous
+//! POST /api/play                {"uri": "https://open.spotify.com/album/..."}
+//! POST /api/volume              {"change": -10}
+//! GET  /api/cards               [{"source":"rfid","device":"lid","id":"04A1...","action":"..."}]
+//! POST /api/cards/<id>          as if the card had been read; 409 if several devices know it
+//! ```
+//!
+//! Commands answer `204 No Content`; errors come as `{"error": "..."}`.
+//! Browsers posting from another site's page get `403 Forbidden`.
+
+use axum::extract::{Path, Request, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::prelude::{BASE64_STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::cards::{Assigned, CardStore};
+use crate::config::{Action, Config, ConfigHttp};
+use crate::control::playback_action;
+use crate::input::InputEvent;
+use crate::status::{Status, StatusBoard};
+use crate::uri::canonicalize_uri;
+use crate::webui;
+
+/// What control events from the API name as their source.
+const CLIENT: &str = "http";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// A card under `input:`, read by a USB reader.
+    Input,
+    /// A card under `rfid:`.
+    Rfid,
+}
+
+/// One configured card.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mapping {
+    pub source: Source,
+    pub device: String,
+    pub id: String,
+    pub action: Action,
+    /// Assigned at runtime rather than written in the config.
+    pub assigned: bool,
+}
+
+impl Mapping {
+    /// The event the device sends when the card is read.
+    fn event(&self) -> InputEvent {
+        match self.source {
+            Source::Input => InputEvent::Evdev {
+                device: self.device.clone(),
+                scanned: self.id.clone(),
+            },
+            Source::Rfid => InputEvent::Card {
+                reader: self.device.clone(),
+                uid: self.id.clone(),
+                uri: None,
+            },
+        }
+    }
+}
+
+/// Which section of the config `device` is in, if any.
+pub fn source(conf: &Config, device: &str) -> Option<Source> {
+    if conf.input.contains_key(device) {
+        Some(Source::Input)
+    } else if conf.rfid.contains_key(device) {
+        Some(Source::Rfid)
+    } else {
+        None
+    }
+}
+
+/// Every card in the config, and those assigned since for devices that are
+/// still configured, sorted by device and ID. The config wins where both
+/// know a card, as it does when the card is read.
+pub fn mappings(conf: &Config, assigned: &Assigned) -> Vec<Mapping> {
+    let input = conf.input.iter().map(|(d, c)| (Source::Input, d, &c.cards));
+    let rfid = conf.rfid.iter().map(|(d, c)| (Source::Rfid, d, &c.cards));
+    let mut all: Vec<Mapping> = input
+        .chain(rfid)
+        .flat_map(|(source, device, cards)| {
+            cards.iter().map(move |(id, action)| Mapping {
+                source,
+                device: device.clone(),
+                id: id.clone(),
+                action: action.clone(),
+                assigned: false,
+            })
+        })
+        .collect();
+    for (device, cards) in assigned {
+        let Some(source) = source(conf, device) else {
+            continue;
+        };
+        for (id, action) in cards {
+            if !all.iter().any(|m| m.device == *device && m.id == *id) {
+                all.push(Mapping {
+                    source,
+                    device: device.clone(),
+                    id: id.clone(),
+                    action: action.clone(),
+                    assigned: true,
+                });
+            }
+        }
+    }
+    all.sort_by(|a, b| (&a.device, &a.id).cmp(&(&b.device, &b.id)));
+    all
+}
+
+/// What the handlers share.
+#[derive(Clone)]
+pub struct Api {
+    pub(crate) conf: Arc<Config>,
+    pub(crate) tx: Sender<InputEvent>,
+    pub(crate) status: StatusBoard,
+    pub(crate) cards: CardStore,
+    token: Option<Arc<str>>,
+}
+
+impl Api {
+    pub fn new(
+        conf: &Config,
+        tx: Sender<InputEvent>,
+        status: StatusBoard,
+        cards: CardStore,
+    ) -> Self {
+        Self {
+            conf: Arc::new(conf.clone()),
+            tx,
+            status,
+            cards,
+            token: conf
+                .http
+                .as_ref()
+                .and_then(|h| h.token.as_deref().map(Into::into)),
+        }
+    }
+
+    pub(crate) async fn mappings(&self) -> Vec<Mapping> {
+        mappings(&self.conf, &self.cards.all().await)
+    }
+
+    async fn send(&self, event: InputEvent) -> Response {
+        match self.tx.send(event).await {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(_) => error(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+        }
+    }
+
+    async fn act(&self, action: Action) -> Response {
+        self.send(InputEvent::Control {
+            client: CLIENT.to_string(),
+            action,
+        })
+        .await
+    }
+
+    /// Whether the request carries the token, as `Bearer <token>` or as the
+    /// password of HTTP basic auth (which browsers ask for).
+    fn authorized(&self, req: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let Some(given) = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return false;
+        };
+        if let Some(bearer) = given.strip_prefix("Bearer ") {
+            return same_secret(bearer, token);
+        }
+        given
+            .strip_prefix("Basic ")
+            .and_then(|b| BASE64_STANDARD.decode(b).ok())
+            .and_then(|b| String::from_utf8(b).ok())
+            .is_some_and(|credentials| {
+                credentials
+                    .split_once(':')
+                    .is_some_and(|(_, password)| same_secret(password, token))
+            })
+    }
+}
+
+/// Compares a given token to the configured one in time that depends only on
+/// the lengths, so the response time gives no hint of a matching prefix.
+fn same_secret(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn error(code: StatusCode, message: impl ToString) -> Response {
+    #[derive(Serialize)]
+    struct Error {
+        error: String,
+    }
+    let body = Error {
+        error: message.to_string(),
+    };
+    (code, Json(body)).into_response()
+}
+
+/// The routes, with the token check in front when one is configured.
+/// The web UI comes along if `ui` is set.
+pub fn router(api: Api) -> Router {
+    let mut router = Router::new()
+        .route("/api/status", get(status))
+        .route("/api/control/{command}", post(control))
+        .route("/api/play", post(play))
+        .route("/api/volume", post(volume))
+        .route("/api/cards", get(cards))
+        .route("/api/cards/{id}", post(trigger));
+    if api.conf.http.as_ref().is_some_and(|h| h.ui) {
+        router = router.merge(webui::routes());
+    }
+    router
+        .route_layer(middleware::from_fn_with_state(api.clone(), authorize))
+        .route_layer(middleware::from_fn(same_origin))
+        .with_state(api)
+}
+
+/// Turn away changes a browser sends on behalf of another site's page.
+/// Form posts and body-less POSTs go cross-origin without a preflight, and
+/// with the basic auth the browser has cached, so any page open on the LAN
+/// could otherwise stop playback or assign cards.
+async fn same_origin(req: Request, next: Next) -> Response {
+    if !req.method().is_safe() && cross_site(&req) {
+        return error(StatusCode::FORBIDDEN, "cross-site request refused");
+    }
+    next.run(req).await
+}
+
+/// Whether a browser says the request comes from another origin. Clients
+/// that aren't browsers send neither header and pass.
+fn cross_site(req: &Request) -> bool {
+    let headers = req.headers();
+    if let Some(site) = headers.get("sec-fetch-site") {
+        return !matches!(site.as_bytes(), b"same-origin" | b"none");
+    }
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return false;
+    };
+    let origin = origin
+        .to_str()
+        .ok()
+        .and_then(|o| o.split_once("://"))
+        .map(|(_, host)| host);
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok());
+    match (origin, host) {
+        (Some(origin), Some(host)) => !origin.eq_ignore_ascii_case(host),
+        // Including `Origin: null`, from sandboxed pages and redirects.
+        _ => true,
+    }
+}
+
+async fn authorize(State(api): State<Api>, req: Request, next: Next) -> Response {
+    if !api.authorized(&req) {
+        let mut response = error(StatusCode::UNAUTHORIZED, "missing or wrong token");
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"soundkid\""),
+        );
+        return response;
+    }
+    next.run(req).await
+}
+
+async fn status(State(api): State<Api>) -> Json<Status> {
+    Json(api.status.get())
+}
+
+async fn control(State(api): State<Api>, Path(command): Path<String>) -> Response {
+    match playback_action(&command) {
+        Some(action) => api.act(action).await,
+        None => error(
+            StatusCode::NOT_FOUND,
+            format!(
+                "unknown command {command:?}: expected pause, resume, toggle, stop, next or previous"
+            ),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct Play {
+    uri: String,
+}
+
+async fn play(State(api): State<Api>, Json(body): Json<Play>) -> Response {
+    match canonicalize_uri(&body.uri) {
+        Ok(uri) => api.act(Action::Play(uri)).await,
+        Err(e) => error(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+#[derive(Deserialize)]
+struct Volume {
+    /// Percent, up or down.
+    change: i32,
+}
+
+async fn volume(State(api): State<Api>, Json(body): Json<Volume>) -> Response {
+    if !(-100..=100).contains(&body.change) {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "change must be between -100 and 100",
+        );
+    }
+    api.act(Action::Volume(body.change)).await
+}
+
+async fn cards(State(api): State<Api>) -> Json<Vec<Mapping>> {
+    Json(api.mappings().await)
+}
+
+/// Read a configured card. An ID that several devices know is refused with
+/// the candidates rather than guessed.
+async fn trigger(State(api): State<Api>, Path(id): Path<String>) -> Response {
+    let mut found: Vec<Mapping> = api.mappings().await;
+    found.retain(|m| m.id == id);
+    match found.as_slice() {
+        [] => error(StatusCode::NOT_FOUND, format!("no card {id:?} configured")),
+        [mapping] => api.send(mapping.event()).await,
+        several => {
+            let devices: Vec<&str> = several.iter().map(|m| m.device.as_str()).collect();
+            error(
+                StatusCode::CONFLICT,
+                format!("card {id:?} is known to several devices: {devices:?}"),
+            )
+        }
+    }
+}
+
+/// Listen on the configured address. Like the other setup functions, this
+/// fails at startup.
+pub async fn setup_http(conf: &ConfigHttp) -> io::Result<TcpListener> {
+    TcpListener::bind(conf.bind).await
+}
+
+/// Spawn a task that serves the API.
+pub fn spawn_http_server(listener: TcpListener, api: Api) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Ok(addr) = listener.local_addr() {
+            info!("HTTP API listening on {addr}");
+        }
+        if let Err(e) = axum::serve(listener, router(api)).await {
+            warn!("HTTP API stopped: {e}");
+        }
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::status::PlayState;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::mpsc::{self, Receiver};
+
+    pub(crate) const ALBUM: &str = "1DFixLWuPkv3KT3TnV35m3";
+
+    pub(crate) fn config(http: &str) -> Config {
+        serde_yaml_ng::from_str(&format!(
+            r#"
+alsa: {{}}
+spotify: {{}}
+http: {http}
+input:
+  /dev/input/event0:
+    "0012345678": "spotify:album:{ALBUM}"
+rfid:
+  lid:
+    driver: mfrc522
+    path: /dev/spidev0.0
+    cards:
+      04A1B2: PAUSE
+"#
+        ))
+        .expect("test config must parse")
+    }
+
+    async fn serve(http: &str) -> (SocketAddr, Receiver<InputEvent>, StatusBoard) {
+        let cards = CardStore::new(std::path::Path::new("/nonexistent"));
+        serve_with(http, cards).await
+    }
+
+    pub(crate) async fn serve_with(
+        http: &str,
+        cards: CardStore,
+    ) -> (SocketAddr, Receiver<InputEvent>, StatusBoard) {
+        let conf = config(http);
+        let listener = setup_http(conf.http.as_ref().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel(8);
+        let status = StatusBoard::default();
+        spawn_http_server(listener, Api::new(&conf, tx, status.clone(), cards));
+        (addr, rx, status)
+    }
+
+    /// One HTTP/1.1 request with a JSON body on its own connection: the
+    /// status code and body.
+    async fn request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> (u16, String) {
+        let mut headers = "Content-Type: application/json\r\n".to_string();
+        if let Some(token) = token {
+            headers += &format!("Authorization: Bearer {token}\r\n");
+        }
+        let (code, _, body) = raw_request(addr, method, path, &headers, body).await;
+        (code, body)
+    }
+
+    /// One HTTP/1.1 request on its own connection, with `headers` as
+    /// `Name: value\r\n` lines: the status code, headers and body.
+    pub(crate) async fn raw_request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> (u16, String, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "{method} {path} HTTP/1.1\r\nHost: soundkid\r\nConnection: close\r\n\
+             {headers}Content-Length: {}\r\n",
+            body.len()
+        );
+        stream
+            .write_all(format!("{head}\r\n{body}").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let code = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (code, head.to_string(), body.to_string())
+    }
+
+    fn control(action: Action) -> InputEvent {
+        InputEvent::Control {
+            client: CLIENT.into(),
+            action,
+        }
+    }
+
+    #[test]
+    fn lists_configured_and_assigned_cards() {
+        let conf = config("{ bind: \"127.0.0.1:0\" }");
+        let mut assigned = Assigned::new();
+        let lid = assigned.entry("lid".into()).or_default();
+        lid.insert("04A1B2".into(), Action::Stop);
+        lid.insert("04C3D4".into(), Action::Next);
+        let gone = assigned.entry("unplugged".into()).or_default();
+        gone.insert("1".into(), Action::Next);
+        assert_eq!(
+            mappings(&conf, &assigned),
+            vec![
+                Mapping {
+                    source: Source::Input,
+                    device: "/dev/input/event0".into(),
+                    id: "0012345678".into(),
+                    action: Action::Play(format!("spotify:album:{ALBUM}")),
+                    assigned: false,
+                },
+                Mapping {
+                    source: Source::Rfid,
+                    device: "lid".into(),
+                    id: "04A1B2".into(),
+                    action: Action::Pause,
+                    assigned: false,
+                },
+                Mapping {
+                    source: Source::Rfid,
+                    device: "lid".into(),
+                    id: "04C3D4".into(),
+                    action: Action::Next,
+                    assigned: true,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_status_and_cards() {
+        let (addr, _rx, status) = serve("{ bind: \"127.0.0.1:0\" }").await;
+        status.update(|s| s.state = PlayState::Playing);
+        let (code, body) = request(addr, "GET", "/api/status", None, "").await;
+        assert_eq!(code, 200);
+        assert!(body.starts_with(r#"{"state":"playing","#), "{body}");
+
+        let (code, body) = request(addr, "GET", "/api/cards", None, "").await;
+        assert_eq!(code, 200);
+        assert_eq!(
+            body,
+            format!(
+                r#"[{{"source":"input","device":"/dev/input/event0","id":"0012345678","action":"spotify:album:{ALBUM}","assigned":false}},{{"source":"rfid","device":"lid","id":"04A1B2","action":"PAUSE","assigned":false}}]"#
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_reach_the_dispatch_loop() {
+        let (addr, mut rx, _) = serve("{ bind: \"127.0.0.1:0\" }").await;
+        let (code, _) = request(addr, "POST", "/api/control/toggle", None, "").await;
+        assert_eq!(code, 204);
+        assert_eq!(rx.recv().await.unwrap(), control(Action::PlayPause));
+
+        let uri = format!(r#"{{"uri": "https://open.spotify.com/album/{ALBUM}?si=x"}}"#);
+        assert_eq!(request(addr, "POST", "/api/play", None, &uri).await.0, 204);
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            control(Action::Play(format!("spotify:album:{ALBUM}")))
+        );
+
+        let change = r#"{"change": -10}"#;
+        assert_eq!(
+            request(addr, "POST", "/api/volume", None, change).await.0,
+            204
+        );
+        assert_eq!(rx.recv().await.unwrap(), control(Action::Volume(-10)));
+
+        assert_eq!(
+            request(addr, "POST", "/api/cards/04A1B2", None, "").await.0,
+            204
+        );
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            InputEvent::Card {
+                reader: "lid".into(),
+                uid: "04A1B2".into(),
+                uri: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_requests() {
+        let (addr, mut rx, _) = serve("{ bind: \"127.0.0.1:0\" }").await;
+        let (code, body) = request(addr, "POST", "/api/control/paws", None, "").await;
+        assert_eq!(code, 404);
+        assert!(
+            body.starts_with(r#"{"error":"unknown command \"paws\""#),
+            "{body}"
+        );
+        let uri = r#"{"uri": "https://example.com/album/x"}"#;
+        assert_eq!(request(addr, "POST", "/api/play", None, uri).await.0, 400);
+        let change = r#"{"change": 500}"#;
+        assert_eq!(
+            request(addr, "POST", "/api/volume", None, change).await.0,
+            400
+        );
+        assert_eq!(
+            request(addr, "POST", "/api/cards/FFFF", None, "").await.0,
+            404
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_card_several_devices_know() {
+        let dir = tempfile::tempdir().unwrap();
+        let cards = CardStore::new(dir.path());
+        cards
+            .assign("/dev/input/event0", "04A1B2", Action::Stop)
+            .await
+            .unwrap();
+        let (addr, mut rx, _) = serve_with("{ bind: \"127.0.0.1:0\" }", cards).await;
+        let (code, body) = request(addr, "POST", "/api/cards/04A1B2", None, "").await;
+        assert_eq!(code, 409);
+        assert!(
+            body.contains(r#"[\"/dev/input/event0\", \"lid\"]"#),
+            "{body}"
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn tokens_match_only_in_full() {
+        assert!(same_secret("s3cret", "s3cret"));
+        assert!(!same_secret("s3creT", "s3cret"));
+        assert!(!same_secret("s3cre", "s3cret"));
+        assert!(!same_secret("s3crets", "s3cret"));
+        assert!(!same_secret("", "s3cret"));
+    }
+
+    #[tokio::test]
+    async fn a_configured_token_is_required() {
+        let (addr, mut rx, _) = serve("{ bind: \"127.0.0.1:0\", token: s3cret }").await;
+        assert_eq!(request(addr, "GET", "/api/status", None, "").await.0, 401);
+        let wrong = Some("guess");
+        assert_eq!(
+            request(addr, "POST", "/api/control/stop", wrong, "")
+                .await
+                .0,
+            401
+        );
+        assert!(rx.try_recv().is_err());
+
+        let right = Some("s3cret");
+        assert_eq!(request(addr, "GET", "/api/status", right, "").await.0, 200);
+        assert_eq!(
+            request(addr, "POST", "/api/control/stop", right, "")
+                .await
+                .0,
+            204
+        );
+        assert_eq!(rx.recv().await.unwrap(), control(Action::Stop));
+    }
+
+    #[tokio::test]
+    async fn cross_site_posts_are_refused() {
+        let (addr, mut rx, _) = serve("{ bind: \"127.0.0.1:0\" }").await;
+        for cross in [
+            "Origin: http://evil.example\r\n",
+            "Origin: null\r\n",
+            "Sec-Fetch-Site: cross-site\r\n",
+            "Sec-Fetch-Site: same-site\r\nOrigin: http://soundkid\r\n",
+        ] {
+            let (code, _, _) = raw_request(addr, "POST", "/api/control/stop", cross, "").await;
+            assert_eq!(code, 403, "{cross}");
+        }
+        assert!(rx.try_recv().is_err());
+        // Reading is harmless; the browser keeps the answer from the page.
+        let evil = "Origin: http://evil.example\r\n";
+        assert_eq!(
+            raw_request(addr, "GET", "/api/status", evil, "").await.0,
+            200
+        );
+
+        for same in [
+            "Origin: http://soundkid\r\n",
+            "Sec-Fetch-Site: same-origin\r\nOrigin: http://soundkid\r\n",
+        ] {
+            let (code, _, _) = raw_request(addr, "POST", "/api/control/stop", same, "").await;
+            assert_eq!(code, 204, "{same}");
+            assert_eq!(rx.recv().await.unwrap(), control(Action::Stop));
+        }
+    }
+
+    #[tokio::test]
+    async fn browsers_can_send_the_token_as_a_password() {
+        let (addr, _rx, _) = serve("{ bind: \"127.0.0.1:0\", token: s3cret }").await;
+        let (code, head, _) = raw_request(addr, "GET", "/api/status", "", "").await;
+        assert_eq!(code, 401);
+        assert!(
+            head.contains("www-authenticate: Basic realm=\"soundkid\""),
+            "{head}"
+        );
+
+        let basic = |credentials: &str| {
+            format!(
+                "Authorization: Basic {}\r\n",
+                BASE64_STANDARD.encode(credentials)
+            )
+        };
+        let (code, _, _) = raw_request(addr, "GET", "/api/status", &basic("mum:s3cret"), "").await;
+        assert_eq!(code, 200);
+        let (code, _, _) = raw_request(addr, "GET", "/api/status", &basic("s3cret:"), "").await;
+        assert_eq!(code, 401);
+    }
+}
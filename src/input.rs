@@ -1,21 +1,191 @@
-use crate::config::{Action, Config};
+use evdev::KeyCode;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
 
-/// An event produced by one of the input readers (evdev keyboard scan, GPIO
-/// line trigger).
+use crate::config::{Action, Config, OnRemove};
+
+/// An event produced by one of the input readers (evdev keyboard scan or
+/// key, GPIO button gesture, rotary encoder, RFID reader).
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InputEvent {
-    Evdev { device: String, scanned: String },
-    Gpio { chip: String, line: u32 },
+    Evdev {
+        device: String,
+        scanned: String,
+    },
+    /// A key on an evdev device in key mode.
+    Key {
+        device: String,
+        code: KeyCode,
+        press: Press,
+    },
+    Gpio {
+        chip: String,
+        line: u32,
+        press: Press,
+    },
+    /// One detent clockwise on the named `rotary:` encoder.
+    RotaryCw {
+        encoder: String,
+    },
+    /// One detent counter-clockwise on the named `rotary:` encoder.
+    RotaryCcw {
+        encoder: String,
+    },
+    /// The encoder's push switch.
+    RotaryPress {
+        encoder: String,
+        press: Press,
+    },
+    /// A card was put on the named `rfid:` reader.
+    Card {
+        reader: String,
+        uid: String,
+        /// The URI written on the tag, for readers with `ndef: true`.
+        uri: Option<String>,
+    },
+    /// An action asked for over a control interface, named by `client`.
+    Control {
+        client: String,
+        action: Action,
+    },
+    /// The card was taken off the reader again: an `rfid:` reader, or an
+    /// `input:` device with `removal_ms`, whose card is `uid`.
+    CardRemoved {
+        reader: String,
+        uid: String,
+    },
+}
+
+/// How a GPIO button was pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    Long,
+    Double,
+    /// Auto-repeat while a long press is still held.
+    Repeat,
+}
+
+impl InputEvent {
+    /// The configured device (evdev device, GPIO chip, rotary encoder or
+    /// RFID reader) or control client this came from.
+    pub fn source(&self) -> &str {
+        match self {
+            InputEvent::Evdev { device, .. } | InputEvent::Key { device, .. } => device,
+            InputEvent::Gpio { chip, .. } => chip,
+            InputEvent::RotaryCw { encoder }
+            | InputEvent::RotaryCcw { encoder }
+            | InputEvent::RotaryPress { encoder, .. } => encoder,
+            InputEvent::Card { reader, .. } | InputEvent::CardRemoved { reader, .. } => reader,
+            InputEvent::Control { client, .. } => client,
+        }
+    }
+
+    /// The card read, for events from card readers: a scanned ID or a UID.
+    pub fn card(&self) -> Option<&str> {
+        match self {
+            InputEvent::Evdev { scanned, .. } => Some(scanned),
+            InputEvent::Card { uid, .. } | InputEvent::CardRemoved { uid, .. } => Some(uid),
+            _ => None,
+        }
+    }
+
+    /// How the button was pressed, for events that come from buttons.
+    pub fn press(&self) -> Option<Press> {
+        match self {
+            InputEvent::Key { press, .. }
+            | InputEvent::Gpio { press, .. }
+            | InputEvent::RotaryPress { press, .. } => Some(*press),
+            _ => None,
+        }
+    }
+}
+
+/// Suppresses repeats of the same event from the same device within that
+/// device's `debounce_ms` window. Lives in front of action lookup so every
+/// reader gets it without implementing it again.
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    windows: HashMap<String, Duration>,
+    last: HashMap<String, (InputEvent, Instant)>,
+}
+
+impl Debouncer {
+    pub fn new(conf: &Config) -> Self {
+        let input = conf.input.iter().map(|(device, d)| (device, d.debounce_ms));
+        let gpio = conf.gpio.iter().map(|(chip, c)| (chip, c.debounce_ms));
+        let windows = input
+            .chain(gpio)
+            .filter(|(_, ms)| *ms > 0)
+            .map(|(source, ms)| (source.clone(), Duration::from_millis(ms)))
+            .collect();
+        Self {
+            windows,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Returns `false` if `ev` repeats its device's previous event within
+    /// the window. Repeats restart the window, so a card left on the reader
+    /// stays suppressed however long it lies there. Auto-repeats from a held
+    /// button are deliberate and always pass.
+    pub fn accept(&mut self, ev: &InputEvent, now: Instant) -> bool {
+        if ev.press() == Some(Press::Repeat) {
+            return true;
+        }
+        let Some(&window) = self.windows.get(ev.source()) else {
+            return true;
+        };
+        let repeat = self
+            .last
+            .get(ev.source())
+            .is_some_and(|(prev, at)| prev == ev && now.duration_since(*at) < window);
+        self.last.insert(ev.source().to_string(), (ev.clone(), now));
+        !repeat
+    }
+}
+
+/// What taking a card off `reader` does, by its `on_remove`.
+pub fn on_remove(conf: &Config, reader: &str) -> OnRemove {
+    let rfid = conf.rfid.get(reader).map(|r| r.on_remove);
+    let input = || conf.input.get(reader).map(|d| d.on_remove);
+    rfid.or_else(input).unwrap_or_default()
 }
 
 /// Resolve an input event to the configured `Action`, or `None` if no mapping
-/// exists for that event.
-pub fn lookup_action<'a>(conf: &'a Config, ev: &InputEvent) -> Option<&'a Action> {
+/// exists for that event. Control events carry their own.
+pub fn lookup_action<'a>(conf: &'a Config, ev: &'a InputEvent) -> Option<&'a Action> {
     match ev {
         InputEvent::Evdev { device, scanned } => {
-            conf.input.get(device).and_then(|m| m.get(scanned))
+            conf.input.get(device).and_then(|d| d.cards.get(scanned))
         }
-        InputEvent::Gpio { chip, line } => conf.gpio.get(chip).and_then(|m| m.get(line)),
+        InputEvent::Key {
+            device,
+            code,
+            press,
+        } => conf
+            .input
+            .get(device)
+            .and_then(|d| d.keys.get(code))
+            .and_then(|k| k.action(*press)),
+        InputEvent::Gpio { chip, line, press } => conf
+            .gpio
+            .get(chip)
+            .and_then(|c| c.lines.get(line))
+            .and_then(|l| l.action(*press)),
+        InputEvent::RotaryCw { encoder } => conf.rotary.get(encoder).map(|r| &r.cw),
+        InputEvent::RotaryCcw { encoder } => conf.rotary.get(encoder).map(|r| &r.ccw),
+        InputEvent::RotaryPress { encoder, press } => conf
+            .rotary
+            .get(encoder)
+            .and_then(|r| r.switch.as_ref())
+            .and_then(|s| s.button.action(*press)),
+        InputEvent::Card { reader, uid, .. } => {
+            conf.rfid.get(reader).and_then(|r| r.cards.get(uid))
+        }
+        InputEvent::CardRemoved { .. } => None,
+        InputEvent::Control { action, .. } => Some(action),
     }
 }
 
@@ -38,10 +208,29 @@ input:
   /dev/input/event0:
     "12345": "spotify:track:{TRACK}"
     "VOL_UP_CARD": "VOLUME_INCREASE"
+  "Media Remote":
+    keys:
+      KEY_PLAYPAUSE: PLAY_PAUSE
+      KEY_NEXTSONG: {{ short: NEXT, long: STOP }}
 gpio:
   /dev/gpiochip0:
     17: "PAUSE"
     27: "RESUME"
+    22: {{ short: NEXT, long: VOLUME_DECREASE, double: STOP }}
+rotary:
+  knob:
+    chip: /dev/gpiochip0
+    a: 5
+    b: 6
+    cw: VOLUME_INCREASE
+    ccw: VOLUME_DECREASE
+    switch: {{ line: 13, short: PLAY_PAUSE }}
+rfid:
+  lid:
+    driver: mfrc522
+    path: /dev/spidev0.0
+    cards:
+      "04A1B2C3D4E5F6": STOP
 "#
         )
     }
@@ -54,9 +243,14 @@ gpio:
     }
 
     fn gpio(chip: &str, line: u32) -> InputEvent {
+        press(chip, line, Press::Short)
+    }
+
+    fn press(chip: &str, line: u32, press: Press) -> InputEvent {
         InputEvent::Gpio {
             chip: chip.into(),
             line,
+            press,
         }
     }
 
@@ -104,6 +298,112 @@ gpio:
         );
     }
 
+    #[test]
+    fn gpio_press_kinds_map_to_their_actions() {
+        let c = config(&full_yaml());
+        let chip = "/dev/gpiochip0";
+        assert_eq!(
+            lookup_action(&c, &press(chip, 22, Press::Short)),
+            Some(&Action::Next)
+        );
+        assert_eq!(
+            lookup_action(&c, &press(chip, 22, Press::Long)),
+            Some(&Action::VolumeDecrease)
+        );
+        assert_eq!(
+            lookup_action(&c, &press(chip, 22, Press::Repeat)),
+            Some(&Action::VolumeDecrease)
+        );
+        assert_eq!(
+            lookup_action(&c, &press(chip, 22, Press::Double)),
+            Some(&Action::Stop)
+        );
+        assert_eq!(lookup_action(&c, &press(chip, 17, Press::Long)), None);
+    }
+
+    #[test]
+    fn rotary_events_map_to_their_actions() {
+        let c = config(&full_yaml());
+        let knob = || "knob".to_string();
+        assert_eq!(
+            lookup_action(&c, &InputEvent::RotaryCw { encoder: knob() }),
+            Some(&Action::VolumeIncrease)
+        );
+        assert_eq!(
+            lookup_action(&c, &InputEvent::RotaryCcw { encoder: knob() }),
+            Some(&Action::VolumeDecrease)
+        );
+        let press = |press| InputEvent::RotaryPress {
+            encoder: knob(),
+            press,
+        };
+        assert_eq!(
+            lookup_action(&c, &press(Press::Short)),
+            Some(&Action::PlayPause)
+        );
+        assert_eq!(lookup_action(&c, &press(Press::Long)), None);
+        assert_eq!(
+            lookup_action(
+                &c,
+                &InputEvent::RotaryCw {
+                    encoder: "other".into()
+                }
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn rfid_cards_map_by_reader_and_uid() {
+        let c = config(&full_yaml());
+        let card = |reader: &str, uid: &str| InputEvent::Card {
+            reader: reader.into(),
+            uid: uid.into(),
+            uri: None,
+        };
+        assert_eq!(
+            lookup_action(&c, &card("lid", "04A1B2C3D4E5F6")),
+            Some(&Action::Stop)
+        );
+        assert_eq!(lookup_action(&c, &card("lid", "DEADBEEF")), None);
+        assert_eq!(lookup_action(&c, &card("other", "04A1B2C3D4E5F6")), None);
+        let removed = InputEvent::CardRemoved {
+            reader: "lid".into(),
+            uid: "04A1B2C3D4E5F6".into(),
+        };
+        assert_eq!(removed.source(), "lid");
+        assert_eq!(lookup_action(&c, &removed), None);
+    }
+
+    fn key(code: KeyCode, press: Press) -> InputEvent {
+        InputEvent::Key {
+            device: "Media Remote".into(),
+            code,
+            press,
+        }
+    }
+
+    #[test]
+    fn key_events_map_by_key_and_press() {
+        let c = config(&full_yaml());
+        assert_eq!(
+            lookup_action(&c, &key(KeyCode::KEY_PLAYPAUSE, Press::Short)),
+            Some(&Action::PlayPause)
+        );
+        assert_eq!(
+            lookup_action(&c, &key(KeyCode::KEY_NEXTSONG, Press::Long)),
+            Some(&Action::Stop)
+        );
+        assert_eq!(
+            lookup_action(&c, &key(KeyCode::KEY_PLAYPAUSE, Press::Long)),
+            None
+        );
+        assert_eq!(
+            lookup_action(&c, &key(KeyCode::KEY_VOLUMEUP, Press::Short)),
+            None
+        );
+    }
+
     #[test]
     fn gpio_unknown_line_returns_none() {
         let c = config(&full_yaml());
@@ -129,6 +429,85 @@ gpio:
         assert_eq!(lookup_action(&c, &gpio("/dev/input/event0", 12345)), None);
     }
 
+    fn debounce_yaml() -> Config {
+        config(
+            r#"
+alsa: {}
+spotify: {}
+input:
+  /dev/input/event0:
+    debounce_ms: 1000
+    cards: {}
+  /dev/input/event1: {}
+gpio:
+  /dev/gpiochip0:
+    debounce_ms: 200
+    lines: {}
+"#,
+        )
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn debounce_drops_repeat_within_window() {
+        let mut d = Debouncer::new(&debounce_yaml());
+        let t = Instant::now();
+        let ev = evdev("/dev/input/event0", "A");
+        assert!(d.accept(&ev, t));
+        assert!(!d.accept(&ev, t + ms(400)));
+    }
+
+    #[test]
+    fn debounce_window_restarts_with_each_repeat() {
+        let mut d = Debouncer::new(&debounce_yaml());
+        let t = Instant::now();
+        let ev = evdev("/dev/input/event0", "A");
+        assert!(d.accept(&ev, t));
+        assert!(!d.accept(&ev, t + ms(900)));
+        assert!(!d.accept(&ev, t + ms(1800)));
+        assert!(d.accept(&ev, t + ms(2900)));
+    }
+
+    #[test]
+    fn debounce_passes_different_event() {
+        let mut d = Debouncer::new(&debounce_yaml());
+        let t = Instant::now();
+        assert!(d.accept(&evdev("/dev/input/event0", "A"), t));
+        assert!(d.accept(&evdev("/dev/input/event0", "B"), t + ms(10)));
+        assert!(d.accept(&evdev("/dev/input/event0", "A"), t + ms(20)));
+    }
+
+    #[test]
+    fn debounce_is_per_device() {
+        let mut d = Debouncer::new(&debounce_yaml());
+        let t = Instant::now();
+        let ev = evdev("/dev/input/event1", "A");
+        assert!(d.accept(&ev, t));
+        assert!(d.accept(&ev, t + ms(10)), "event1 has no debounce window");
+    }
+
+    #[test]
+    fn debounce_applies_to_gpio() {
+        let mut d = Debouncer::new(&debounce_yaml());
+        let t = Instant::now();
+        let ev = gpio("/dev/gpiochip0", 17);
+        assert!(d.accept(&ev, t));
+        assert!(!d.accept(&ev, t + ms(150)));
+        assert!(d.accept(&ev, t + ms(400)));
+    }
+
+    #[test]
+    fn debounce_never_drops_auto_repeat() {
+        let mut d = Debouncer::new(&debounce_yaml());
+        let t = Instant::now();
+        let ev = press("/dev/gpiochip0", 17, Press::Repeat);
+        assert!(d.accept(&ev, t));
+        assert!(d.accept(&ev, t + ms(50)));
+    }
+
     #[test]
     fn empty_sections_return_none() {
         let c = config(
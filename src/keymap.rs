@@ -0,0 +1,500 @@
+use evdev::KeyCode;
+
+use crate::config::{Charset, Layout};
+
+/// Turns raw evdev key events into characters. HID readers "type" their IDs
+/// like a keyboard using the keymap they think the host has, so upper-case
+/// hex digits arrive as shift + letter and, on AZERTY, every digit needs
+/// shift. The decoder tracks the modifiers and applies the device's layout.
+#[derive(Debug, Clone)]
+pub struct KeyDecoder {
+    charset: Charset,
+    layout: Layout,
+    left_shift: bool,
+    right_shift: bool,
+    altgr: bool,
+}
+
+impl KeyDecoder {
+    pub fn new(charset: Charset, layout: Layout) -> Self {
+        Self {
+            charset,
+            layout,
+            left_shift: false,
+            right_shift: false,
+            altgr: false,
+        }
+    }
+
+    /// Feed one evdev key event (`value` 1 = down, 0 = up, 2 = autorepeat).
+    /// Returns the character for key-down events that produce one; modifier
+    /// changes, releases, repeats and unmapped keys (Enter, Tab, ...) return
+    /// `None`. Ending an ID is the `ScanBuffer`'s business.
+    pub fn feed(&mut self, code: KeyCode, value: i32) -> Option<char> {
+        match code {
+            KeyCode::KEY_LEFTSHIFT => self.left_shift = value != 0,
+            KeyCode::KEY_RIGHTSHIFT => self.right_shift = value != 0,
+            KeyCode::KEY_RIGHTALT => self.altgr = value != 0,
+            _ => {}
+        }
+        // Ignore key-up and key-repeat to avoid duplicates.
+        if value != 1 {
+            return None;
+        }
+        self.char_for(code)
+    }
+
+    fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    fn char_for(&self, code: KeyCode) -> Option<char> {
+        if let Some(digit) = keypad_digit(code) {
+            return Some(digit);
+        }
+        // Third-level characters (`@` on QWERTZ, `€`, ...) never show up in
+        // card IDs; drop them rather than decoding the wrong level.
+        if self.altgr {
+            return None;
+        }
+        let (plain, shifted) = layout_key(self.layout, code)?;
+        match self.charset {
+            // Digits-only mode ignores shift: some readers send it around
+            // every keystroke, and on AZERTY the digits are the shifted level.
+            Charset::Digits => [plain, shifted].into_iter().find(char::is_ascii_digit),
+            Charset::Alphanumeric if self.shift() => Some(shifted),
+            Charset::Alphanumeric => Some(plain),
+        }
+    }
+}
+
+/// `(unshifted, shifted)` character for a key on `layout`. Each layout only
+/// lists the keys where it differs from US.
+fn layout_key(layout: Layout, code: KeyCode) -> Option<(char, char)> {
+    let own = match layout {
+        Layout::Us => None,
+        Layout::Gb => gb_key(code),
+        Layout::De => de_key(code),
+        Layout::Ch => ch_key(code),
+        Layout::Fr => fr_key(code),
+        Layout::Be => be_key(code),
+        Layout::Es => es_key(code),
+        Layout::It => it_key(code),
+    };
+    own.or_else(|| us_key(code))
+}
+
+fn keypad_digit(code: KeyCode) -> Option<char> {
+    Some(match code {
+        KeyCode::KEY_KP0 => '0',
+        KeyCode::KEY_KP1 => '1',
+        KeyCode::KEY_KP2 => '2',
+        KeyCode::KEY_KP3 => '3',
+        KeyCode::KEY_KP4 => '4',
+        KeyCode::KEY_KP5 => '5',
+        KeyCode::KEY_KP6 => '6',
+        KeyCode::KEY_KP7 => '7',
+        KeyCode::KEY_KP8 => '8',
+        KeyCode::KEY_KP9 => '9',
+        _ => return None,
+    })
+}
+
+/// `(unshifted, shifted)` character for a key on a US layout.
+fn us_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_1 => ('1', '!'),
+        KeyCode::KEY_2 => ('2', '@'),
+        KeyCode::KEY_3 => ('3', '#'),
+        KeyCode::KEY_4 => ('4', '$'),
+        KeyCode::KEY_5 => ('5', '%'),
+        KeyCode::KEY_6 => ('6', '^'),
+        KeyCode::KEY_7 => ('7', '&'),
+        KeyCode::KEY_8 => ('8', '*'),
+        KeyCode::KEY_9 => ('9', '('),
+        KeyCode::KEY_0 => ('0', ')'),
+        KeyCode::KEY_MINUS => ('-', '_'),
+        KeyCode::KEY_EQUAL => ('=', '+'),
+        KeyCode::KEY_Q => ('q', 'Q'),
+        KeyCode::KEY_W => ('w', 'W'),
+        KeyCode::KEY_E => ('e', 'E'),
+        KeyCode::KEY_R => ('r', 'R'),
+        KeyCode::KEY_T => ('t', 'T'),
+        KeyCode::KEY_Y => ('y', 'Y'),
+        KeyCode::KEY_U => ('u', 'U'),
+        KeyCode::KEY_I => ('i', 'I'),
+        KeyCode::KEY_O => ('o', 'O'),
+        KeyCode::KEY_P => ('p', 'P'),
+        KeyCode::KEY_A => ('a', 'A'),
+        KeyCode::KEY_S => ('s', 'S'),
+        KeyCode::KEY_D => ('d', 'D'),
+        KeyCode::KEY_F => ('f', 'F'),
+        KeyCode::KEY_G => ('g', 'G'),
+        KeyCode::KEY_H => ('h', 'H'),
+        KeyCode::KEY_J => ('j', 'J'),
+        KeyCode::KEY_K => ('k', 'K'),
+        KeyCode::KEY_L => ('l', 'L'),
+        KeyCode::KEY_Z => ('z', 'Z'),
+        KeyCode::KEY_X => ('x', 'X'),
+        KeyCode::KEY_C => ('c', 'C'),
+        KeyCode::KEY_V => ('v', 'V'),
+        KeyCode::KEY_B => ('b', 'B'),
+        KeyCode::KEY_N => ('n', 'N'),
+        KeyCode::KEY_M => ('m', 'M'),
+        KeyCode::KEY_SEMICOLON => (';', ':'),
+        KeyCode::KEY_APOSTROPHE => ('\'', '"'),
+        KeyCode::KEY_COMMA => (',', '<'),
+        KeyCode::KEY_DOT => ('.', '>'),
+        KeyCode::KEY_SLASH => ('/', '?'),
+        KeyCode::KEY_LEFTBRACE => ('[', '{'),
+        KeyCode::KEY_RIGHTBRACE => (']', '}'),
+        KeyCode::KEY_BACKSLASH => ('\\', '|'),
+        KeyCode::KEY_GRAVE => ('`', '~'),
+        _ => return None,
+    })
+}
+
+/// UK English.
+fn gb_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_2 => ('2', '"'),
+        KeyCode::KEY_3 => ('3', '£'),
+        KeyCode::KEY_APOSTROPHE => ('\'', '@'),
+        KeyCode::KEY_BACKSLASH => ('#', '~'),
+        KeyCode::KEY_GRAVE => ('`', '¬'),
+        _ => return None,
+    })
+}
+
+/// German QWERTZ.
+fn de_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_Y => ('z', 'Z'),
+        KeyCode::KEY_Z => ('y', 'Y'),
+        KeyCode::KEY_2 => ('2', '"'),
+        KeyCode::KEY_3 => ('3', '§'),
+        KeyCode::KEY_6 => ('6', '&'),
+        KeyCode::KEY_7 => ('7', '/'),
+        KeyCode::KEY_8 => ('8', '('),
+        KeyCode::KEY_9 => ('9', ')'),
+        KeyCode::KEY_0 => ('0', '='),
+        KeyCode::KEY_MINUS => ('ß', '?'),
+        KeyCode::KEY_EQUAL => ('´', '`'),
+        KeyCode::KEY_LEFTBRACE => ('ü', 'Ü'),
+        KeyCode::KEY_RIGHTBRACE => ('+', '*'),
+        KeyCode::KEY_SEMICOLON => ('ö', 'Ö'),
+        KeyCode::KEY_APOSTROPHE => ('ä', 'Ä'),
+        KeyCode::KEY_BACKSLASH => ('#', '\''),
+        KeyCode::KEY_GRAVE => ('^', '°'),
+        KeyCode::KEY_COMMA => (',', ';'),
+        KeyCode::KEY_DOT => ('.', ':'),
+        KeyCode::KEY_SLASH => ('-', '_'),
+        _ => return None,
+    })
+}
+
+/// Swiss German QWERTZ.
+fn ch_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_Y => ('z', 'Z'),
+        KeyCode::KEY_Z => ('y', 'Y'),
+        KeyCode::KEY_1 => ('1', '+'),
+        KeyCode::KEY_2 => ('2', '"'),
+        KeyCode::KEY_3 => ('3', '*'),
+        KeyCode::KEY_4 => ('4', 'ç'),
+        KeyCode::KEY_6 => ('6', '&'),
+        KeyCode::KEY_7 => ('7', '/'),
+        KeyCode::KEY_8 => ('8', '('),
+        KeyCode::KEY_9 => ('9', ')'),
+        KeyCode::KEY_0 => ('0', '='),
+        KeyCode::KEY_MINUS => ('\'', '?'),
+        KeyCode::KEY_EQUAL => ('^', '`'),
+        KeyCode::KEY_LEFTBRACE => ('ü', 'è'),
+        KeyCode::KEY_RIGHTBRACE => ('¨', '!'),
+        KeyCode::KEY_SEMICOLON => ('ö', 'é'),
+        KeyCode::KEY_APOSTROPHE => ('ä', 'à'),
+        KeyCode::KEY_BACKSLASH => ('$', '£'),
+        KeyCode::KEY_GRAVE => ('§', '°'),
+        KeyCode::KEY_COMMA => (',', ';'),
+        KeyCode::KEY_DOT => ('.', ':'),
+        KeyCode::KEY_SLASH => ('-', '_'),
+        _ => return None,
+    })
+}
+
+/// French AZERTY. Digits are on the shifted level.
+fn fr_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_Q => ('a', 'A'),
+        KeyCode::KEY_A => ('q', 'Q'),
+        KeyCode::KEY_W => ('z', 'Z'),
+        KeyCode::KEY_Z => ('w', 'W'),
+        KeyCode::KEY_SEMICOLON => ('m', 'M'),
+        KeyCode::KEY_M => (',', '?'),
+        KeyCode::KEY_1 => ('&', '1'),
+        KeyCode::KEY_2 => ('é', '2'),
+        KeyCode::KEY_3 => ('"', '3'),
+        KeyCode::KEY_4 => ('\'', '4'),
+        KeyCode::KEY_5 => ('(', '5'),
+        KeyCode::KEY_6 => ('-', '6'),
+        KeyCode::KEY_7 => ('è', '7'),
+        KeyCode::KEY_8 => ('_', '8'),
+        KeyCode::KEY_9 => ('ç', '9'),
+        KeyCode::KEY_0 => ('à', '0'),
+        KeyCode::KEY_MINUS => (')', '°'),
+        KeyCode::KEY_EQUAL => ('=', '+'),
+        KeyCode::KEY_LEFTBRACE => ('^', '¨'),
+        KeyCode::KEY_RIGHTBRACE => ('$', '£'),
+        KeyCode::KEY_APOSTROPHE => ('ù', '%'),
+        KeyCode::KEY_BACKSLASH => ('*', 'µ'),
+        KeyCode::KEY_GRAVE => ('²', '~'),
+        KeyCode::KEY_COMMA => (';', '.'),
+        KeyCode::KEY_DOT => (':', '/'),
+        KeyCode::KEY_SLASH => ('!', '§'),
+        _ => return None,
+    })
+}
+
+/// Belgian AZERTY. Like French, with a different top row and punctuation.
+fn be_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_6 => ('§', '6'),
+        KeyCode::KEY_8 => ('!', '8'),
+        KeyCode::KEY_EQUAL => ('-', '_'),
+        KeyCode::KEY_BACKSLASH => ('µ', '£'),
+        KeyCode::KEY_GRAVE => ('²', '³'),
+        KeyCode::KEY_SLASH => ('=', '+'),
+        _ => return fr_key(code),
+    })
+}
+
+/// Spanish QWERTY.
+fn es_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_2 => ('2', '"'),
+        KeyCode::KEY_3 => ('3', '·'),
+        KeyCode::KEY_6 => ('6', '&'),
+        KeyCode::KEY_7 => ('7', '/'),
+        KeyCode::KEY_8 => ('8', '('),
+        KeyCode::KEY_9 => ('9', ')'),
+        KeyCode::KEY_0 => ('0', '='),
+        KeyCode::KEY_MINUS => ('\'', '?'),
+        KeyCode::KEY_EQUAL => ('¡', '¿'),
+        KeyCode::KEY_LEFTBRACE => ('`', '^'),
+        KeyCode::KEY_RIGHTBRACE => ('+', '*'),
+        KeyCode::KEY_SEMICOLON => ('ñ', 'Ñ'),
+        KeyCode::KEY_APOSTROPHE => ('´', '¨'),
+        KeyCode::KEY_BACKSLASH => ('ç', 'Ç'),
+        KeyCode::KEY_GRAVE => ('º', 'ª'),
+        KeyCode::KEY_COMMA => (',', ';'),
+        KeyCode::KEY_DOT => ('.', ':'),
+        KeyCode::KEY_SLASH => ('-', '_'),
+        _ => return None,
+    })
+}
+
+/// Italian QWERTY.
+fn it_key(code: KeyCode) -> Option<(char, char)> {
+    Some(match code {
+        KeyCode::KEY_2 => ('2', '"'),
+        KeyCode::KEY_3 => ('3', '£'),
+        KeyCode::KEY_6 => ('6', '&'),
+        KeyCode::KEY_7 => ('7', '/'),
+        KeyCode::KEY_8 => ('8', '('),
+        KeyCode::KEY_9 => ('9', ')'),
+        KeyCode::KEY_0 => ('0', '='),
+        KeyCode::KEY_MINUS => ('\'', '?'),
+        KeyCode::KEY_EQUAL => ('ì', '^'),
+        KeyCode::KEY_LEFTBRACE => ('è', 'é'),
+        KeyCode::KEY_RIGHTBRACE => ('+', '*'),
+        KeyCode::KEY_SEMICOLON => ('ò', 'ç'),
+        KeyCode::KEY_APOSTROPHE => ('à', '°'),
+        KeyCode::KEY_BACKSLASH => ('ù', '§'),
+        KeyCode::KEY_GRAVE => ('\\', '|'),
+        KeyCode::KEY_COMMA => (',', ';'),
+        KeyCode::KEY_DOT => ('.', ':'),
+        KeyCode::KEY_SLASH => ('-', '_'),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOWN: i32 = 1;
+    const UP: i32 = 0;
+    const REPEAT: i32 = 2;
+
+    /// Type `keys` (each pressed and released) and collect the decoded output.
+    fn typed(decoder: &mut KeyDecoder, keys: &[KeyCode]) -> String {
+        let mut out = String::new();
+        for &k in keys {
+            out.extend(decoder.feed(k, DOWN));
+            out.extend(decoder.feed(k, UP));
+        }
+        out
+    }
+
+    #[test]
+    fn digits_row_decodes() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Us);
+        assert_eq!(
+            typed(&mut d, &[KeyCode::KEY_1, KeyCode::KEY_0, KeyCode::KEY_9]),
+            "109"
+        );
+    }
+
+    #[test]
+    fn keypad_digits_decode_and_enter_does_not() {
+        let mut d = KeyDecoder::new(Charset::Digits, Layout::Us);
+        assert_eq!(
+            typed(
+                &mut d,
+                &[KeyCode::KEY_KP4, KeyCode::KEY_KP2, KeyCode::KEY_KPENTER]
+            ),
+            "42"
+        );
+    }
+
+    #[test]
+    fn letters_decode_lower_case_without_shift() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Us);
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_A, KeyCode::KEY_F]), "af");
+    }
+
+    #[test]
+    fn shift_makes_letters_upper_case() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Us);
+        let mut out = String::new();
+        out.extend(d.feed(KeyCode::KEY_LEFTSHIFT, DOWN));
+        out += &typed(&mut d, &[KeyCode::KEY_D, KeyCode::KEY_E]);
+        out.extend(d.feed(KeyCode::KEY_LEFTSHIFT, UP));
+        out += &typed(&mut d, &[KeyCode::KEY_A]);
+        assert_eq!(out, "DEa");
+    }
+
+    #[test]
+    fn either_shift_key_counts() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Us);
+        d.feed(KeyCode::KEY_LEFTSHIFT, DOWN);
+        d.feed(KeyCode::KEY_RIGHTSHIFT, DOWN);
+        d.feed(KeyCode::KEY_LEFTSHIFT, UP);
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_B]), "B");
+        d.feed(KeyCode::KEY_RIGHTSHIFT, UP);
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_B]), "b");
+    }
+
+    #[test]
+    fn digits_charset_drops_letters() {
+        let mut d = KeyDecoder::new(Charset::Digits, Layout::Us);
+        assert_eq!(
+            typed(
+                &mut d,
+                &[
+                    KeyCode::KEY_1,
+                    KeyCode::KEY_A,
+                    KeyCode::KEY_2,
+                    KeyCode::KEY_MINUS
+                ]
+            ),
+            "12"
+        );
+    }
+
+    #[test]
+    fn digits_charset_ignores_shift() {
+        let mut d = KeyDecoder::new(Charset::Digits, Layout::Us);
+        d.feed(KeyCode::KEY_LEFTSHIFT, DOWN);
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_7]), "7");
+    }
+
+    #[test]
+    fn key_repeat_is_ignored() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Us);
+        assert_eq!(d.feed(KeyCode::KEY_5, DOWN), Some('5'));
+        assert_eq!(d.feed(KeyCode::KEY_5, REPEAT), None);
+        assert_eq!(d.feed(KeyCode::KEY_5, UP), None);
+    }
+
+    /// Type `keys` with shift held down.
+    fn shifted(decoder: &mut KeyDecoder, keys: &[KeyCode]) -> String {
+        decoder.feed(KeyCode::KEY_LEFTSHIFT, DOWN);
+        let out = typed(decoder, keys);
+        decoder.feed(KeyCode::KEY_LEFTSHIFT, UP);
+        out
+    }
+
+    #[test]
+    fn us_shifted_digit_is_symbol() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Us);
+        assert_eq!(shifted(&mut d, &[KeyCode::KEY_1]), "!");
+    }
+
+    #[test]
+    fn gb_shifted_two_is_double_quote() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Gb);
+        assert_eq!(shifted(&mut d, &[KeyCode::KEY_2]), "\"");
+    }
+
+    #[test]
+    fn de_swaps_y_and_z() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::De);
+        assert_eq!(
+            typed(&mut d, &[KeyCode::KEY_Y, KeyCode::KEY_Z, KeyCode::KEY_A]),
+            "zya"
+        );
+    }
+
+    #[test]
+    fn fr_digits_are_shifted() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Fr);
+        assert_eq!(
+            shifted(&mut d, &[KeyCode::KEY_1, KeyCode::KEY_2, KeyCode::KEY_0]),
+            "120"
+        );
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_1]), "&");
+    }
+
+    #[test]
+    fn fr_letters_follow_azerty() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Fr);
+        assert_eq!(
+            shifted(
+                &mut d,
+                &[KeyCode::KEY_Q, KeyCode::KEY_W, KeyCode::KEY_SEMICOLON]
+            ),
+            "AZM"
+        );
+    }
+
+    #[test]
+    fn be_falls_back_to_fr() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Be);
+        assert_eq!(shifted(&mut d, &[KeyCode::KEY_8]), "8");
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_8]), "!");
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_Q]), "a");
+    }
+
+    #[test]
+    fn digits_charset_finds_digits_on_azerty_either_way() {
+        let mut d = KeyDecoder::new(Charset::Digits, Layout::Fr);
+        assert_eq!(shifted(&mut d, &[KeyCode::KEY_4]), "4");
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_4]), "4");
+    }
+
+    #[test]
+    fn altgr_level_is_dropped() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::De);
+        d.feed(KeyCode::KEY_RIGHTALT, DOWN);
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_Q]), "");
+        d.feed(KeyCode::KEY_RIGHTALT, UP);
+        assert_eq!(typed(&mut d, &[KeyCode::KEY_Q]), "q");
+    }
+
+    #[test]
+    fn unmapped_key_is_ignored() {
+        let mut d = KeyDecoder::new(Charset::Alphanumeric, Layout::Us);
+        assert_eq!(d.feed(KeyCode::KEY_F1, DOWN), None);
+    }
+}
@@ -1,6 +1,26 @@
+pub mod button;
+pub mod cards;
 pub mod config;
+pub mod control;
+pub mod gpio;
+pub mod hotplug;
+pub mod http;
 pub mod input;
+pub mod keymap;
+pub mod mfrc522;
+pub mod mqtt;
+pub mod ndef;
 pub mod player;
+pub mod pn532;
+pub mod rdm6300;
 pub mod reader;
+pub mod rotary;
 pub mod runtime;
+pub mod scan;
+pub mod selector;
+pub mod serial;
+pub mod state;
+pub mod status;
+pub mod tag;
 pub mod uri;
+pub mod webui;
@@ -0,0 +1,634 @@
+//! NXP MFRC522 (the common "RC522" module) as a native card reader, over
+//! Linux spidev. Only what reading UIDs needs is implemented: wake the card,
+//! run the ISO 14443-3 anticollision/select cascade, and halt it again, so
+//! the next poll can tell whether it is still on the reader. In between,
+//! Type 2 tags can be asked for their NDEF message.
+//!
+//! All chip access goes through [`Transport`], which the tests implement
+//! with a simulated chip and card.
+
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::ndef;
+use crate::tag::{Tag, uid_hex};
+
+/// Register addresses (datasheet section 9).
+mod reg {
+    pub const COMMAND: u8 = 0x01;
+    pub const COM_IRQ: u8 = 0x04;
+    pub const ERROR: u8 = 0x06;
+    pub const FIFO_DATA: u8 = 0x09;
+    pub const FIFO_LEVEL: u8 = 0x0A;
+    pub const BIT_FRAMING: u8 = 0x0D;
+    pub const MODE: u8 = 0x11;
+    pub const TX_CONTROL: u8 = 0x14;
+    pub const TX_ASK: u8 = 0x15;
+    pub const T_MODE: u8 = 0x2A;
+    pub const T_PRESCALER: u8 = 0x2B;
+    pub const T_RELOAD_H: u8 = 0x2C;
+    pub const T_RELOAD_L: u8 = 0x2D;
+    pub const VERSION: u8 = 0x37;
+}
+
+const CMD_IDLE: u8 = 0x00;
+const CMD_TRANSCEIVE: u8 = 0x0C;
+const CMD_SOFT_RESET: u8 = 0x0F;
+const POWER_DOWN: u8 = 0x10;
+
+const IRQ_RX: u8 = 0x20;
+const IRQ_IDLE: u8 = 0x10;
+const IRQ_TIMER: u8 = 0x01;
+const ERR_COLLISION: u8 = 0x08;
+/// BufferOvfl, ParityErr and ProtocolErr.
+const ERR_FATAL: u8 = 0x13;
+
+/// ISO 14443-3 commands.
+const PICC_WUPA: u8 = 0x52;
+const PICC_HLTA: u8 = 0x50;
+const PICC_READ: u8 = 0x30;
+const PICC_CASCADE: [u8; 3] = [0x93, 0x95, 0x97];
+const PICC_CASCADE_TAG: u8 = 0x88;
+const SAK_UID_INCOMPLETE: u8 = 0x04;
+
+/// How often to look at the interrupt register before giving up on a
+/// command. The chip's own timer (about 15 ms) normally ends it much sooner.
+const MAX_IRQ_POLLS: u32 = 2000;
+
+/// Raw register access. The chip also speaks I2C and UART, but only SPI is
+/// wired up here.
+pub trait Transport {
+    fn read(&mut self, reg: u8) -> io::Result<u8>;
+    fn write(&mut self, reg: u8, value: u8) -> io::Result<()>;
+}
+
+#[derive(Debug, Error)]
+pub enum Mfrc522Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(
+        "no MFRC522 answering (version register reads {0:#04x}); check the wiring \
+         and that SPI is enabled"
+    )]
+    NoChip(u8),
+    #[error("MFRC522 did not finish a command")]
+    Stuck,
+    #[error("more than one card in the field")]
+    Collision,
+    #[error("garbled answer from the card: {0}")]
+    Protocol(&'static str),
+}
+
+pub struct Mfrc522<T> {
+    bus: T,
+}
+
+impl<T: Transport> Mfrc522<T> {
+    pub fn new(bus: T) -> Self {
+        Self { bus }
+    }
+
+    /// Reset the chip and set it up for ISO 14443A at 106 kbit/s with the
+    /// antenna on. Returns the chip's version byte.
+    pub fn init(&mut self) -> Result<u8, Mfrc522Error> {
+        let version = self.bus.read(reg::VERSION)?;
+        if version == 0x00 || version == 0xFF {
+            return Err(Mfrc522Error::NoChip(version));
+        }
+        self.bus.write(reg::COMMAND, CMD_SOFT_RESET)?;
+        let mut polls = 0;
+        while self.bus.read(reg::COMMAND)? & POWER_DOWN != 0 {
+            polls += 1;
+            if polls > 50 {
+                return Err(Mfrc522Error::Stuck);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // Timer: 13.56 MHz / (2 * 0xD3E + 1) ≈ 2 kHz, 30 ticks ≈ 15 ms,
+        // started automatically at the end of each transmission.
+        self.bus.write(reg::T_MODE, 0x8D)?;
+        self.bus.write(reg::T_PRESCALER, 0x3E)?;
+        self.bus.write(reg::T_RELOAD_H, 0)?;
+        self.bus.write(reg::T_RELOAD_L, 30)?;
+        // 100% ASK modulation, CRC preset 0x6363.
+        self.bus.write(reg::TX_ASK, 0x40)?;
+        self.bus.write(reg::MODE, 0x3D)?;
+        let tx = self.bus.read(reg::TX_CONTROL)?;
+        self.bus.write(reg::TX_CONTROL, tx | 0x03)?;
+        Ok(version)
+    }
+
+    /// Read the UID of the card on the reader, or `None` if there is none.
+    /// The card is halted afterwards; the next call wakes it up again.
+    pub fn read_uid(&mut self) -> Result<Option<Vec<u8>>, Mfrc522Error> {
+        Ok(self.read_tag(false)?.map(|tag| tag.uid))
+    }
+
+    /// Like [`read_uid`](Self::read_uid), and with `ndef` also read the
+    /// NDEF message if the card is a Type 2 tag.
+    pub fn read_tag(&mut self, ndef: bool) -> Result<Option<Tag>, Mfrc522Error> {
+        let Some((uid, sak)) = self.select()? else {
+            return Ok(None);
+        };
+        let message = if ndef && sak == ndef::TYPE2_SAK {
+            // The UID alone still finds the card's entry under `cards`.
+            ndef::read_type2(|page| self.read_pages(page)).unwrap_or_else(|e| {
+                warn!("could not read NDEF from card {}: {e}", uid_hex(&uid));
+                None
+            })
+        } else {
+            None
+        };
+        self.halt()?;
+        Ok(Some(Tag { uid, ndef: message }))
+    }
+
+    /// Wake up the card in the field and select it. Returns its UID and
+    /// final SAK.
+    fn select(&mut self) -> Result<Option<(Vec<u8>, u8)>, Mfrc522Error> {
+        let Some(atqa) = self.transceive(&[PICC_WUPA], 7)? else {
+            return Ok(None);
+        };
+        if atqa.len() != 2 {
+            return Err(Mfrc522Error::Protocol("ATQA is not two bytes"));
+        }
+        let mut uid = Vec::with_capacity(10);
+        for sel in PICC_CASCADE {
+            // Anticollision: the card answers with (part of) its UID.
+            let Some(part) = self.transceive(&[sel, 0x20], 0)? else {
+                return Ok(None);
+            };
+            if part.len() != 5 || part[..4].iter().fold(0, |a, b| a ^ b) != part[4] {
+                return Err(Mfrc522Error::Protocol("bad UID check byte"));
+            }
+            let mut select = vec![sel, 0x70];
+            select.extend_from_slice(&part);
+            select.extend_from_slice(&crc_a(&select));
+            let Some(sak) = self.transceive(&select, 0)? else {
+                return Ok(None);
+            };
+            if sak.len() != 3 || crc_a(&sak[..1]) != sak[1..] {
+                return Err(Mfrc522Error::Protocol("bad SAK"));
+            }
+            if sak[0] & SAK_UID_INCOMPLETE != 0 {
+                if part[0] != PICC_CASCADE_TAG {
+                    return Err(Mfrc522Error::Protocol("UID continues without cascade tag"));
+                }
+                uid.extend_from_slice(&part[1..4]);
+                continue;
+            }
+            uid.extend_from_slice(&part[..4]);
+            return Ok(Some((uid, sak[0])));
+        }
+        Err(Mfrc522Error::Protocol(
+            "UID longer than three cascade levels",
+        ))
+    }
+
+    /// READ on the selected Type 2 tag: 16 bytes from `page` on.
+    fn read_pages(&mut self, page: u8) -> Result<[u8; 16], Mfrc522Error> {
+        let mut frame = vec![PICC_READ, page];
+        frame.extend_from_slice(&crc_a(&frame));
+        // A refusal is a 4-bit NAK, which lands here as a single byte.
+        let answer = self
+            .transceive(&frame, 0)?
+            .ok_or(Mfrc522Error::Protocol("no answer to READ"))?;
+        if answer.len() != 18 || crc_a(&answer[..16]) != answer[16..] {
+            return Err(Mfrc522Error::Protocol("card refused READ"));
+        }
+        Ok(answer[..16].try_into().expect("16 bytes"))
+    }
+
+    /// Put the selected card to sleep. It doesn't answer a halt.
+    fn halt(&mut self) -> Result<(), Mfrc522Error> {
+        let mut frame = vec![PICC_HLTA, 0x00];
+        frame.extend_from_slice(&crc_a(&frame));
+        self.transceive(&frame, 0).map(|_| ())
+    }
+
+    /// Send `data` to the card and return its answer, or `None` if it
+    /// didn't answer before the timer ran out. `last_bits` is how many bits
+    /// of the last byte to send, 0 for all eight.
+    fn transceive(&mut self, data: &[u8], last_bits: u8) -> Result<Option<Vec<u8>>, Mfrc522Error> {
+        self.bus.write(reg::COMMAND, CMD_IDLE)?;
+        self.bus.write(reg::COM_IRQ, 0x7F)?;
+        self.bus.write(reg::FIFO_LEVEL, 0x80)?;
+        for &b in data {
+            self.bus.write(reg::FIFO_DATA, b)?;
+        }
+        self.bus.write(reg::COMMAND, CMD_TRANSCEIVE)?;
+        self.bus
+            .write(reg::BIT_FRAMING, 0x80 | (last_bits & 0x07))?;
+
+        let mut polls = 0;
+        let irq = loop {
+            let irq = self.bus.read(reg::COM_IRQ)?;
+            if irq & (IRQ_RX | IRQ_IDLE | IRQ_TIMER) != 0 {
+                break irq;
+            }
+            polls += 1;
+            if polls > MAX_IRQ_POLLS {
+                return Err(Mfrc522Error::Stuck);
+            }
+        };
+        self.bus.write(reg::BIT_FRAMING, 0)?;
+        if irq & (IRQ_RX | IRQ_IDLE) == 0 {
+            return Ok(None);
+        }
+        let error = self.bus.read(reg::ERROR)?;
+        if error & ERR_FATAL != 0 {
+            return Err(Mfrc522Error::Protocol(
+                "buffer overflow, parity or protocol error",
+            ));
+        }
+        if error & ERR_COLLISION != 0 {
+            return Err(Mfrc522Error::Collision);
+        }
+        let len = self.bus.read(reg::FIFO_LEVEL)? & 0x7F;
+        if len == 0 {
+            return Ok(None);
+        }
+        // None of the answers we ask for end mid-byte, so the valid bits in
+        // ControlReg don't matter.
+        let answer = (0..len)
+            .map(|_| self.bus.read(reg::FIFO_DATA))
+            .collect::<io::Result<Vec<u8>>>()?;
+        Ok(Some(answer))
+    }
+}
+
+/// CRC_A from ISO 14443-3, low byte first as it goes on the air.
+pub fn crc_a(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0x6363;
+    for &byte in data {
+        let mut b = byte ^ (crc as u8);
+        b ^= b << 4;
+        let b = u16::from(b);
+        crc = (crc >> 8) ^ (b << 8) ^ (b << 3) ^ (b >> 4);
+    }
+    crc.to_le_bytes()
+}
+
+/// `struct spi_ioc_transfer` from include/uapi/linux/spi/spidev.h.
+#[repr(C)]
+#[derive(Default)]
+struct SpiIocTransfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+const _: () = assert!(size_of::<SpiIocTransfer>() == 32);
+
+/// `_IOW('k', 0, struct spi_ioc_transfer[1])`
+const SPI_IOC_MESSAGE_1: u32 =
+    (1 << 30) | ((size_of::<SpiIocTransfer>() as u32) << 16) | (0x6B << 8);
+/// `_IOW('k', 1, __u8)`
+const SPI_IOC_WR_MODE: u32 = (1 << 30) | (1 << 16) | (0x6B << 8) | 1;
+/// `_IOW('k', 3, __u8)`
+const SPI_IOC_WR_BITS_PER_WORD: u32 = (1 << 30) | (1 << 16) | (0x6B << 8) | 3;
+/// `_IOW('k', 4, __u32)`
+const SPI_IOC_WR_MAX_SPEED_HZ: u32 = (1 << 30) | (4 << 16) | (0x6B << 8) | 4;
+
+/// The MFRC522 manages 10 MHz, but jumper wires on a breadboard don't.
+const SPI_SPEED_HZ: u32 = 1_000_000;
+
+/// An MFRC522 on a `/dev/spidev*` device, SPI mode 0.
+pub struct Spidev {
+    fd: OwnedFd,
+}
+
+impl Spidev {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let c_path = CString::new(Path::new(path).as_os_str().as_bytes())?;
+        // SAFETY: plain open(2) of a NUL-terminated path; checked below.
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is owned by nobody else.
+        let spi = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        spi.set(SPI_IOC_WR_MODE, &0u8)?;
+        spi.set(SPI_IOC_WR_BITS_PER_WORD, &8u8)?;
+        spi.set(SPI_IOC_WR_MAX_SPEED_HZ, &SPI_SPEED_HZ)?;
+        Ok(spi)
+    }
+
+    fn set<V>(&self, request: u32, value: &V) -> io::Result<()> {
+        // SAFETY: each request takes a pointer to a value of exactly the
+        // type passed alongside it.
+        let rc = unsafe {
+            libc::ioctl(
+                self.fd.as_raw_fd(),
+                request as libc::Ioctl,
+                value as *const V,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Clock `buf` out and replace it with what came back.
+    fn transfer(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let xfer = SpiIocTransfer {
+            tx_buf: buf.as_ptr() as u64,
+            rx_buf: buf.as_mut_ptr() as u64,
+            len: buf.len() as u32,
+            ..Default::default()
+        };
+        self.set(SPI_IOC_MESSAGE_1, &xfer)
+    }
+}
+
+impl Transport for Spidev {
+    fn read(&mut self, reg: u8) -> io::Result<u8> {
+        let mut buf = [0x80 | ((reg << 1) & 0x7E), 0];
+        self.transfer(&mut buf)?;
+        Ok(buf[1])
+    }
+
+    fn write(&mut self, reg: u8, value: u8) -> io::Result<()> {
+        self.transfer(&mut [(reg << 1) & 0x7E, value])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A card in the field of a [`SimChip`].
+    struct SimCard {
+        uid: Vec<u8>,
+        halted: bool,
+        /// Type 2 memory from page 0 on.
+        memory: Vec<u8>,
+    }
+
+    impl SimCard {
+        /// The UID split into cascade levels, with cascade tags.
+        fn levels(&self) -> Vec<[u8; 4]> {
+            let u = &self.uid;
+            match u.len() {
+                4 => vec![[u[0], u[1], u[2], u[3]]],
+                7 => vec![
+                    [PICC_CASCADE_TAG, u[0], u[1], u[2]],
+                    [u[3], u[4], u[5], u[6]],
+                ],
+                _ => vec![
+                    [PICC_CASCADE_TAG, u[0], u[1], u[2]],
+                    [PICC_CASCADE_TAG, u[3], u[4], u[5]],
+                    [u[6], u[7], u[8], u[9]],
+                ],
+            }
+        }
+
+        fn respond(&mut self, frame: &[u8], last_bits: u8) -> Option<Vec<u8>> {
+            match frame {
+                [PICC_WUPA] if last_bits == 7 => {
+                    self.halted = false;
+                    Some(vec![0x44, 0x00])
+                }
+                _ if self.halted => None,
+                [PICC_HLTA, 0x00, crc @ ..] if crc == crc_a(&[PICC_HLTA, 0x00]) => {
+                    self.halted = true;
+                    None
+                }
+                [sel, 0x20] => {
+                    let level = PICC_CASCADE.iter().position(|s| s == sel)?;
+                    let part = *self.levels().get(level)?;
+                    let bcc = part.iter().fold(0, |a, b| a ^ b);
+                    Some([&part[..], &[bcc]].concat())
+                }
+                [PICC_READ, page, crc @ ..] if crc == crc_a(&[PICC_READ, *page]) => {
+                    let at = *page as usize * 4;
+                    let data = self.memory.get(at..at + 16)?;
+                    Some([data, &crc_a(data)].concat())
+                }
+                [sel, 0x70, rest @ ..] if rest.len() == 7 => {
+                    let (body, crc) = frame.split_at(7);
+                    assert_eq!(crc, crc_a(body), "select sent with a bad CRC");
+                    let level = PICC_CASCADE.iter().position(|s| s == sel)?;
+                    let more = level + 1 < self.levels().len();
+                    let sak = if more { SAK_UID_INCOMPLETE } else { 0x00 };
+                    Some([&[sak][..], &crc_a(&[sak])].concat())
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// Just enough of an MFRC522 to run `Mfrc522` against: registers, the
+    /// FIFO, and Transceive talking to an optional card.
+    struct SimChip {
+        version: u8,
+        regs: [u8; 64],
+        fifo: VecDeque<u8>,
+        card: Option<SimCard>,
+        /// Frames sent to the card, for assertions.
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl SimChip {
+        fn new(card: Option<&[u8]>) -> Self {
+            Self {
+                version: 0x92,
+                regs: [0; 64],
+                fifo: VecDeque::new(),
+                card: card.map(|uid| SimCard {
+                    uid: uid.to_vec(),
+                    halted: false,
+                    memory: Vec::new(),
+                }),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl Transport for &mut SimChip {
+        fn read(&mut self, r: u8) -> io::Result<u8> {
+            Ok(match r {
+                reg::VERSION => self.version,
+                reg::FIFO_DATA => self.fifo.pop_front().unwrap_or(0),
+                reg::FIFO_LEVEL => self.fifo.len() as u8,
+                _ => self.regs[r as usize],
+            })
+        }
+
+        fn write(&mut self, r: u8, value: u8) -> io::Result<()> {
+            match r {
+                reg::COMMAND if value == CMD_SOFT_RESET => self.regs = [0; 64],
+                reg::FIFO_DATA => self.fifo.push_back(value),
+                reg::FIFO_LEVEL if value & 0x80 != 0 => self.fifo.clear(),
+                reg::COM_IRQ => self.regs[r as usize] &= !value,
+                reg::BIT_FRAMING
+                    if value & 0x80 != 0 && self.regs[reg::COMMAND as usize] == CMD_TRANSCEIVE =>
+                {
+                    let frame: Vec<u8> = self.fifo.drain(..).collect();
+                    self.sent.push(frame.clone());
+                    let answer = self
+                        .card
+                        .as_mut()
+                        .and_then(|c| c.respond(&frame, value & 0x07));
+                    self.regs[reg::COM_IRQ as usize] = match answer {
+                        Some(answer) => {
+                            self.fifo.extend(answer);
+                            IRQ_RX | IRQ_IDLE
+                        }
+                        None => IRQ_TIMER,
+                    };
+                }
+                _ => self.regs[r as usize] = value,
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc_a_matches_the_standard() {
+        // HLTA's CRC, as every ISO 14443 trace shows it.
+        assert_eq!(crc_a(&[0x50, 0x00]), [0x57, 0xCD]);
+    }
+
+    #[test]
+    fn init_turns_the_antenna_on() {
+        let mut chip = SimChip::new(None);
+        let version = Mfrc522::new(&mut chip).init().unwrap();
+        assert_eq!(version, 0x92);
+        assert_eq!(chip.regs[reg::TX_CONTROL as usize] & 0x03, 0x03);
+        assert_eq!(chip.regs[reg::T_RELOAD_L as usize], 30);
+    }
+
+    #[test]
+    fn init_without_a_chip_fails() {
+        let mut chip = SimChip::new(None);
+        chip.version = 0xFF;
+        let err = Mfrc522::new(&mut chip).init().unwrap_err();
+        assert!(matches!(err, Mfrc522Error::NoChip(0xFF)), "{err}");
+    }
+
+    #[test]
+    fn no_card_reads_none() {
+        let mut chip = SimChip::new(None);
+        let mut rc = Mfrc522::new(&mut chip);
+        rc.init().unwrap();
+        assert_eq!(rc.read_uid().unwrap(), None);
+    }
+
+    #[test]
+    fn reads_a_four_byte_uid_and_halts_the_card() {
+        let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+        let mut chip = SimChip::new(Some(&uid));
+        let mut rc = Mfrc522::new(&mut chip);
+        rc.init().unwrap();
+        assert_eq!(rc.read_uid().unwrap(), Some(uid.to_vec()));
+        assert!(chip.card.as_ref().unwrap().halted);
+        assert_eq!(chip.sent.last().unwrap()[..2], [PICC_HLTA, 0x00]);
+    }
+
+    #[test]
+    fn reads_a_seven_byte_uid_over_two_cascade_levels() {
+        let uid = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+        let mut chip = SimChip::new(Some(&uid));
+        let mut rc = Mfrc522::new(&mut chip);
+        rc.init().unwrap();
+        assert_eq!(rc.read_uid().unwrap(), Some(uid.to_vec()));
+    }
+
+    #[test]
+    fn reads_a_ten_byte_uid_over_three_cascade_levels() {
+        let uid: Vec<u8> = (1..=10).collect();
+        let mut chip = SimChip::new(Some(&uid));
+        let mut rc = Mfrc522::new(&mut chip);
+        rc.init().unwrap();
+        assert_eq!(rc.read_uid().unwrap(), Some(uid));
+    }
+
+    #[test]
+    fn reads_the_ndef_message_then_halts() {
+        let uid = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+        let mut chip = SimChip::new(Some(&uid));
+        // Pages 0-2 hold UID and lock bytes, page 3 the CC; then an NDEF TLV
+        // with a URI record for "spotify:x" and a terminator.
+        let mut memory = vec![0u8; 12];
+        memory.extend_from_slice(&[0xE1, 0x10, 0x12, 0x00, 0x03, 0x0E, 0xD1, 0x01, 0x0A, b'U']);
+        memory.extend_from_slice(b"\0spotify:x\xFE");
+        memory.resize(64, 0);
+        chip.card.as_mut().unwrap().memory = memory;
+        let mut rc = Mfrc522::new(&mut chip);
+        rc.init().unwrap();
+        let tag = rc.read_tag(true).unwrap().unwrap();
+        assert_eq!(tag.uid, uid);
+        assert_eq!(
+            crate::ndef::message_uri(&tag.ndef.unwrap())
+                .unwrap()
+                .as_deref(),
+            Some("spotify:x")
+        );
+        assert!(chip.card.as_ref().unwrap().halted);
+    }
+
+    #[test]
+    fn a_card_refusing_read_still_reports_its_uid() {
+        let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+        let mut chip = SimChip::new(Some(&uid));
+        let mut rc = Mfrc522::new(&mut chip);
+        rc.init().unwrap();
+        assert_eq!(rc.read_tag(false).unwrap().unwrap().ndef, None);
+        let tag = rc.read_tag(true).unwrap().unwrap();
+        assert_eq!((tag.uid, tag.ndef), (uid.to_vec(), None));
+        // And it was let go, so the next poll wakes it again.
+        assert_eq!(rc.read_uid().unwrap(), Some(uid.to_vec()));
+    }
+
+    #[test]
+    fn a_halted_card_is_woken_on_the_next_poll() {
+        let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+        let mut chip = SimChip::new(Some(&uid));
+        let mut rc = Mfrc522::new(&mut chip);
+        rc.init().unwrap();
+        rc.read_uid().unwrap();
+        assert_eq!(rc.read_uid().unwrap(), Some(uid.to_vec()));
+    }
+
+    #[test]
+    fn collision_is_reported() {
+        let mut chip = SimChip::new(Some(&[1, 2, 3, 4]));
+        Mfrc522::new(&mut chip).init().unwrap();
+        chip.regs[reg::ERROR as usize] = ERR_COLLISION;
+        let err = Mfrc522::new(&mut chip).read_uid().unwrap_err();
+        assert!(matches!(err, Mfrc522Error::Collision), "{err}");
+    }
+
+    #[test]
+    fn spidev_ioctl_numbers_match_the_kernel() {
+        assert_eq!(SPI_IOC_MESSAGE_1, 0x4020_6B00);
+        assert_eq!(SPI_IOC_WR_MODE, 0x4001_6B01);
+        assert_eq!(SPI_IOC_WR_MAX_SPEED_HZ, 0x4004_6B04);
+    }
+
+    #[test]
+    fn missing_spidev_is_not_found() {
+        let err = Spidev::open("/dev/spidev-does-not-exist").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
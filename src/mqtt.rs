@@ -0,0 +1,508 @@
+//! MQTT, mainly for Home Assistant. soundkid publishes what it is up to,
+//! retained, and takes commands on one topic in the control socket's
+//! language:
+//!
+//! ```text
+//! <prefix>/available   online / offline (last will)
+//! <prefix>/state       playing / paused / stopped
+//! <prefix>/uri         what is playing, empty when stopped
+//! <prefix>/volume      percent
+//! <prefix>/last_card   the ID of the last card read
+//! <prefix>/command     <- toggle, next, volume 40, play https://open.spotify.com/..., ...
+//! ```
+//!
+//! Home Assistant has no MQTT media player, so the discovery messages
+//! describe a device with sensors, a volume slider, a URI text field and
+//! buttons instead.
+
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::ConfigMqtt;
+use crate::control::{Request, parse_request};
+use crate::input::InputEvent;
+use crate::status::{PlayState, Status, StatusBoard};
+
+/// What control events from MQTT name as their source.
+const CLIENT: &str = "mqtt";
+
+/// How long to wait before reconnecting to the broker.
+const RECONNECT_PAUSE: Duration = Duration::from_secs(5);
+
+/// Queued messages between the task and the MQTT event loop; enough for
+/// everything sent on connecting.
+const QUEUE: usize = 32;
+
+#[derive(Clone)]
+struct Topics {
+    available: String,
+    state: String,
+    uri: String,
+    volume: String,
+    last_card: String,
+    command: String,
+}
+
+impl Topics {
+    fn new(prefix: &str) -> Self {
+        let topic = |name| format!("{prefix}/{name}");
+        Self {
+            available: topic("available"),
+            state: topic("state"),
+            uri: topic("uri"),
+            volume: topic("volume"),
+            last_card: topic("last_card"),
+            command: topic("command"),
+        }
+    }
+}
+
+/// The retained messages that describe `status`. The volume is left out
+/// until amixer has reported one.
+fn status_messages(topics: &Topics, status: &Status) -> Vec<(String, String)> {
+    let state = match status.state {
+        PlayState::Stopped => "stopped",
+        PlayState::Playing => "playing",
+        PlayState::Paused => "paused",
+    };
+    let mut messages = vec![
+        (topics.state.clone(), state.to_string()),
+        (topics.uri.clone(), status.uri.clone().unwrap_or_default()),
+        (
+            topics.last_card.clone(),
+            status
+                .last_card
+                .as_ref()
+                .map(|c| c.id.clone())
+                .unwrap_or_default(),
+        ),
+    ];
+    if let Some(volume) = status.volume {
+        messages.push((topics.volume.clone(), volume.to_string()));
+    }
+    messages
+}
+
+/// The prefix with anything but letters, digits and `-` made `_`. Names
+/// this soundkid to the broker and to Home Assistant.
+fn node_id(prefix: &str) -> String {
+    prefix
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Home Assistant discovery: one config message per entity, all on one
+/// device.
+fn discovery_messages(conf: &ConfigMqtt) -> Vec<(String, String)> {
+    let topics = Topics::new(&conf.prefix);
+    let node = node_id(&conf.prefix);
+    let device = json!({
+        "identifiers": [node],
+        "name": "soundkid",
+        "model": "soundkid",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let button = |name, press| json!({ "name": name, "command_topic": topics.command, "payload_press": press });
+    let entities: [(&str, &str, Value); 8] = [
+        (
+            "sensor",
+            "state",
+            json!({ "name": "State", "state_topic": topics.state, "icon": "mdi:play-pause" }),
+        ),
+        (
+            "sensor",
+            "last_card",
+            json!({
+                "name": "Last card",
+                "state_topic": topics.last_card,
+                "icon": "mdi:card-account-details",
+            }),
+        ),
+        (
+            "text",
+            "uri",
+            json!({
+                "name": "Playing",
+                "state_topic": topics.uri,
+                "command_topic": topics.command,
+                "command_template": "play {{ value }}",
+                "icon": "mdi:spotify",
+            }),
+        ),
+        (
+            "number",
+            "volume",
+            json!({
+                "name": "Volume",
+                "state_topic": topics.volume,
+                "command_topic": topics.command,
+                "command_template": "volume {{ value | int }}",
+                "min": 0,
+                "max": 100,
+                "unit_of_measurement": "%",
+                "icon": "mdi:volume-high",
+            }),
+        ),
+        ("button", "play_pause", button("Play/pause", "toggle")),
+        ("button", "next", button("Next", "next")),
+        ("button", "previous", button("Previous", "previous")),
+        ("button", "stop", button("Stop", "stop")),
+    ];
+    entities
+        .into_iter()
+        .map(|(component, object, mut config)| {
+            config["unique_id"] = json!(format!("{node}_{object}"));
+            config["availability_topic"] = json!(topics.available);
+            config["device"] = device.clone();
+            let topic = format!(
+                "{}/{component}/{node}/{object}/config",
+                conf.discovery_prefix
+            );
+            (topic, config.to_string())
+        })
+        .collect()
+}
+
+/// Spawn the MQTT client. It keeps reconnecting to the broker for as long
+/// as soundkid runs, so there's nothing to fail at startup.
+pub fn spawn_mqtt(
+    conf: &ConfigMqtt,
+    tx: Sender<InputEvent>,
+    status: StatusBoard,
+) -> JoinHandle<()> {
+    let topics = Topics::new(&conf.prefix);
+    let mut options = MqttOptions::new(node_id(&conf.prefix), &conf.host, conf.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        &topics.available,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &conf.username {
+        options.set_credentials(username, conf.password.clone().unwrap_or_default());
+    }
+    let (client, eventloop) = AsyncClient::new(options, QUEUE);
+    let discovery = discovery_messages(conf);
+    tokio::spawn(run(client, eventloop, topics, discovery, tx, status))
+}
+
+/// Queue retained messages. Only the event loop drains the client's queue,
+/// so this doesn't wait for room.
+fn publish(client: &AsyncClient, messages: Vec<(String, String)>) {
+    for (topic, payload) in messages {
+        if let Err(e) = client.try_publish(&topic, QoS::AtLeastOnce, true, payload) {
+            warn!("MQTT: can't publish to {topic}: {e}");
+        }
+    }
+}
+
+async fn run(
+    client: AsyncClient,
+    mut eventloop: EventLoop,
+    topics: Topics,
+    discovery: Vec<(String, String)>,
+    tx: Sender<InputEvent>,
+    status: StatusBoard,
+) {
+    // Status changes are published from a task of their own: polling the
+    // event loop is best not cut short by a select. Only while connected,
+    // though: nothing drains the queue in between, and stale messages
+    // filling it would crowd out the subscription on reconnecting. The
+    // whole status goes out on connecting anyway.
+    let connected = Arc::new(AtomicBool::new(false));
+    let mut updates = status.subscribe();
+    let forward = {
+        let (client, topics, connected) = (client.clone(), topics.clone(), connected.clone());
+        tokio::spawn(async move {
+            while updates.changed().await.is_ok() {
+                let current = updates.borrow_and_update().clone();
+                if connected.load(Ordering::Relaxed) {
+                    publish(&client, status_messages(&topics, &current));
+                }
+            }
+        })
+    };
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("MQTT connected");
+                connected.store(true, Ordering::Relaxed);
+                if let Err(e) = client.try_subscribe(&topics.command, QoS::AtLeastOnce) {
+                    warn!("MQTT: can't subscribe to {}: {e}", topics.command);
+                }
+                publish(&client, discovery.clone());
+                publish(
+                    &client,
+                    vec![(topics.available.clone(), "online".to_string())],
+                );
+                publish(&client, status_messages(&topics, &status.get()));
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) if message.topic == topics.command => {
+                let line = String::from_utf8_lossy(&message.payload);
+                debug!("MQTT command {line:?}");
+                match parse_request(&line, CLIENT) {
+                    // Waiting for room here would hold up the event loop and
+                    // with it the keep-alives.
+                    Ok(Request::Event(event)) => match tx.try_send(event) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(_)) => {
+                            warn!("MQTT command {line:?} dropped: too many events queued")
+                        }
+                        Err(TrySendError::Closed(_)) => break,
+                    },
+                    Ok(Request::Status) => {
+                        publish(&client, status_messages(&topics, &status.get()))
+                    }
+                    Err(e) => warn!("MQTT command {line:?}: {e}"),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                connected.store(false, Ordering::Relaxed);
+                warn!("MQTT: {e}, reconnecting in {RECONNECT_PAUSE:?}");
+                tokio::time::sleep(RECONNECT_PAUSE).await;
+            }
+        }
+    }
+    forward.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Action;
+    use crate::status::SeenCard;
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, PubAck, Publish, SubAck, SubscribeReasonCode};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+
+    const MAX_PACKET: usize = 64 * 1024;
+
+    fn config(port: u16) -> ConfigMqtt {
+        serde_yaml_ng::from_str(&format!(
+            "{{ host: 127.0.0.1, port: {port}, username: box, password: pw, prefix: kitchen/soundkid }}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn status_goes_out_on_its_topics() {
+        let topics = Topics::new("soundkid");
+        let mut status = Status {
+            state: PlayState::Playing,
+            uri: Some("spotify:album:x".into()),
+            last_card: Some(SeenCard {
+                reader: "lid".into(),
+                id: "04A1".into(),
+            }),
+            ..Status::default()
+        };
+        let message = |topic: &str, payload: &str| (topic.to_string(), payload.to_string());
+        assert_eq!(
+            status_messages(&topics, &status),
+            vec![
+                message("soundkid/state", "playing"),
+                message("soundkid/uri", "spotify:album:x"),
+                message("soundkid/last_card", "04A1"),
+            ]
+        );
+        status.volume = Some(40);
+        assert!(status_messages(&topics, &status).contains(&message("soundkid/volume", "40")));
+    }
+
+    #[test]
+    fn discovery_describes_one_device() {
+        let messages: HashMap<String, Value> = discovery_messages(&config(1883))
+            .into_iter()
+            .map(|(topic, payload)| (topic, serde_json::from_str(&payload).unwrap()))
+            .collect();
+        assert_eq!(messages.len(), 8);
+        let volume = &messages["homeassistant/number/kitchen_soundkid/volume/config"];
+        assert_eq!(volume["unique_id"], "kitchen_soundkid_volume");
+        assert_eq!(volume["command_topic"], "kitchen/soundkid/command");
+        assert_eq!(volume["state_topic"], "kitchen/soundkid/volume");
+        assert_eq!(volume["availability_topic"], "kitchen/soundkid/available");
+        let next = &messages["homeassistant/button/kitchen_soundkid/next/config"];
+        assert_eq!(next["payload_press"], "next");
+        assert!(
+            messages
+                .values()
+                .all(|config| config["device"]["identifiers"][0] == "kitchen_soundkid")
+        );
+    }
+
+    /// Just enough of a broker for one client: it accepts the connection,
+    /// acknowledges what needs acknowledging and hands over every packet.
+    struct Broker {
+        stream: TcpStream,
+        buf: BytesMut,
+    }
+
+    impl Broker {
+        async fn accept(listener: &TcpListener) -> Self {
+            let (stream, _) = listener.accept().await.unwrap();
+            Self {
+                stream,
+                buf: BytesMut::new(),
+            }
+        }
+
+        async fn send(&mut self, packet: Packet) {
+            let mut out = BytesMut::new();
+            packet.write(&mut out, MAX_PACKET).unwrap();
+            self.stream.write_all(&out).await.unwrap();
+        }
+
+        async fn next(&mut self) -> Packet {
+            loop {
+                if let Ok(packet) = Packet::read(&mut self.buf, MAX_PACKET) {
+                    match &packet {
+                        Packet::Connect(_) => {
+                            let ack = ConnAck::new(ConnectReturnCode::Success, false);
+                            self.send(Packet::ConnAck(ack)).await;
+                        }
+                        Packet::Subscribe(s) => {
+                            let granted = SubscribeReasonCode::Success(QoS::AtLeastOnce);
+                            let ack = SubAck::new(s.pkid, vec![granted; s.filters.len()]);
+                            self.send(Packet::SubAck(ack)).await;
+                        }
+                        Packet::Publish(p) if p.pkid != 0 => {
+                            self.send(Packet::PubAck(PubAck::new(p.pkid))).await;
+                        }
+                        Packet::PingReq => self.send(Packet::PingResp).await,
+                        _ => {}
+                    }
+                    return packet;
+                }
+                let n = self.stream.read_buf(&mut self.buf).await.unwrap();
+                assert_ne!(n, 0, "client hung up");
+            }
+        }
+
+        /// Packets until a retained publish to `topic`; its payload.
+        async fn published(&mut self, topic: &str) -> String {
+            loop {
+                if let Packet::Publish(p) = self.next().await {
+                    if p.topic == topic {
+                        assert!(p.retain);
+                        return String::from_utf8(p.payload.to_vec()).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn talks_to_a_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let conf = config(listener.local_addr().unwrap().port());
+        let (tx, mut rx) = mpsc::channel(1);
+        let status = StatusBoard::default();
+        let client = spawn_mqtt(&conf, tx, status.clone());
+        let mut broker = Broker::accept(&listener).await;
+
+        let Packet::Connect(connect) = broker.next().await else {
+            panic!("client must start with CONNECT");
+        };
+        assert_eq!(connect.client_id, "kitchen_soundkid");
+        assert_eq!(connect.login.unwrap().username, "box");
+        let Packet::Subscribe(subscribe) = broker.next().await else {
+            panic!("client must subscribe first");
+        };
+        assert_eq!(subscribe.filters[0].path, "kitchen/soundkid/command");
+        let config = broker
+            .published("homeassistant/button/kitchen_soundkid/play_pause/config")
+            .await;
+        assert!(config.contains(r#""payload_press":"toggle""#), "{config}");
+        assert_eq!(
+            broker.published("kitchen/soundkid/available").await,
+            "online"
+        );
+        assert_eq!(broker.published("kitchen/soundkid/state").await, "stopped");
+
+        status.update(|s| s.state = PlayState::Paused);
+        assert_eq!(broker.published("kitchen/soundkid/state").await, "paused");
+
+        let command = Publish::new("kitchen/soundkid/command", QoS::AtMostOnce, "volume 40");
+        broker.send(Packet::Publish(command)).await;
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            InputEvent::Control {
+                client: CLIENT.into(),
+                action: Action::SetVolume(40),
+            }
+        );
+
+        // With the queue full, commands are dropped rather than holding up
+        // the connection.
+        for command in ["pause", "next", "status"] {
+            let command = Publish::new("kitchen/soundkid/command", QoS::AtMostOnce, command);
+            broker.send(Packet::Publish(command)).await;
+        }
+        assert_eq!(broker.published("kitchen/soundkid/state").await, "paused");
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            InputEvent::Control {
+                client: CLIENT.into(),
+                action: Action::Pause,
+            }
+        );
+        assert!(rx.try_recv().is_err());
+        client.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn comes_back_online_after_an_outage_full_of_changes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let conf = config(listener.local_addr().unwrap().port());
+        let (tx, _rx) = mpsc::channel(8);
+        let status = StatusBoard::default();
+        let client = spawn_mqtt(&conf, tx, status.clone());
+        let mut broker = Broker::accept(&listener).await;
+        assert_eq!(broker.published("kitchen/soundkid/state").await, "stopped");
+
+        drop(broker);
+        // Long enough for the client to notice, short of its reconnect.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        for volume in 0..=QUEUE as u8 + 8 {
+            status.update(|s| s.volume = Some(volume));
+            tokio::task::yield_now().await;
+        }
+
+        let mut broker = Broker::accept(&listener).await;
+        let subscribed = tokio::time::timeout(Duration::from_secs(60), async {
+            loop {
+                if let Packet::Subscribe(s) = broker.next().await {
+                    return s;
+                }
+            }
+        })
+        .await
+        .expect("no SUBSCRIBE after reconnecting");
+        assert_eq!(subscribed.filters[0].path, "kitchen/soundkid/command");
+        assert_eq!(
+            broker.published("kitchen/soundkid/available").await,
+            "online"
+        );
+        assert_eq!(broker.published("kitchen/soundkid/volume").await, "40");
+        client.abort();
+    }
+}
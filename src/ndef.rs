@@ -0,0 +1,362 @@
+//! NDEF, the NFC Forum's format for data on tags: here just enough to find
+//! the URI a tag was written with, on Type 2 tags (NTAG21x, MIFARE
+//! Ultralight) as phone apps write them.
+
+use thiserror::Error;
+
+/// SAK of a Type 2 tag: neither ISO 14443-4 nor MIFARE Classic.
+pub const TYPE2_SAK: u8 = 0x00;
+
+/// Page holding the capability container; user memory follows it.
+const CC_PAGE: u8 = 3;
+const NDEF_MAGIC: u8 = 0xE1;
+
+const TLV_NULL: u8 = 0x00;
+const TLV_NDEF: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xFE;
+
+/// The most user memory a Type 2 tag has (NTAG216).
+const MAX_TYPE2_BYTES: usize = 888;
+
+const ME: u8 = 0x40;
+const CF: u8 = 0x20;
+const SR: u8 = 0x10;
+const IL: u8 = 0x08;
+
+const TNF_WELL_KNOWN: u8 = 0x01;
+const TNF_ABSOLUTE_URI: u8 = 0x03;
+
+/// Abbreviations a URI record's first byte stands for, from the NFC Forum
+/// URI Record Type Definition.
+const URI_PREFIXES: [&str; 36] = [
+    "",
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+    "tel:",
+    "mailto:",
+    "ftp://anonymous:anonymous@",
+    "ftp://ftp.",
+    "ftps://",
+    "sftp://",
+    "smb://",
+    "nfs://",
+    "ftp://",
+    "dav://",
+    "news:",
+    "telnet://",
+    "imap:",
+    "rtsp://",
+    "urn:",
+    "pop:",
+    "sip:",
+    "sips:",
+    "tftp:",
+    "btspp://",
+    "btl2cap://",
+    "btgoep://",
+    "tcpobex://",
+    "irdaobex://",
+    "file://",
+    "urn:epc:id:",
+    "urn:epc:tag:",
+    "urn:epc:pat:",
+    "urn:epc:raw:",
+    "urn:epc:",
+    "urn:nfc:",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NdefError {
+    #[error("NDEF message ends mid-record")]
+    Truncated,
+    #[error("chunked NDEF records are not supported")]
+    Chunked,
+    #[error("unknown URI prefix code {0:#04x}")]
+    UnknownPrefix(u8),
+    #[error("URI is not UTF-8")]
+    NotUtf8,
+}
+
+/// One record of an NDEF message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub tnf: u8,
+    pub kind: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    /// The URI, if this is a URI record.
+    pub fn uri(&self) -> Result<Option<String>, NdefError> {
+        let uri = match (self.tnf, &self.kind[..]) {
+            (TNF_WELL_KNOWN, b"U") => {
+                let Some((&code, rest)) = self.payload.split_first() else {
+                    return Err(NdefError::Truncated);
+                };
+                let prefix = URI_PREFIXES
+                    .get(code as usize)
+                    .ok_or(NdefError::UnknownPrefix(code))?;
+                let rest = std::str::from_utf8(rest).map_err(|_| NdefError::NotUtf8)?;
+                format!("{prefix}{rest}")
+            }
+            (TNF_ABSOLUTE_URI, kind) => std::str::from_utf8(kind)
+                .map_err(|_| NdefError::NotUtf8)?
+                .to_string(),
+            _ => return Ok(None),
+        };
+        Ok(Some(uri))
+    }
+}
+
+/// Split an NDEF message into its records.
+pub fn parse_message(mut bytes: &[u8]) -> Result<Vec<Record>, NdefError> {
+    let mut records = Vec::new();
+    while let Some(&header) = bytes.first() {
+        if header & CF != 0 {
+            return Err(NdefError::Chunked);
+        }
+        take(&mut bytes, 1)?;
+        let type_len = take(&mut bytes, 1)?[0] as usize;
+        let payload_len = if header & SR != 0 {
+            take(&mut bytes, 1)?[0] as usize
+        } else {
+            let len = take(&mut bytes, 4)?;
+            u32::from_be_bytes(len.try_into().expect("four bytes")) as usize
+        };
+        let id_len = if header & IL != 0 {
+            take(&mut bytes, 1)?[0] as usize
+        } else {
+            0
+        };
+        let kind = take(&mut bytes, type_len)?.to_vec();
+        take(&mut bytes, id_len)?;
+        let payload = take(&mut bytes, payload_len)?.to_vec();
+        records.push(Record {
+            tnf: header & 0x07,
+            kind,
+            payload,
+        });
+        if header & ME != 0 {
+            break;
+        }
+    }
+    Ok(records)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], NdefError> {
+    let (head, rest) = bytes.split_at_checked(n).ok_or(NdefError::Truncated)?;
+    *bytes = rest;
+    Ok(head)
+}
+
+/// The first URI in an NDEF message, if it has one.
+pub fn message_uri(message: &[u8]) -> Result<Option<String>, NdefError> {
+    for record in parse_message(message)? {
+        if let Some(uri) = record.uri()? {
+            return Ok(Some(uri));
+        }
+    }
+    Ok(None)
+}
+
+/// How far [`find_message`] got in a Type 2 tag's user memory.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Scan<'a> {
+    Message(&'a [u8]),
+    /// The TLVs end without an NDEF message.
+    NoMessage,
+    /// The memory read so far ends before the message does.
+    NeedMore,
+}
+
+/// Walk the TLV blocks at the start of a Type 2 tag's user memory to its
+/// NDEF message.
+pub fn find_message(memory: &[u8]) -> Scan<'_> {
+    let mut at = 0;
+    loop {
+        let Some(&tag) = memory.get(at) else {
+            return Scan::NeedMore;
+        };
+        match tag {
+            TLV_NULL => {
+                at += 1;
+                continue;
+            }
+            TLV_TERMINATOR => return Scan::NoMessage,
+            _ => {}
+        }
+        let (len, header) = match memory.get(at + 1) {
+            None => return Scan::NeedMore,
+            Some(0xFF) => match memory.get(at + 2..at + 4) {
+                Some(&[hi, lo]) => (u16::from_be_bytes([hi, lo]) as usize, 4),
+                _ => return Scan::NeedMore,
+            },
+            Some(&len) => (len as usize, 2),
+        };
+        let body = at + header..at + header + len;
+        if memory.len() < body.end {
+            return Scan::NeedMore;
+        }
+        if tag == TLV_NDEF {
+            return Scan::Message(&memory[body]);
+        }
+        at = body.end;
+    }
+}
+
+/// Read the NDEF message off a Type 2 tag. `read` returns the 16 bytes
+/// starting at a page, as the tag's READ command does. `None` if the tag
+/// isn't formatted for NDEF or holds no message.
+pub fn read_type2<E>(
+    mut read: impl FnMut(u8) -> Result<[u8; 16], E>,
+) -> Result<Option<Vec<u8>>, E> {
+    let first = read(CC_PAGE)?;
+    if first[0] != NDEF_MAGIC {
+        return Ok(None);
+    }
+    let mut memory = first[4..].to_vec();
+    let mut page = CC_PAGE + 4;
+    loop {
+        match find_message(&memory) {
+            Scan::Message(message) => return Ok(Some(message.to_vec())),
+            Scan::NoMessage => return Ok(None),
+            Scan::NeedMore if memory.len() >= MAX_TYPE2_BYTES => return Ok(None),
+            Scan::NeedMore => {}
+        }
+        memory.extend_from_slice(&read(page)?);
+        page += 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Message begin, which the parser doesn't need.
+    const MB: u8 = 0x80;
+
+    const ALBUM: &str = "1DFixLWuPkv3KT3TnV35m3";
+
+    /// A short URI record, as phone apps write one.
+    fn uri_record(code: u8, rest: &str) -> Vec<u8> {
+        let mut record = vec![MB | ME | SR | TNF_WELL_KNOWN, 1, rest.len() as u8 + 1, b'U'];
+        record.push(code);
+        record.extend_from_slice(rest.as_bytes());
+        record
+    }
+
+    /// Type 2 memory from the capability container on: CC, then a lock
+    /// control TLV, the NDEF TLV and a terminator.
+    fn type2_memory(message: &[u8]) -> Vec<u8> {
+        let mut memory = vec![NDEF_MAGIC, 0x10, 0x3E, 0x00];
+        memory.extend_from_slice(&[0x01, 0x03, 0xA0, 0x0C, 0x34]);
+        memory.extend_from_slice(&[TLV_NDEF, message.len() as u8]);
+        memory.extend_from_slice(message);
+        memory.push(TLV_TERMINATOR);
+        memory.resize(memory.len().next_multiple_of(16) + 16, 0);
+        memory
+    }
+
+    fn read_from(memory: &[u8]) -> impl FnMut(u8) -> Result<[u8; 16], ()> + '_ {
+        |page| {
+            let at = (page - CC_PAGE) as usize * 4;
+            Ok(memory[at..at + 16].try_into().unwrap())
+        }
+    }
+
+    #[test]
+    fn expands_uri_prefixes() {
+        let message = uri_record(0x04, &format!("open.spotify.com/album/{ALBUM}"));
+        assert_eq!(
+            message_uri(&message).unwrap(),
+            Some(format!("https://open.spotify.com/album/{ALBUM}"))
+        );
+        let message = uri_record(0x00, &format!("spotify:album:{ALBUM}"));
+        assert_eq!(
+            message_uri(&message).unwrap(),
+            Some(format!("spotify:album:{ALBUM}"))
+        );
+    }
+
+    #[test]
+    fn finds_the_uri_after_other_records() {
+        // A text record ("en", "hi") before the URI, long-form header.
+        let mut message = vec![MB, 1, 0, 0, 0, 5, b'T', 2, b'e', b'n', b'h', b'i'];
+        let mut uri = uri_record(0x00, "spotify:album:x");
+        uri[0] &= !MB;
+        message.extend(uri);
+        let records = parse_message(&message).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].uri().unwrap(), None);
+        assert_eq!(
+            message_uri(&message).unwrap().as_deref(),
+            Some("spotify:album:x")
+        );
+    }
+
+    #[test]
+    fn absolute_uri_records_carry_the_uri_as_their_type() {
+        let uri = b"spotify:track:x";
+        let mut message = vec![MB | ME | SR | TNF_ABSOLUTE_URI, uri.len() as u8, 0];
+        message.extend_from_slice(uri);
+        assert_eq!(
+            message_uri(&message).unwrap().as_deref(),
+            Some("spotify:track:x")
+        );
+    }
+
+    #[test]
+    fn broken_messages_are_errors() {
+        let message = uri_record(0x04, "open.spotify.com");
+        assert_eq!(
+            message_uri(&message[..message.len() - 1]),
+            Err(NdefError::Truncated)
+        );
+        assert_eq!(
+            message_uri(&uri_record(0x42, "x")),
+            Err(NdefError::UnknownPrefix(0x42))
+        );
+        assert_eq!(
+            message_uri(&[MB | CF | SR | TNF_WELL_KNOWN, 1, 1, b'U', 0]),
+            Err(NdefError::Chunked)
+        );
+        assert_eq!(message_uri(&[]), Ok(None));
+    }
+
+    #[test]
+    fn tlv_scan_skips_nulls_and_other_blocks() {
+        let memory = [
+            0x00, 0x00, 0x01, 0x03, 0xA0, 0x0C, 0x34, 0x03, 0x02, 0xAA, 0xBB,
+        ];
+        assert_eq!(find_message(&memory), Scan::Message(&[0xAA, 0xBB]));
+        assert_eq!(find_message(&memory[..10]), Scan::NeedMore);
+        assert_eq!(find_message(&[0x00, 0xFE]), Scan::NoMessage);
+        let long = [&[0x03, 0xFF, 0x00, 0x01][..], &[0x5A]].concat();
+        assert_eq!(find_message(&long), Scan::Message(&[0x5A]));
+    }
+
+    #[test]
+    fn reads_a_message_spanning_several_reads() {
+        let message = uri_record(0x04, &format!("open.spotify.com/album/{ALBUM}?si=abc"));
+        let memory = type2_memory(&message);
+        let mut reads = 0;
+        let mut read = read_from(&memory);
+        let found = read_type2(|page| {
+            reads += 1;
+            read(page)
+        });
+        assert_eq!(found, Ok(Some(message)));
+        assert_eq!(reads, 5);
+    }
+
+    #[test]
+    fn unformatted_tags_have_no_message() {
+        let memory = vec![0u8; 64];
+        assert_eq!(read_type2(read_from(&memory)), Ok(None));
+        let mut empty = type2_memory(&[]);
+        empty[9] = TLV_TERMINATOR;
+        assert_eq!(read_type2(read_from(&empty)), Ok(None));
+    }
+}
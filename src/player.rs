@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result, anyhow, bail};
 use librespot::core::{
@@ -14,10 +15,14 @@ use librespot::playback::{
 use librespot_oauth::OAuthClientBuilder;
 use thiserror::Error;
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep_until};
 use tracing::{info, warn};
 
-use crate::config::ConfigSpotify;
+use crate::config::{ConfigPlayer, ConfigSpotify, RescanPolicy};
+use crate::state::{SavedPlayback, StateStore};
+use crate::status::{PlayState, StatusBoard};
 
 /// Errors visible across the `PlayerControl` boundary.
 ///
@@ -41,6 +46,19 @@ enum Command {
     Stop,
     Pause,
     Resume,
+    TogglePause,
+    Next,
+    Previous,
+}
+
+/// What actually travels over the channel: a playback command, or the
+/// request to save state and exit. Kept apart so `apply_command` only ever
+/// sees transitions between states.
+#[derive(Debug)]
+enum Message {
+    Command(Command),
+    /// The sender is signalled once the state is on disk.
+    Shutdown(oneshot::Sender<()>),
 }
 
 /// Bounded queue depth: in normal operation we never exceed one or two
@@ -59,12 +77,15 @@ pub trait PlayerControl: Clone + Send + Sync + 'static {
     fn stop(&self) -> impl std::future::Future<Output = Result<(), PlayerError>> + Send;
     fn pause(&self) -> impl std::future::Future<Output = Result<(), PlayerError>> + Send;
     fn resume(&self) -> impl std::future::Future<Output = Result<(), PlayerError>> + Send;
+    fn toggle_pause(&self) -> impl std::future::Future<Output = Result<(), PlayerError>> + Send;
+    fn next(&self) -> impl std::future::Future<Output = Result<(), PlayerError>> + Send;
+    fn previous(&self) -> impl std::future::Future<Output = Result<(), PlayerError>> + Send;
 }
 
 /// Cheap, clonable handle to the background player task.
 #[derive(Clone)]
 pub struct SpotifyPlayer {
-    tx: Sender<Command>,
+    tx: Sender<Message>,
 }
 
 impl PlayerControl for SpotifyPlayer {
@@ -83,6 +104,18 @@ impl PlayerControl for SpotifyPlayer {
     async fn resume(&self) -> Result<(), PlayerError> {
         self.send(Command::Resume).await
     }
+
+    async fn toggle_pause(&self) -> Result<(), PlayerError> {
+        self.send(Command::TogglePause).await
+    }
+
+    async fn next(&self) -> Result<(), PlayerError> {
+        self.send(Command::Next).await
+    }
+
+    async fn previous(&self) -> Result<(), PlayerError> {
+        self.send(Command::Previous).await
+    }
 }
 
 impl SpotifyPlayer {
@@ -90,8 +123,13 @@ impl SpotifyPlayer {
     ///
     /// Returns a clonable `SpotifyPlayer` handle for sending commands and the
     /// `JoinHandle` of the background task. Callers should watch the handle so
-    /// that a panic or unexpected return takes down the process.
-    pub async fn new(spotify: &ConfigSpotify) -> Result<(Self, JoinHandle<()>)> {
+    /// that a panic or unexpected return takes down the process. The task
+    /// keeps the playback part of `status` up to date.
+    pub async fn new(
+        spotify: &ConfigSpotify,
+        conf: &ConfigPlayer,
+        status: StatusBoard,
+    ) -> Result<(Self, JoinHandle<()>)> {
         let mut session_config = SessionConfig::default();
         if let Some(client_id) = &spotify.client_id {
             session_config.client_id = client_id.clone();
@@ -130,13 +168,36 @@ impl SpotifyPlayer {
         );
 
         let (tx, rx) = mpsc::channel(COMMAND_QUEUE_DEPTH);
-        let join = tokio::spawn(player_task(session, player, rx));
+        let store = StateStore::new(&spotify.cache_dir);
+        let join = tokio::spawn(player_task(
+            session,
+            player,
+            rx,
+            conf.clone(),
+            store,
+            status,
+        ));
 
         Ok((Self { tx }, join))
     }
 
+    /// Save the playback position and stop the audio. Unlike `stop`, the
+    /// saved state is kept, so the next start comes up paused where this
+    /// one left off. The player task exits afterwards.
+    pub async fn shutdown(&self) -> Result<(), PlayerError> {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.tx
+            .send(Message::Shutdown(done_tx))
+            .await
+            .map_err(|_| PlayerError::Closed)?;
+        done_rx.await.map_err(|_| PlayerError::Closed)
+    }
+
     async fn send(&self, cmd: Command) -> Result<(), PlayerError> {
-        self.tx.send(cmd).await.map_err(|_| PlayerError::Closed)
+        self.tx
+            .send(Message::Command(cmd))
+            .await
+            .map_err(|_| PlayerError::Closed)
     }
 }
 
@@ -155,78 +216,423 @@ async fn oauth_login(client_id: &str) -> Result<librespot_oauth::OAuthToken> {
         .map_err(|e| anyhow!("failed to obtain Spotify access token: {e}"))
 }
 
+/// The librespot calls playback makes, behind a trait so the state machine
+/// can be tested without an audio device or a Spotify session.
+trait Audio {
+    fn load(&self, track: SpotifyUri, start_playing: bool, position_ms: u32);
+    fn play(&self);
+    fn pause(&self);
+    fn stop(&self);
+    /// Resolves when the loaded track has played to its end.
+    async fn end_of_track(&self);
+}
+
+impl Audio for Player {
+    fn load(&self, track: SpotifyUri, start_playing: bool, position_ms: u32) {
+        Player::load(self, track, start_playing, position_ms);
+    }
+
+    fn play(&self) {
+        Player::play(self);
+    }
+
+    fn pause(&self) {
+        Player::pause(self);
+    }
+
+    fn stop(&self) {
+        Player::stop(self);
+    }
+
+    async fn end_of_track(&self) {
+        self.await_end_of_track().await;
+    }
+}
+
+/// Where a card's URI gets its tracks from, behind a trait for the same
+/// reason as [`Audio`].
+trait Catalog {
+    /// The URI's tracks, see [`resolve_tracks`].
+    async fn tracks(&self, canonical: &str) -> Result<Vec<SpotifyUri>>;
+}
+
+impl Catalog for Session {
+    async fn tracks(&self, canonical: &str) -> Result<Vec<SpotifyUri>> {
+        resolve_tracks(self, canonical).await
+    }
+}
+
 /// Player state machine. `Idle` waits for the next command, `Playing`
 /// races track completion against incoming commands so a new card scan
 /// (or pause/resume/stop) takes effect immediately.
 enum State {
     Idle,
-    Playing { queue: Vec<SpotifyUri>, idx: usize },
+    Playing(Playback),
 }
 
-async fn player_task(session: Session, player: Arc<Player>, mut rx: Receiver<Command>) {
-    let mut state = State::Idle;
+/// The URI currently being played, resolved into its track queue.
+struct Playback {
+    /// Canonical URI as it came in with `Command::Play`, used to recognise a
+    /// re-scan of the same card.
+    uri: String,
+    queue: Vec<SpotifyUri>,
+    idx: usize,
+    /// Whether `queue[idx]` has been handed to librespot yet. Pausing and
+    /// resuming must not load the track again, or it restarts from zero.
+    loaded: bool,
+    /// Paused for `stop_on_idle_minutes`, so the track was unloaded to free
+    /// the audio device. RESUME loads it again at `offset`.
+    released: bool,
+    /// When playback was paused, or `None` while playing. Drives
+    /// `stop_on_idle_minutes`.
+    paused_at: Option<Instant>,
+    /// Position within `queue[idx]` up to `resumed_at`. Tracked with our own
+    /// clock rather than librespot events; a second or so of drift around
+    /// buffering is fine for picking up after a restart.
+    offset: Duration,
+    /// When audio last started, or `None` while paused or not yet loaded.
+    resumed_at: Option<Instant>,
+}
+
+impl Playback {
+    fn new(uri: String, queue: Vec<SpotifyUri>) -> Self {
+        Self {
+            uri,
+            queue,
+            idx: 0,
+            loaded: false,
+            released: false,
+            paused_at: None,
+            offset: Duration::ZERO,
+            resumed_at: None,
+        }
+    }
+
+    /// Rebuild a paused playback from a saved snapshot, so a RESUME picks up
+    /// at the saved position.
+    fn restored(saved: SavedPlayback, queue: Vec<SpotifyUri>) -> Self {
+        Self {
+            idx: saved.idx,
+            paused_at: Some(Instant::now()),
+            offset: Duration::from_millis(saved.position_ms.into()),
+            ..Self::new(saved.uri, queue)
+        }
+    }
+
+    fn next_track(self) -> Self {
+        let idx = self.idx + 1;
+        self.skip_to(idx)
+    }
+
+    /// Start `queue[idx]` from the top, playing even if paused before.
+    fn skip_to(self, idx: usize) -> Self {
+        Self {
+            idx,
+            loaded: false,
+            released: false,
+            paused_at: None,
+            offset: Duration::ZERO,
+            resumed_at: None,
+            ..self
+        }
+    }
+
+    fn load(&mut self, player: &impl Audio) {
+        let track_uri = self.queue[self.idx].clone();
+        info!("Playing {track_uri:?}");
+        let position_ms = self.offset.as_millis().try_into().unwrap_or(u32::MAX);
+        player.load(track_uri, !self.paused(), position_ms);
+        if !self.paused() {
+            self.resumed_at = Some(Instant::now());
+        }
+        self.loaded = true;
+    }
+
+    fn position(&self) -> Duration {
+        self.offset + self.resumed_at.map(|t| t.elapsed()).unwrap_or_default()
+    }
+
+    fn snapshot(&self) -> SavedPlayback {
+        SavedPlayback {
+            uri: self.uri.clone(),
+            idx: self.idx,
+            position_ms: self.position().as_millis().try_into().unwrap_or(u32::MAX),
+        }
+    }
+
+    fn paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    fn pause(&mut self, player: &impl Audio) {
+        player.pause();
+        if let Some(resumed_at) = self.resumed_at.take() {
+            self.offset += resumed_at.elapsed();
+        }
+        self.paused_at.get_or_insert_with(Instant::now);
+    }
+
+    /// Stop librespot, keeping the queue and the position.
+    fn release(&mut self, player: &impl Audio) {
+        player.stop();
+        self.loaded = false;
+        self.released = true;
+    }
+
+    fn resume(&mut self, player: &impl Audio) {
+        if self.released {
+            // Loaded again, playing, by the player task.
+            self.released = false;
+            self.paused_at = None;
+            return;
+        }
+        player.play();
+        self.resumed_at.get_or_insert_with(Instant::now);
+        self.paused_at = None;
+    }
+}
+
+/// When paused playback has been idle for `stop_on_idle_minutes`. None
+/// while playing, once released, when unset, or when so far off it would
+/// overflow.
+fn idle_deadline(conf: &ConfigPlayer, pb: &Playback) -> Option<Instant> {
+    if pb.released {
+        return None;
+    }
+    let idle = Duration::from_secs(conf.stop_on_idle_minutes?.checked_mul(60)?);
+    pb.paused_at?.checked_add(idle)
+}
+
+/// Resolves at the [`idle_deadline`]; never resolves without one.
+async fn idle_timeout(conf: &ConfigPlayer, pb: &Playback) {
+    match idle_deadline(conf, pb) {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn player_task(
+    catalog: impl Catalog,
+    player: Arc<impl Audio>,
+    mut rx: Receiver<Message>,
+    conf: ConfigPlayer,
+    store: StateStore,
+    status: StatusBoard,
+) {
+    let mut state = restore(&catalog, &store).await;
+    publish(&status, &state);
+    let mut saved = match &state {
+        State::Playing(pb) => Some(pb.snapshot()),
+        State::Idle => None,
+    };
     loop {
         state = match state {
             State::Idle => match rx.recv().await {
-                Some(cmd) => apply_command(cmd, &session, &player, State::Idle).await,
+                Some(Message::Shutdown(done)) => {
+                    return shutdown(&*player, &store, &State::Idle, &mut saved, done).await;
+                }
+                Some(Message::Command(cmd)) => {
+                    apply_command(cmd, &catalog, &*player, &conf, State::Idle).await
+                }
                 None => return,
             },
-            State::Playing { queue, idx } if idx >= queue.len() => State::Idle,
-            State::Playing { queue, idx } => {
-                let track_uri = queue[idx].clone();
-                info!("Playing {track_uri:?}");
-                player.load(track_uri, true, 0);
+            State::Playing(pb) if pb.idx >= pb.queue.len() => State::Idle,
+            State::Playing(mut pb) => {
+                if !pb.loaded && !pb.released {
+                    pb.load(&*player);
+                }
                 tokio::select! {
-                    _ = player.await_end_of_track() => {
-                        State::Playing { queue, idx: idx + 1 }
+                    _ = player.end_of_track() => State::Playing(pb.next_track()),
+                    _ = idle_timeout(&conf, &pb) => {
+                        info!("Paused for too long, releasing the audio device");
+                        pb.release(&*player);
+                        State::Playing(pb)
                     }
                     cmd = rx.recv() => match cmd {
-                        Some(cmd) => apply_command(
-                            cmd, &session, &player, State::Playing { queue, idx },
+                        Some(Message::Shutdown(done)) => {
+                            let state = State::Playing(pb);
+                            return shutdown(&*player, &store, &state, &mut saved, done).await;
+                        }
+                        Some(Message::Command(cmd)) => apply_command(
+                            cmd, &catalog, &*player, &conf, State::Playing(pb),
                         ).await,
                         None => return,
                     }
                 }
             }
         };
+        persist(&store, &state, &mut saved).await;
+        publish(&status, &state);
+    }
+}
+
+fn publish(status: &StatusBoard, state: &State) {
+    status.update(|s| match state {
+        State::Playing(pb) if pb.idx < pb.queue.len() => {
+            s.state = if pb.paused() {
+                PlayState::Paused
+            } else {
+                PlayState::Playing
+            };
+            s.uri = Some(pb.uri.clone());
+            s.track = Some(pb.idx);
+        }
+        _ => {
+            s.state = PlayState::Stopped;
+            s.uri = None;
+            s.track = None;
+        }
+    });
+}
+
+/// Pick up the snapshot left by the previous run, paused. Anything that no
+/// longer resolves (card removed from Spotify, album got shorter) starts the
+/// player idle instead.
+async fn restore(catalog: &impl Catalog, store: &StateStore) -> State {
+    let Some(saved) = store.load().await else {
+        return State::Idle;
+    };
+    match catalog.tracks(&saved.uri).await {
+        Ok(queue) if saved.idx < queue.len() => {
+            info!(
+                "Restored {:?} at track {} / {} ms, paused",
+                saved.uri, saved.idx, saved.position_ms
+            );
+            State::Playing(Playback::restored(saved, queue))
+        }
+        Ok(_) => {
+            warn!(
+                "saved track {} of {:?} no longer exists",
+                saved.idx, saved.uri
+            );
+            State::Idle
+        }
+        Err(e) => {
+            warn!("could not restore {:?}: {e:#}", saved.uri);
+            State::Idle
+        }
     }
 }
 
+/// Bring the snapshot on disk in line with `state`. `saved` is what was last
+/// written, so unchanged states (a RESUME right after a PAUSE) cost no I/O.
+async fn persist(store: &StateStore, state: &State, saved: &mut Option<SavedPlayback>) {
+    let current = match state {
+        State::Playing(pb) if pb.idx < pb.queue.len() => Some(pb.snapshot()),
+        _ => None,
+    };
+    if current == *saved {
+        return;
+    }
+    let result = match &current {
+        Some(snapshot) => store.save(snapshot).await,
+        None => store.clear().await,
+    };
+    match result {
+        Ok(()) => *saved = current,
+        Err(e) => warn!("{e}"),
+    }
+}
+
+async fn shutdown(
+    player: &impl Audio,
+    store: &StateStore,
+    state: &State,
+    saved: &mut Option<SavedPlayback>,
+    done: oneshot::Sender<()>,
+) {
+    persist(store, state, saved).await;
+    player.stop();
+    let _ = done.send(());
+}
+
 async fn apply_command(
     cmd: Command,
-    session: &Session,
-    player: &Arc<Player>,
+    catalog: &impl Catalog,
+    player: &impl Audio,
+    conf: &ConfigPlayer,
     state: State,
 ) -> State {
-    match cmd {
-        Command::Play(uri) => {
-            if matches!(state, State::Playing { .. }) {
+    match (cmd, state) {
+        (Command::Play(uri), State::Playing(mut pb))
+            if pb.uri == uri && conf.on_rescan != RescanPolicy::Restart =>
+        {
+            if conf.on_rescan == RescanPolicy::TogglePause {
+                if pb.paused() {
+                    pb.resume(player);
+                } else {
+                    pb.pause(player);
+                }
+            }
+            info!("Re-scan of {uri:?} handled as {:?}", conf.on_rescan);
+            State::Playing(pb)
+        }
+        (Command::Play(uri), state) => {
+            if matches!(state, State::Playing(_)) {
                 player.stop();
             }
-            match resolve_tracks(session, &uri).await {
+            match catalog.tracks(&uri).await {
                 Ok(queue) if queue.is_empty() => {
                     warn!("URI {uri:?} resolved to no playable tracks");
                     State::Idle
                 }
-                Ok(queue) => State::Playing { queue, idx: 0 },
+                Ok(queue) => State::Playing(Playback::new(uri, queue)),
                 Err(e) => {
                     warn!("could not resolve {uri:?}: {e:#}");
                     State::Idle
                 }
             }
         }
-        Command::Stop => {
+        (Command::Stop, state) => {
+            if let State::Playing(pb) = state {
+                info!("Stopping {:?}, dropping its queue", pb.uri);
+            }
             player.stop();
             State::Idle
         }
-        Command::Pause => {
+        (Command::Pause, State::Playing(mut pb)) => {
+            pb.pause(player);
+            State::Playing(pb)
+        }
+        (Command::Resume, State::Playing(mut pb))
+        | (Command::TogglePause, State::Playing(mut pb))
+            if pb.paused() =>
+        {
+            pb.resume(player);
+            State::Playing(pb)
+        }
+        (Command::TogglePause, State::Playing(mut pb)) => {
+            pb.pause(player);
+            State::Playing(pb)
+        }
+        (Command::Pause, State::Idle) => {
             player.pause();
-            state
+            State::Idle
         }
-        Command::Resume => {
+        (Command::Resume, state) => {
             player.play();
             state
         }
+        (Command::TogglePause, State::Idle) => {
+            info!("Nothing is playing, ignoring play/pause toggle");
+            State::Idle
+        }
+        (Command::Next, State::Playing(pb)) => {
+            let pb = pb.next_track();
+            if pb.idx >= pb.queue.len() {
+                info!("Skipped past the last track of {:?}", pb.uri);
+                player.stop();
+            }
+            State::Playing(pb)
+        }
+        (Command::Previous, State::Playing(pb)) => {
+            let idx = pb.idx.saturating_sub(1);
+            State::Playing(pb.skip_to(idx))
+        }
+        (Command::Next | Command::Previous, State::Idle) => {
+            info!("Nothing is playing, ignoring track skip");
+            State::Idle
+        }
     }
 }
 
@@ -256,3 +662,452 @@ async fn resolve_tracks(session: &Session, canonical: &str) -> Result<Vec<Spotif
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::Notify;
+
+    const ALBUM: &str = "spotify:album:7LQhG0xSDjFiKJnziyB3Zj";
+    /// The first track of the album.
+    const TRACK: &str = "spotify:track:6rqhFgbbKwnb9MLmUQDhG6";
+
+    /// What playback asked librespot to do.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Op {
+        Load {
+            track: usize,
+            playing: bool,
+            position_ms: u32,
+        },
+        Play,
+        Pause,
+        Stop,
+    }
+
+    #[derive(Default)]
+    struct FakeAudio {
+        queue: Vec<SpotifyUri>,
+        ops: Mutex<Vec<Op>>,
+        /// Notified to end the track playing.
+        ended: Notify,
+    }
+
+    impl FakeAudio {
+        /// The calls so far, clearing them.
+        fn take_ops(&self) -> Vec<Op> {
+            std::mem::take(&mut self.ops.lock().unwrap())
+        }
+
+        fn push(&self, op: Op) {
+            self.ops.lock().unwrap().push(op);
+        }
+    }
+
+    impl Audio for FakeAudio {
+        fn load(&self, track: SpotifyUri, start_playing: bool, position_ms: u32) {
+            let track = self.queue.iter().position(|t| *t == track).unwrap();
+            self.push(Op::Load {
+                track,
+                playing: start_playing,
+                position_ms,
+            });
+        }
+
+        fn play(&self) {
+            self.push(Op::Play);
+        }
+
+        fn pause(&self) {
+            self.push(Op::Pause);
+        }
+
+        fn stop(&self) {
+            self.push(Op::Stop);
+        }
+
+        async fn end_of_track(&self) {
+            self.ended.notified().await;
+        }
+    }
+
+    /// Knows the album (three tracks) and its first track on its own.
+    struct FakeCatalog;
+
+    impl Catalog for FakeCatalog {
+        async fn tracks(&self, canonical: &str) -> Result<Vec<SpotifyUri>> {
+            match canonical {
+                ALBUM => Ok(queue(3)),
+                TRACK => Ok(queue(1)),
+                other => bail!("no such URI {other:?}"),
+            }
+        }
+    }
+
+    fn queue(tracks: usize) -> Vec<SpotifyUri> {
+        [
+            "6rqhFgbbKwnb9MLmUQDhG6",
+            "7LQhG0xSDjFiKJnziyB3Zj",
+            "1DFixLWuPkv3KT3TnV35m3",
+        ][..tracks]
+            .iter()
+            .map(|id| SpotifyUri::from_uri(&format!("spotify:track:{id}")).unwrap())
+            .collect()
+    }
+
+    fn audio(tracks: usize) -> FakeAudio {
+        FakeAudio {
+            queue: queue(tracks),
+            ..FakeAudio::default()
+        }
+    }
+
+    fn saved(idx: usize, position_ms: u32) -> SavedPlayback {
+        SavedPlayback {
+            uri: ALBUM.into(),
+            idx,
+            position_ms,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn position_counts_only_while_playing() {
+        let audio = audio(3);
+        let mut pb = Playback::new(ALBUM.into(), queue(3));
+        pb.load(&audio);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        pb.pause(&audio);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(pb.snapshot(), saved(0, 10_000));
+        pb.resume(&audio);
+        tokio::time::advance(Duration::from_millis(2_500)).await;
+        // Saved mid-track while playing, as the periodic save does.
+        assert_eq!(pb.snapshot(), saved(0, 12_500));
+        assert_eq!(
+            audio.take_ops(),
+            vec![
+                Op::Load {
+                    track: 0,
+                    playing: true,
+                    position_ms: 0
+                },
+                Op::Pause,
+                Op::Play,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restored_playback_picks_up_at_the_saved_offset() {
+        let audio = audio(3);
+        let mut pb = Playback::restored(saved(2, 61_500), queue(3));
+        assert!(pb.paused());
+        assert_eq!(pb.snapshot(), saved(2, 61_500));
+        // Loaded paused at the offset; RESUME continues from there.
+        pb.load(&audio);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(pb.snapshot(), saved(2, 61_500));
+        pb.resume(&audio);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(pb.snapshot(), saved(2, 66_500));
+        assert_eq!(
+            audio.take_ops(),
+            vec![
+                Op::Load {
+                    track: 2,
+                    playing: false,
+                    position_ms: 61_500
+                },
+                Op::Play,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_next_track_starts_at_zero() {
+        let audio = audio(3);
+        let mut pb = Playback::restored(saved(0, 61_500), queue(3));
+        pb.load(&audio);
+        let pb = pb.next_track();
+        assert!(!pb.paused());
+        assert_eq!(pb.snapshot(), saved(1, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_stop_comes_only_while_paused() {
+        let audio = audio(1);
+        let conf = |minutes| ConfigPlayer {
+            stop_on_idle_minutes: minutes,
+            ..ConfigPlayer::default()
+        };
+        let mut pb = Playback::new(ALBUM.into(), queue(1));
+        pb.load(&audio);
+        assert_eq!(idle_deadline(&conf(Some(5)), &pb), None);
+        pb.pause(&audio);
+        let paused_at = Instant::now();
+        assert_eq!(
+            idle_deadline(&conf(Some(5)), &pb),
+            Some(paused_at + Duration::from_secs(300))
+        );
+        assert_eq!(idle_deadline(&conf(None), &pb), None);
+        // Too far off to represent: never.
+        assert_eq!(idle_deadline(&conf(Some(u64::MAX)), &pb), None);
+        assert_eq!(idle_deadline(&conf(Some(u64::MAX / 60)), &pb), None);
+    }
+
+    fn rescan(on_rescan: RescanPolicy) -> ConfigPlayer {
+        ConfigPlayer {
+            on_rescan,
+            ..ConfigPlayer::default()
+        }
+    }
+
+    /// Track and whether paused, or None when idle or past the end.
+    fn at(state: &State) -> Option<(usize, bool)> {
+        match state {
+            State::Playing(pb) if pb.idx < pb.queue.len() => Some((pb.idx, pb.paused())),
+            _ => None,
+        }
+    }
+
+    /// Run `cmds` from idle through `apply_command`, loading each track the
+    /// way the player task does.
+    async fn apply_all(conf: &ConfigPlayer, audio: &FakeAudio, cmds: Vec<Command>) -> State {
+        let mut state = State::Idle;
+        for cmd in cmds {
+            state = apply_command(cmd, &FakeCatalog, audio, conf, state).await;
+            if let State::Playing(pb) = &mut state {
+                if pb.idx < pb.queue.len() && !pb.loaded && !pb.released {
+                    pb.load(audio);
+                }
+            }
+        }
+        state
+    }
+
+    fn play(uri: &str) -> Command {
+        Command::Play(uri.to_string())
+    }
+
+    #[tokio::test]
+    async fn a_rescan_restarts_by_default() {
+        let audio = audio(3);
+        let conf = rescan(RescanPolicy::Restart);
+        let state = apply_all(&conf, &audio, vec![play(ALBUM), Command::Next, play(ALBUM)]).await;
+        assert_eq!(at(&state), Some((0, false)));
+        let load = |track| Op::Load {
+            track,
+            playing: true,
+            position_ms: 0,
+        };
+        assert_eq!(audio.take_ops(), vec![load(0), load(1), Op::Stop, load(0)]);
+    }
+
+    #[tokio::test]
+    async fn a_rescan_can_toggle_pause() {
+        let audio = audio(3);
+        let conf = rescan(RescanPolicy::TogglePause);
+        let state = apply_all(&conf, &audio, vec![play(ALBUM), play(ALBUM)]).await;
+        assert_eq!(at(&state), Some((0, true)));
+        let state = apply_command(play(ALBUM), &FakeCatalog, &audio, &conf, state).await;
+        assert_eq!(at(&state), Some((0, false)));
+        assert_eq!(audio.take_ops()[1..], [Op::Pause, Op::Play]);
+    }
+
+    #[tokio::test]
+    async fn a_rescan_can_be_ignored() {
+        let audio = audio(3);
+        let conf = rescan(RescanPolicy::Ignore);
+        let state = apply_all(&conf, &audio, vec![play(ALBUM), Command::Next, play(ALBUM)]).await;
+        assert_eq!(at(&state), Some((1, false)));
+        assert_eq!(audio.take_ops().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn another_card_replaces_what_plays() {
+        let audio = audio(3);
+        let conf = rescan(RescanPolicy::Ignore);
+        let state = apply_all(&conf, &audio, vec![play(ALBUM), Command::Next, play(TRACK)]).await;
+        let State::Playing(pb) = &state else {
+            panic!("not playing");
+        };
+        assert_eq!((pb.uri.as_str(), pb.idx, pb.queue.len()), (TRACK, 0, 1));
+        assert_eq!(audio.take_ops()[2], Op::Stop);
+    }
+
+    #[tokio::test]
+    async fn a_uri_that_does_not_resolve_leaves_the_player_idle() {
+        let audio = audio(3);
+        let conf = ConfigPlayer::default();
+        let state = apply_all(
+            &conf,
+            &audio,
+            vec![play(ALBUM), play(TRACK), play("spotify:album:gone")],
+        )
+        .await;
+        assert!(matches!(state, State::Idle));
+    }
+
+    #[tokio::test]
+    async fn next_past_the_last_track_stops() {
+        let audio = audio(3);
+        let conf = ConfigPlayer::default();
+        let cmds = vec![play(ALBUM), Command::Next, Command::Next];
+        let state = apply_all(&conf, &audio, cmds).await;
+        assert_eq!(at(&state), Some((2, false)));
+        audio.take_ops();
+        let state = apply_command(Command::Next, &FakeCatalog, &audio, &conf, state).await;
+        assert_eq!(at(&state), None);
+        assert_eq!(audio.take_ops(), vec![Op::Stop]);
+    }
+
+    #[tokio::test]
+    async fn previous_on_the_first_track_starts_it_again() {
+        let audio = audio(3);
+        let conf = ConfigPlayer::default();
+        let cmds = vec![play(ALBUM), Command::Pause, Command::Previous];
+        let state = apply_all(&conf, &audio, cmds).await;
+        // Playing again, from the top.
+        assert_eq!(at(&state), Some((0, false)));
+        let load = |playing| Op::Load {
+            track: 0,
+            playing,
+            position_ms: 0,
+        };
+        assert_eq!(audio.take_ops(), vec![load(true), Op::Pause, load(true)]);
+    }
+
+    #[tokio::test]
+    async fn skips_and_toggles_while_idle_are_ignored() {
+        let audio = audio(3);
+        let conf = ConfigPlayer::default();
+        let cmds = vec![Command::Next, Command::Previous, Command::TogglePause];
+        let state = apply_all(&conf, &audio, cmds).await;
+        assert!(matches!(state, State::Idle));
+        assert!(audio.take_ops().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_task_releases_the_audio_when_idle_and_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        let status = StatusBoard::default();
+        let audio = Arc::new(audio(3));
+        let conf = ConfigPlayer {
+            stop_on_idle_minutes: Some(1),
+            ..ConfigPlayer::default()
+        };
+        let (tx, rx) = mpsc::channel(COMMAND_QUEUE_DEPTH);
+        let task = tokio::spawn(player_task(
+            FakeCatalog,
+            audio.clone(),
+            rx,
+            conf,
+            store.clone(),
+            status.clone(),
+        ));
+        let mut seen = status.subscribe();
+        tx.send(Message::Command(play(ALBUM))).await.unwrap();
+        seen.wait_for(|s| s.state == PlayState::Playing)
+            .await
+            .unwrap();
+
+        audio.ended.notify_one();
+        seen.wait_for(|s| s.track == Some(1)).await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(3)).await;
+        tx.send(Message::Command(Command::Pause)).await.unwrap();
+        seen.wait_for(|s| s.state == PlayState::Paused)
+            .await
+            .unwrap();
+        audio.take_ops();
+        tokio::time::advance(Duration::from_secs(59)).await;
+        tokio::task::yield_now().await;
+        assert!(audio.take_ops().is_empty());
+        tokio::time::advance(Duration::from_secs(2)).await;
+        while audio.ops.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(audio.take_ops(), vec![Op::Stop]);
+        // The queue and the position are kept, on disk too.
+        assert_eq!(status.get().state, PlayState::Paused);
+        assert_eq!(store.load().await, Some(saved(1, 3_000)));
+
+        tx.send(Message::Command(Command::Resume)).await.unwrap();
+        seen.wait_for(|s| s.state == PlayState::Playing)
+            .await
+            .unwrap();
+        tokio::task::yield_now().await;
+        assert_eq!(
+            audio.take_ops(),
+            vec![Op::Load {
+                track: 1,
+                playing: true,
+                position_ms: 3_000
+            }]
+        );
+
+        drop(tx);
+        task.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_task_saves_the_position_on_transitions_and_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        let status = StatusBoard::default();
+        let (tx, rx) = mpsc::channel(COMMAND_QUEUE_DEPTH);
+        let task = tokio::spawn(player_task(
+            FakeCatalog,
+            Arc::new(audio(3)),
+            rx,
+            ConfigPlayer::default(),
+            store.clone(),
+            status.clone(),
+        ));
+        let mut seen = status.subscribe();
+        tx.send(Message::Command(play(ALBUM))).await.unwrap();
+        seen.wait_for(|s| s.state == PlayState::Playing)
+            .await
+            .unwrap();
+        // Playing on its own writes nothing.
+        tokio::time::advance(Duration::from_secs(20)).await;
+        tokio::task::yield_now().await;
+        assert_eq!(store.load().await, Some(saved(0, 0)));
+
+        tx.send(Message::Command(Command::Pause)).await.unwrap();
+        seen.wait_for(|s| s.state == PlayState::Paused)
+            .await
+            .unwrap();
+        assert_eq!(store.load().await, Some(saved(0, 20_000)));
+
+        tx.send(Message::Command(Command::Resume)).await.unwrap();
+        seen.wait_for(|s| s.state == PlayState::Playing)
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let (done, saved_on_exit) = oneshot::channel();
+        tx.send(Message::Shutdown(done)).await.unwrap();
+        saved_on_exit.await.unwrap();
+        task.await.unwrap();
+        assert_eq!(store.load().await, Some(saved(0, 25_000)));
+    }
+
+    #[tokio::test]
+    async fn persist_saves_changes_and_clears_when_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        let mut last = None;
+        let state = State::Playing(Playback::restored(saved(1, 500), queue(3)));
+        persist(&store, &state, &mut last).await;
+        assert_eq!(store.load().await, Some(saved(1, 500)));
+        assert_eq!(last, Some(saved(1, 500)));
+
+        // Past the end of the queue counts as idle.
+        let done = State::Playing(Playback::restored(saved(3, 0), queue(3)));
+        persist(&store, &done, &mut last).await;
+        assert_eq!(store.load().await, None);
+        assert_eq!(last, None);
+    }
+}
@@ -0,0 +1,637 @@
+//! NXP PN532 NFC controller as a native card reader, on I2C (`/dev/i2c-*`)
+//! or its high-speed UART (a serial tty). It finds ISO 14443A tags (NTAG21x,
+//! MIFARE) with InListPassiveTarget and reports their UID, and on request
+//! the NDEF message of Type 2 tags.
+//!
+//! The frame codec is independent of the bus, and the driver only sees
+//! whole frames through [`Link`], so both are tested against recorded byte
+//! streams.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::ndef;
+use crate::serial::Serial;
+use crate::tag::{Tag, uid_hex};
+
+/// Frame identifier: host to PN532, and PN532 to host.
+const TFI_HOST: u8 = 0xD4;
+const TFI_PN532: u8 = 0xD5;
+/// What an application-level error frame carries instead of a TFI.
+const TFI_ERROR: u8 = 0x7F;
+
+const CMD_GET_FIRMWARE_VERSION: u8 = 0x02;
+const CMD_SAM_CONFIGURATION: u8 = 0x14;
+const CMD_RF_CONFIGURATION: u8 = 0x32;
+const CMD_IN_DATA_EXCHANGE: u8 = 0x40;
+const CMD_IN_LIST_PASSIVE_TARGET: u8 = 0x4A;
+const CMD_IN_RELEASE: u8 = 0x52;
+/// Type 2 tag READ: 16 bytes from a page on.
+const TAG_READ: u8 = 0x30;
+
+/// The PN532 acknowledges a command within about 1 ms; allow for slow buses.
+const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The PN532's fixed 7-bit I2C address.
+const I2C_ADDRESS: u16 = 0x24;
+/// `I2C_SLAVE` from include/uapi/linux/i2c-dev.h.
+const I2C_SLAVE: u32 = 0x0703;
+/// The high-speed UART always runs at this rate.
+const HSU_BAUD: u32 = 115_200;
+
+/// One frame from the PN532.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Ack,
+    Nack,
+    /// The PN532 couldn't make sense of the last command.
+    Error,
+    /// A normal information frame: TFI, then command code and data.
+    Info(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum FrameError {
+    #[error("length checksum mismatch")]
+    Length,
+    #[error("data checksum mismatch")]
+    Data,
+    #[error("extended frames are not supported")]
+    Extended,
+}
+
+/// Wrap `data` (command code and parameters) in a normal information frame.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let len = (data.len() + 1) as u8;
+    let sum = data.iter().fold(TFI_HOST, |a, b| a.wrapping_add(*b));
+    let mut frame = vec![0x00, 0x00, 0xFF, len, len.wrapping_neg(), TFI_HOST];
+    frame.extend_from_slice(data);
+    frame.extend_from_slice(&[sum.wrapping_neg(), 0x00]);
+    frame
+}
+
+/// Cuts frames out of a byte stream. Bytes before a start code (preambles,
+/// postambles, line noise) are skipped, and a frame with a bad checksum is
+/// reported and dropped so the next one can still be found.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The next complete frame, if there is one yet.
+    pub fn next_frame(&mut self) -> Option<Result<Frame, FrameError>> {
+        let start = self.buf.windows(2).position(|w| w == [0x00, 0xFF]);
+        let Some(start) = start else {
+            // Keep a trailing 0x00; it may be the first half of a start code.
+            let keep = usize::from(self.buf.last() == Some(&0x00));
+            self.buf.drain(..self.buf.len() - keep);
+            return None;
+        };
+        self.buf.drain(..start + 2);
+        let (len, lcs) = match self.buf[..] {
+            [len, lcs, ..] => (len, lcs),
+            _ => {
+                self.buf.splice(0..0, [0x00, 0xFF]);
+                return None;
+            }
+        };
+        match (len, lcs) {
+            (0x00, 0xFF) => {
+                self.buf.drain(..2);
+                return Some(Ok(Frame::Ack));
+            }
+            (0xFF, 0x00) => {
+                self.buf.drain(..2);
+                return Some(Ok(Frame::Nack));
+            }
+            (0xFF, 0xFF) => return Some(Err(FrameError::Extended)),
+            _ if len.wrapping_add(lcs) != 0 => return Some(Err(FrameError::Length)),
+            _ => {}
+        }
+        let total = 2 + len as usize + 1;
+        if self.buf.len() < total {
+            self.buf.splice(0..0, [0x00, 0xFF]);
+            return None;
+        }
+        let frame: Vec<u8> = self.buf.drain(..total).collect();
+        let data = &frame[2..total - 1];
+        let dcs = frame[total - 1];
+        if data.iter().fold(dcs, |a, b| a.wrapping_add(*b)) != 0 {
+            return Some(Err(FrameError::Data));
+        }
+        Some(Ok(if data == [TFI_ERROR] {
+            Frame::Error
+        } else {
+            Frame::Info(data.to_vec())
+        }))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Pn532Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("no answer from the PN532; check the wiring and its interface switches")]
+    NoAnswer,
+    #[error("bad frame from the PN532: {0}")]
+    Frame(#[from] FrameError),
+    #[error("PN532 rejected command {0:#04x}")]
+    Rejected(u8),
+    #[error("talking to the card failed with status {0:#04x}")]
+    Card(u8),
+    #[error("unexpected answer from the PN532: {0}")]
+    Protocol(&'static str),
+}
+
+/// Moves whole frames to and from the PN532.
+pub trait Link {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()>;
+    /// The next frame, or `None` if nothing arrives within `timeout`.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>, Pn532Error>;
+}
+
+impl<L: Link + ?Sized> Link for Box<L> {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        (**self).send(frame)
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>, Pn532Error> {
+        (**self).receive(timeout)
+    }
+}
+
+/// A PN532 on its high-speed UART.
+pub struct SerialLink {
+    port: Serial,
+    decoder: Decoder,
+}
+
+impl SerialLink {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut port = Serial::open(path, HSU_BAUD)?;
+        // Wake it from power-down: a 0x55 sync and a long preamble.
+        port.write_all(&[0x55, 0x55, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])?;
+        Ok(Self {
+            port,
+            decoder: Decoder::default(),
+        })
+    }
+}
+
+impl Link for SerialLink {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.port.write_all(frame)
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>, Pn532Error> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 64];
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(Some(frame?));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            let n = self.port.read_timeout(&mut buf, left)?;
+            self.decoder.feed(&buf[..n]);
+        }
+    }
+}
+
+/// A PN532 on an I2C bus. Every read starts with a status byte that says
+/// whether a frame is ready; it's polled until one is.
+pub struct I2cLink {
+    bus: File,
+}
+
+impl I2cLink {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let bus = OpenOptions::new().read(true).write(true).open(path)?;
+        // SAFETY: I2C_SLAVE takes the address as a plain integer argument.
+        let rc = unsafe {
+            libc::ioctl(
+                bus.as_raw_fd(),
+                I2C_SLAVE as libc::Ioctl,
+                libc::c_ulong::from(I2C_ADDRESS),
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { bus })
+    }
+}
+
+impl Link for I2cLink {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.bus.write_all(frame)
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>, Pn532Error> {
+        let deadline = Instant::now() + timeout;
+        let mut status = [0u8; 1];
+        loop {
+            self.bus.read_exact(&mut status)?;
+            if status[0] & 0x01 != 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // The read that follows starts over with the status byte. Whatever
+        // comes after the frame is filler, so one read of the largest frame
+        // we expect is enough.
+        let mut buf = [0u8; 64];
+        self.bus.read_exact(&mut buf)?;
+        let mut decoder = Decoder::default();
+        decoder.feed(&buf[1..]);
+        match decoder.next_frame() {
+            Some(frame) => Ok(Some(frame?)),
+            None => Err(Pn532Error::Protocol("truncated frame")),
+        }
+    }
+}
+
+pub struct Pn532<L> {
+    link: L,
+}
+
+impl<L: Link> Pn532<L> {
+    pub fn new(link: L) -> Self {
+        Self { link }
+    }
+
+    /// Check the chip answers, turn off the secure access module and limit
+    /// how long it searches for a card, so a poll with no card returns.
+    /// Returns the firmware version and revision.
+    pub fn init(&mut self) -> Result<(u8, u8), Pn532Error> {
+        let firmware = self.command(CMD_GET_FIRMWARE_VERSION, &[], RESPONSE_TIMEOUT)?;
+        let [_ic, version, revision, ..] = firmware[..] else {
+            return Err(Pn532Error::Protocol("short firmware version"));
+        };
+        // Normal mode, virtual card timeout 1 s, no IRQ line.
+        self.command(CMD_SAM_CONFIGURATION, &[0x01, 0x14, 0x00], RESPONSE_TIMEOUT)?;
+        // MaxRetries: ATR forever, PSL once, passive activation twice.
+        self.command(
+            CMD_RF_CONFIGURATION,
+            &[0x05, 0xFF, 0x01, 0x02],
+            RESPONSE_TIMEOUT,
+        )?;
+        Ok((version, revision))
+    }
+
+    /// Look for an ISO 14443A card and return its UID, or `None` if there
+    /// is none in the field.
+    pub fn read_uid(&mut self) -> Result<Option<Vec<u8>>, Pn532Error> {
+        Ok(self.read_tag(false)?.map(|tag| tag.uid))
+    }
+
+    /// Like [`read_uid`](Self::read_uid), and with `ndef` also read the
+    /// NDEF message if the card is a Type 2 tag.
+    pub fn read_tag(&mut self, ndef: bool) -> Result<Option<Tag>, Pn532Error> {
+        // One target at 106 kbit/s type A.
+        let found = self.command(CMD_IN_LIST_PASSIVE_TARGET, &[0x01, 0x00], RESPONSE_TIMEOUT)?;
+        // NbTg, Tg, SENS_RES (2), SEL_RES, NFCIDLength, NFCID1...
+        let (sak, uid) = match found[..] {
+            [0, ..] => return Ok(None),
+            [_, _, _, _, sak, len, ref rest @ ..] if rest.len() >= len as usize => {
+                (sak, rest[..len as usize].to_vec())
+            }
+            _ => return Err(Pn532Error::Protocol("short target data")),
+        };
+        let message = if ndef && sak == ndef::TYPE2_SAK {
+            // The UID alone still finds the card's entry under `cards`.
+            ndef::read_type2(|page| self.read_pages(page)).unwrap_or_else(|e| {
+                warn!("could not read NDEF from card {}: {e}", uid_hex(&uid));
+                None
+            })
+        } else {
+            None
+        };
+        // Let it go, so the next poll finds it afresh or not at all.
+        self.command(CMD_IN_RELEASE, &[0x00], RESPONSE_TIMEOUT)?;
+        Ok(Some(Tag { uid, ndef: message }))
+    }
+
+    /// READ on the listed Type 2 tag.
+    fn read_pages(&mut self, page: u8) -> Result<[u8; 16], Pn532Error> {
+        let answer = self.command(
+            CMD_IN_DATA_EXCHANGE,
+            &[0x01, TAG_READ, page],
+            RESPONSE_TIMEOUT,
+        )?;
+        match answer[..] {
+            [0x00, ref data @ ..] if data.len() >= 16 => {
+                Ok(data[..16].try_into().expect("16 bytes"))
+            }
+            [0x00, ..] => Err(Pn532Error::Protocol("short page read")),
+            [status, ..] => Err(Pn532Error::Card(status)),
+            [] => Err(Pn532Error::Protocol("empty data exchange")),
+        }
+    }
+
+    /// Send a command, wait for its ACK and then its response, and return
+    /// the response's parameters.
+    fn command(
+        &mut self,
+        code: u8,
+        params: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Pn532Error> {
+        let mut data = vec![code];
+        data.extend_from_slice(params);
+        self.link.send(&encode(&data))?;
+        match self.link.receive(ACK_TIMEOUT)? {
+            Some(Frame::Ack) => {}
+            Some(Frame::Nack) | Some(Frame::Error) => return Err(Pn532Error::Rejected(code)),
+            Some(Frame::Info(_)) => return Err(Pn532Error::Protocol("response before ACK")),
+            None => return Err(Pn532Error::NoAnswer),
+        }
+        match self.link.receive(timeout)? {
+            Some(Frame::Info(data)) => match data[..] {
+                [TFI_PN532, reply, ref rest @ ..] if reply == code + 1 => Ok(rest.to_vec()),
+                _ => Err(Pn532Error::Protocol("response to another command")),
+            },
+            Some(Frame::Error) => Err(Pn532Error::Rejected(code)),
+            Some(_) => Err(Pn532Error::Protocol("ACK instead of a response")),
+            None => Err(Pn532Error::NoAnswer),
+        }
+    }
+}
+
+/// Open a PN532 by path: `/dev/i2c-*` is I2C, anything else a serial tty.
+pub fn open(path: &str) -> io::Result<Pn532<Box<dyn Link + Send>>> {
+    let link: Box<dyn Link + Send> = if path.starts_with("/dev/i2c-") {
+        Box::new(I2cLink::open(path)?)
+    } else {
+        Box::new(SerialLink::open(path)?)
+    };
+    Ok(Pn532::new(link))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A link replaying recorded PN532 output: each `send` queues the chunks
+    /// the PN532 answered that command with.
+    struct Replay {
+        answers: VecDeque<Vec<Vec<u8>>>,
+        pending: VecDeque<Vec<u8>>,
+        decoder: Decoder,
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl Link for Replay {
+        fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+            self.sent.push(frame.to_vec());
+            self.pending
+                .extend(self.answers.pop_front().unwrap_or_default());
+            Ok(())
+        }
+
+        fn receive(&mut self, _timeout: Duration) -> Result<Option<Frame>, Pn532Error> {
+            loop {
+                if let Some(frame) = self.decoder.next_frame() {
+                    return Ok(Some(frame?));
+                }
+                match self.pending.pop_front() {
+                    Some(chunk) => self.decoder.feed(&chunk),
+                    None => return Ok(None),
+                }
+            }
+        }
+    }
+
+    const ACK: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+    const FIRMWARE: [u8; 13] = [
+        0x00, 0x00, 0xFF, 0x06, 0xFA, 0xD5, 0x03, 0x32, 0x01, 0x06, 0x07, 0xE8, 0x00,
+    ];
+    const SAM_OK: [u8; 9] = [0x00, 0x00, 0xFF, 0x02, 0xFE, 0xD5, 0x15, 0x16, 0x00];
+    const RF_OK: [u8; 9] = [0x00, 0x00, 0xFF, 0x02, 0xFE, 0xD5, 0x33, 0xF8, 0x00];
+    const RELEASED: [u8; 10] = [0x00, 0x00, 0xFF, 0x03, 0xFD, 0xD5, 0x53, 0x00, 0xD8, 0x00];
+    const NO_TARGET: [u8; 10] = [0x00, 0x00, 0xFF, 0x03, 0xFD, 0xD5, 0x4B, 0x00, 0xE0, 0x00];
+    /// An NTAG215 sticker.
+    const NTAG: [u8; 22] = [
+        0x00, 0x00, 0xFF, 0x0F, 0xF1, 0xD5, 0x4B, 0x01, 0x01, 0x00, 0x44, 0x00, 0x07, 0x04, 0xA1,
+        0xB2, 0xC3, 0xD4, 0xE5, 0xF6, 0xCA, 0x00,
+    ];
+    /// A MIFARE Classic 1K card.
+    const CLASSIC: [u8; 19] = [
+        0x00, 0x00, 0xFF, 0x0C, 0xF4, 0xD5, 0x4B, 0x01, 0x01, 0x00, 0x04, 0x08, 0x04, 0xDE, 0xAD,
+        0xBE, 0xEF, 0x96, 0x00,
+    ];
+
+    /// A PN532 response frame carrying `data` (command code and result).
+    fn answer(data: &[u8]) -> Vec<u8> {
+        let mut frame = encode(data);
+        frame[5] = TFI_PN532;
+        let sum = data.iter().fold(TFI_PN532, |a, b| a.wrapping_add(*b));
+        let at = frame.len() - 2;
+        frame[at] = sum.wrapping_neg();
+        frame
+    }
+
+    fn decode_all(bytes: &[u8]) -> Vec<Result<Frame, FrameError>> {
+        let mut d = Decoder::default();
+        d.feed(bytes);
+        std::iter::from_fn(|| d.next_frame()).collect()
+    }
+
+    fn replay(answers: Vec<Vec<&[u8]>>) -> Pn532<Replay> {
+        Pn532::new(Replay {
+            answers: answers
+                .into_iter()
+                .map(|chunks| chunks.into_iter().map(<[u8]>::to_vec).collect())
+                .collect(),
+            pending: VecDeque::new(),
+            decoder: Decoder::default(),
+            sent: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn encodes_commands_like_the_datasheet() {
+        assert_eq!(
+            encode(&[0x02]),
+            [0x00, 0x00, 0xFF, 0x02, 0xFE, 0xD4, 0x02, 0x2A, 0x00]
+        );
+        assert_eq!(
+            encode(&[0x4A, 0x01, 0x00]),
+            [
+                0x00, 0x00, 0xFF, 0x04, 0xFC, 0xD4, 0x4A, 0x01, 0x00, 0xE1, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_ack_then_response() {
+        let stream = [&ACK[..], &FIRMWARE[..]].concat();
+        assert_eq!(
+            decode_all(&stream),
+            vec![
+                Ok(Frame::Ack),
+                Ok(Frame::Info(vec![0xD5, 0x03, 0x32, 0x01, 0x06, 0x07]))
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_a_frame_split_across_reads() {
+        let mut d = Decoder::default();
+        for chunk in NTAG.chunks(4) {
+            assert_eq!(d.next_frame(), None);
+            d.feed(chunk);
+        }
+        assert!(matches!(d.next_frame(), Some(Ok(Frame::Info(_)))));
+        assert_eq!(d.next_frame(), None);
+    }
+
+    #[test]
+    fn skips_noise_before_the_start_code() {
+        let stream = [&[0x55, 0x13, 0xFF, 0x00][..], &ACK[..]].concat();
+        assert_eq!(decode_all(&stream), vec![Ok(Frame::Ack)]);
+    }
+
+    #[test]
+    fn bad_checksum_is_reported_and_the_next_frame_still_found() {
+        let mut corrupt = FIRMWARE;
+        corrupt[8] ^= 0x01;
+        let stream = [&corrupt[..], &ACK[..]].concat();
+        assert_eq!(
+            decode_all(&stream),
+            vec![Err(FrameError::Data), Ok(Frame::Ack)]
+        );
+        let bad_len = [0x00, 0x00, 0xFF, 0x05, 0x05, 0xD5];
+        assert_eq!(decode_all(&bad_len), vec![Err(FrameError::Length)]);
+    }
+
+    #[test]
+    fn decodes_nack_and_error_frames() {
+        let nack = [0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
+        let error = [0x00, 0x00, 0xFF, 0x01, 0xFF, 0x7F, 0x81, 0x00];
+        let stream = [&nack[..], &error[..]].concat();
+        assert_eq!(decode_all(&stream), vec![Ok(Frame::Nack), Ok(Frame::Error)]);
+    }
+
+    #[test]
+    fn init_reads_the_firmware_and_configures() {
+        let mut pn = replay(vec![
+            vec![&ACK, &FIRMWARE],
+            vec![&ACK, &SAM_OK],
+            vec![&ACK, &RF_OK],
+        ]);
+        assert_eq!(pn.init().unwrap(), (0x01, 0x06));
+        assert_eq!(pn.link.sent[1][5..7], [0xD4, CMD_SAM_CONFIGURATION]);
+    }
+
+    #[test]
+    fn reads_an_ntag_uid_and_releases_it() {
+        let mut pn = replay(vec![
+            vec![&ACK[..3], &ACK[3..], &NTAG],
+            vec![&ACK, &RELEASED],
+        ]);
+        assert_eq!(
+            pn.read_uid().unwrap(),
+            Some(vec![0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6])
+        );
+        assert_eq!(pn.link.sent[1][6], CMD_IN_RELEASE);
+    }
+
+    #[test]
+    fn reads_the_ndef_message_of_an_ntag() {
+        // CC and the start of an NDEF TLV holding "spotify:x" in a URI record.
+        let pages = |bytes: &[u8]| {
+            let mut data = vec![CMD_IN_DATA_EXCHANGE + 1, 0x00];
+            data.extend_from_slice(bytes);
+            answer(&data)
+        };
+        let first = pages(&[
+            0xE1, 0x10, 0x3E, 0x00, 0x03, 0x0E, 0xD1, 0x01, 0x0A, b'U', 0x00, b's', b'p', b'o',
+            b't', b'i',
+        ]);
+        let second = pages(&[
+            b'f', b'y', b':', b'x', 0xFE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        let mut pn = replay(vec![
+            vec![&ACK, &NTAG],
+            vec![&ACK, &first],
+            vec![&ACK, &second],
+            vec![&ACK, &RELEASED],
+        ]);
+        let tag = pn.read_tag(true).unwrap().unwrap();
+        let message = tag.ndef.unwrap();
+        assert_eq!(
+            crate::ndef::message_uri(&message).unwrap().as_deref(),
+            Some("spotify:x")
+        );
+        assert_eq!(
+            pn.link.sent[1][6..10],
+            [CMD_IN_DATA_EXCHANGE, 0x01, TAG_READ, 3]
+        );
+        assert_eq!(pn.link.sent[2][9], 7);
+        assert_eq!(pn.link.sent[3][6], CMD_IN_RELEASE);
+    }
+
+    #[test]
+    fn ndef_is_not_read_from_a_classic() {
+        let mut pn = replay(vec![vec![&ACK, &CLASSIC], vec![&ACK, &RELEASED]]);
+        let tag = pn.read_tag(true).unwrap().unwrap();
+        assert_eq!(tag.ndef, None);
+        assert_eq!(pn.link.sent.len(), 2);
+    }
+
+    #[test]
+    fn a_failed_page_read_still_reports_the_uid() {
+        let failed = answer(&[CMD_IN_DATA_EXCHANGE + 1, 0x01]);
+        let mut pn = replay(vec![
+            vec![&ACK, &NTAG],
+            vec![&ACK, &failed],
+            vec![&ACK, &RELEASED],
+        ]);
+        let tag = pn.read_tag(true).unwrap().unwrap();
+        assert!(!tag.uid.is_empty());
+        assert_eq!(tag.ndef, None);
+        assert_eq!(pn.link.sent[2][6], CMD_IN_RELEASE);
+    }
+
+    #[test]
+    fn reads_a_classic_uid() {
+        let mut pn = replay(vec![vec![&ACK, &CLASSIC], vec![&ACK, &RELEASED]]);
+        assert_eq!(pn.read_uid().unwrap(), Some(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+    }
+
+    #[test]
+    fn empty_field_reads_none() {
+        let mut pn = replay(vec![vec![&ACK, &NO_TARGET]]);
+        assert_eq!(pn.read_uid().unwrap(), None);
+        assert_eq!(pn.link.sent.len(), 1);
+    }
+
+    #[test]
+    fn silence_is_no_answer() {
+        let mut pn = replay(vec![]);
+        assert!(matches!(pn.init(), Err(Pn532Error::NoAnswer)));
+    }
+
+    #[test]
+    fn a_response_to_another_command_is_rejected() {
+        let mut pn = replay(vec![vec![&ACK, &SAM_OK]]);
+        assert!(matches!(pn.read_uid(), Err(Pn532Error::Protocol(_))));
+    }
+}
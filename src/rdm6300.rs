@@ -0,0 +1,214 @@
+//! RDM6300 125 kHz reader for EM4100 tags, on a UART. It sends a frame
+//! whenever a tag is in its field, and keeps sending while the tag stays:
+//!
+//! ```text
+//! 0x02 | 10 hex digits: version + 32-bit tag number | 2 hex digits: XOR of the 5 bytes | 0x03
+//! ```
+
+use std::io;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::serial::Serial;
+
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const FRAME_LEN: usize = 14;
+
+/// The RDM6300's UART speed out of the box.
+pub const DEFAULT_BAUD: u32 = 9600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum FrameError {
+    #[error("frame does not end in ETX")]
+    Framing,
+    #[error("frame is not hex")]
+    NotHex,
+    #[error("checksum mismatch")]
+    Checksum,
+}
+
+/// Cuts tag IDs out of the reader's byte stream.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The five ID bytes of the next complete frame, if there is one yet.
+    /// A damaged frame is reported and skipped.
+    pub fn next_tag(&mut self) -> Option<Result<[u8; 5], FrameError>> {
+        let Some(start) = self.buf.iter().position(|&b| b == STX) else {
+            self.buf.clear();
+            return None;
+        };
+        self.buf.drain(..start);
+        if self.buf.len() < FRAME_LEN {
+            return None;
+        }
+        if self.buf[FRAME_LEN - 1] != ETX {
+            // Resync on the next STX.
+            self.buf.remove(0);
+            return Some(Err(FrameError::Framing));
+        }
+        let frame: Vec<u8> = self.buf.drain(..FRAME_LEN).collect();
+        let mut bytes = [0u8; 6];
+        for (byte, pair) in bytes.iter_mut().zip(frame[1..13].chunks(2)) {
+            *byte = match std::str::from_utf8(pair)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(b) => b,
+                None => return Some(Err(FrameError::NotHex)),
+            };
+        }
+        let (id, checksum) = bytes.split_at(5);
+        if id.iter().fold(0, |a, b| a ^ b) != checksum[0] {
+            return Some(Err(FrameError::Checksum));
+        }
+        Some(Ok(id.try_into().expect("split at 5")))
+    }
+}
+
+pub struct Rdm6300 {
+    port: Serial,
+    decoder: Decoder,
+}
+
+impl Rdm6300 {
+    pub fn open(path: &str, baud: u32) -> io::Result<Self> {
+        Ok(Self {
+            port: Serial::open(path, baud)?,
+            decoder: Decoder::default(),
+        })
+    }
+
+    /// Listen for `window` and return the last tag read in it, or `None`
+    /// if the reader stayed quiet (no tag in the field).
+    pub fn read_tag(&mut self, window: Duration) -> io::Result<Option<[u8; 5]>> {
+        let deadline = Instant::now() + window;
+        let mut last = None;
+        let mut buf = [0u8; 64];
+        loop {
+            while let Some(tag) = self.decoder.next_tag() {
+                match tag {
+                    Ok(id) => last = Some(id),
+                    Err(e) => debug!("dropping RDM6300 frame: {e}"),
+                }
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(last);
+            }
+            let n = self.port.read_timeout(&mut buf, left)?;
+            self.decoder.feed(&buf[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    /// Tag 0x0F00A5C3E1, as the reader sends it.
+    const FRAME: &[u8] = b"\x020F00A5C3E188\x03";
+    const ID: [u8; 5] = [0x0F, 0x00, 0xA5, 0xC3, 0xE1];
+
+    fn decode_all(bytes: &[u8]) -> Vec<Result<[u8; 5], FrameError>> {
+        let mut d = Decoder::default();
+        d.feed(bytes);
+        std::iter::from_fn(|| d.next_tag()).collect()
+    }
+
+    #[test]
+    fn decodes_a_frame() {
+        assert_eq!(decode_all(FRAME), vec![Ok(ID)]);
+    }
+
+    #[test]
+    fn decodes_lowercase_hex() {
+        assert_eq!(decode_all(b"\x020f00a5c3e188\x03"), vec![Ok(ID)]);
+    }
+
+    #[test]
+    fn decodes_a_frame_split_across_reads() {
+        let mut d = Decoder::default();
+        d.feed(&FRAME[..5]);
+        assert_eq!(d.next_tag(), None);
+        d.feed(&FRAME[5..]);
+        assert_eq!(d.next_tag(), Some(Ok(ID)));
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        assert_eq!(
+            decode_all(b"\x020F00A5C3E18B\x03"),
+            vec![Err(FrameError::Checksum)]
+        );
+    }
+
+    #[test]
+    fn noise_and_broken_frames_are_skipped() {
+        let stream = [&b"\xff\x00\x020F00A5C3"[..], FRAME].concat();
+        assert_eq!(decode_all(&stream), vec![Err(FrameError::Framing), Ok(ID)]);
+        assert_eq!(
+            decode_all(b"\x020F00A5C3E1ZZ\x03"),
+            vec![Err(FrameError::NotHex)]
+        );
+    }
+
+    /// A pseudo-terminal: the master end stands in for the reader, the
+    /// slave's path is what soundkid opens. The slave fd is kept open so the
+    /// pty isn't hung up before the test opens it by path.
+    fn pty() -> (OwnedFd, OwnedFd, String) {
+        let (mut master, mut slave) = (0, 0);
+        // SAFETY: openpty fills in two fds; name, termios and winsize are
+        // optional and left null.
+        let rc = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(rc, 0, "openpty: {}", io::Error::last_os_error());
+        // SAFETY: both fds were just created and are owned by nobody else.
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // SAFETY: ptsname returns a static buffer valid until the next call.
+        let name = unsafe { CStr::from_ptr(libc::ptsname(master.as_raw_fd())) };
+        let name = name.to_string_lossy().into_owned();
+        (master, slave, name)
+    }
+
+    fn write(fd: &OwnedFd, bytes: &[u8]) {
+        // SAFETY: writes from a live slice of the given length.
+        let n = unsafe { libc::write(fd.as_raw_fd(), bytes.as_ptr().cast(), bytes.len()) };
+        assert_eq!(n, bytes.len() as isize);
+    }
+
+    #[test]
+    fn reads_tags_through_a_pty() {
+        let (master, _slave, path) = pty();
+        let mut reader = Rdm6300::open(&path, DEFAULT_BAUD).unwrap();
+        assert_eq!(reader.read_tag(Duration::from_millis(20)).unwrap(), None);
+
+        // The tag stays on the reader: the same frame, over and over.
+        write(&master, &[FRAME, FRAME, &FRAME[..6]].concat());
+        let window = Duration::from_millis(200);
+        assert_eq!(reader.read_tag(window).unwrap(), Some(ID));
+        write(&master, &FRAME[6..]);
+        assert_eq!(reader.read_tag(window).unwrap(), Some(ID));
+        // Taken off: the reader goes quiet.
+        assert_eq!(reader.read_tag(Duration::from_millis(50)).unwrap(), None);
+    }
+}
@@ -1,22 +1,64 @@
 use evdev::{Device, EventSummary, KeyCode};
-use futures::stream::StreamExt;
 use gpio_cdev::{AsyncLineEventHandle, Chip, EventRequestFlags, LineRequestFlags};
+use std::collections::HashMap;
 use std::fs;
+use std::io::BufRead;
 use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, error, info, warn};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep_until};
+use tracing::{debug, info, warn};
 
-use crate::input::InputEvent;
+use crate::button::{EdgeFilter, PressDetector};
+use crate::config::{
+    Bias, ConfigGpioLine, ConfigInputDevice, ConfigPseudo, ConfigRfid, ConfigRotary,
+};
+use crate::gpio::{self, GpioEdges, LineSettings, PairedEdges};
+use crate::hotplug::{DeviceState, DeviceStates};
+use crate::input::{InputEvent, Press};
+use crate::keymap::KeyDecoder;
+use crate::mfrc522::{Mfrc522, Mfrc522Error, Spidev};
+use crate::ndef;
+use crate::pn532::{self, Link, Pn532, Pn532Error};
+use crate::rdm6300::{self, Rdm6300};
+use crate::rotary::{Channel, QuadratureDecoder, Step};
+use crate::scan::ScanBuffer;
+use crate::selector::{DeviceInfo, DeviceSelector, Selection, list_candidates};
+use crate::tag::{RepeatPresence, Scan, Tag, TagChange, TagPresence, uid_hex};
 
 #[derive(Debug, Error)]
 pub enum ReaderError {
+    #[error("invalid input device {0:?}: {1}")]
+    BadSelector(String, String),
     #[error(
-        "input device {0:?} not found. Try a path in /dev/input/ or a device name \
-         (list with `evtest`)."
+        "input device {desc:?} not found. Try a path in /dev/input/ (by-id/ stays \
+         the same across reboots), a device name, usb:VVVV:PPPP, phys:... or uniq:.... \
+         {}",
+        list_candidates(candidates)
     )]
-    DeviceNotFound(String),
+    DeviceNotFound {
+        desc: String,
+        candidates: Vec<DeviceInfo>,
+    },
+    #[error(
+        "input device {desc:?} matches more than one device; tell them apart with \
+         phys:, uniq: or a /dev/input/by-id/ path. {}",
+        list_candidates(candidates)
+    )]
+    DeviceAmbiguous {
+        desc: String,
+        candidates: Vec<DeviceInfo>,
+    },
+    #[error("could not grab input device {path:?} (is something else grabbing it?): {source}")]
+    Grab {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
     #[error("could not open input device {path:?}: {source}")]
     Open {
         path: String,
@@ -50,10 +92,25 @@ pub enum ReaderError {
         #[source]
         source: gpio_cdev::Error,
     },
+    #[error("could not set up RFID reader on {path:?}: {source}")]
+    Rfid {
+        path: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("could not request GPIO line {line} on {path:?}: {source}")]
+    GpioRequest {
+        path: String,
+        line: u32,
+        #[source]
+        source: std::io::Error,
+    },
 }
 
 pub struct Input {
     pub device_desc: String,
+    /// The `/dev/input/event*` node the description resolved to.
+    pub path: String,
     pub device: Device,
 }
 
@@ -65,125 +122,394 @@ impl Input {
             .unwrap_or(false)
     }
 
-    /// Resolve `device_desc` to a `/dev/input/event*` path.
-    /// Either it's already a path, or it's a device name that matches one.
-    fn find_device_path(device_desc: &str) -> Option<String> {
-        if Self::is_char_device(device_desc) {
-            return Some(device_desc.to_string());
+    /// Resolve `device_desc` (see [`DeviceSelector`]) to a
+    /// `/dev/input/event*` path.
+    fn find_device_path(device_desc: &str) -> Result<String, ReaderError> {
+        let selector: DeviceSelector = device_desc
+            .parse()
+            .map_err(|e| ReaderError::BadSelector(device_desc.to_string(), e))?;
+        if let DeviceSelector::Path(path) = &selector {
+            if Self::is_char_device(path) {
+                // Report the node a by-id symlink points at.
+                return Ok(fs::canonicalize(path)
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| path.clone()));
+            }
         }
+        selector
+            .select(Self::list_devices())
+            .map(|d| d.path)
+            .map_err(|selection| match selection {
+                Selection::None(candidates) => ReaderError::DeviceNotFound {
+                    desc: device_desc.to_string(),
+                    candidates,
+                },
+                Selection::Ambiguous(candidates) => ReaderError::DeviceAmbiguous {
+                    desc: device_desc.to_string(),
+                    candidates,
+                },
+            })
+    }
 
+    /// Everything in `/dev/input` we can open, in event number order.
+    fn list_devices() -> Vec<DeviceInfo> {
         let entries = match fs::read_dir("/dev/input") {
             Ok(rd) => rd,
             Err(e) => {
                 debug!("could not read /dev/input: {e}");
-                return None;
+                return Vec::new();
             }
         };
-        for entry in entries.flatten() {
-            let name = entry.file_name();
-            let Some(name) = name.to_str() else { continue };
-            if !name.starts_with("event") {
-                continue;
-            }
-            let path_str = entry.path().to_string_lossy().into_owned();
-            debug!("checking device path {path_str:?}");
-            match Device::open(&path_str) {
-                Ok(d) if d.name() == Some(device_desc) => return Some(path_str),
-                Ok(_) => {}
-                Err(e) => debug!("could not open {path_str:?}: {e}"),
-            }
-        }
-        None
+        let mut devices: Vec<(u32, DeviceInfo)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let number = name.to_str()?.strip_prefix("event")?.parse().ok()?;
+                let path = entry.path().to_string_lossy().into_owned();
+                match Device::open(&path) {
+                    Ok(d) => {
+                        let id = d.input_id();
+                        Some((
+                            number,
+                            DeviceInfo {
+                                name: d.name().map(str::to_string),
+                                vendor: id.vendor(),
+                                product: id.product(),
+                                phys: d.physical_path().map(str::to_string),
+                                uniq: d.unique_name().map(str::to_string),
+                                path,
+                            },
+                        ))
+                    }
+                    Err(e) => {
+                        debug!("could not open {path:?}: {e}");
+                        None
+                    }
+                }
+            })
+            .collect();
+        devices.sort_by_key(|(number, _)| *number);
+        devices.into_iter().map(|(_, d)| d).collect()
     }
 
-    pub fn new(device_desc: &str) -> Result<Self, ReaderError> {
-        let path = Self::find_device_path(device_desc).ok_or_else(|| {
-            error!(
-                "Cannot resolve device description {device_desc:?}. \
-                 Try a path in /dev/input/ or a device name (e.g. from `evtest`)."
-            );
-            ReaderError::DeviceNotFound(device_desc.to_string())
-        })?;
-        let device = Device::open(&path).map_err(|source| ReaderError::Open {
+    /// Open the device `device_desc` selects. With `grab`, take it for
+    /// exclusive use; the grab is released when the device is dropped.
+    pub fn new(device_desc: &str, grab: bool) -> Result<Self, ReaderError> {
+        let path = Self::find_device_path(device_desc)?;
+        let mut device = Device::open(&path).map_err(|source| ReaderError::Open {
             path: path.clone(),
             source,
         })?;
-        info!("Using input device {path:?}");
+        if grab {
+            device.grab().map_err(|source| ReaderError::Grab {
+                path: path.clone(),
+                source,
+            })?;
+            info!("Using input device {path:?}, grabbed for exclusive use");
+        } else {
+            info!("Using input device {path:?}");
+        }
         Ok(Self {
             device_desc: device_desc.to_string(),
+            path,
             device,
         })
     }
 }
 
-/// Spawn a task that reads numeric scancodes from an evdev device, accumulates
-/// them until ENTER, and pushes the resulting string into the channel as an
-/// `InputEvent::Evdev`.
-pub fn spawn_evdev_reader(reader: Input, tx: Sender<InputEvent>) {
+/// Why a reader stopped reading.
+enum ReadEnd {
+    /// The device went away (or stopped making sense); try to re-attach.
+    Disconnected,
+    /// Nobody is listening anymore; stop for good.
+    Closed,
+}
+
+/// How long to wait before re-opening a device that errored but whose node
+/// is still there, so a broken device doesn't spin.
+const REATTACH_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest wait between attempts to open a device that isn't there or
+/// can't be opened. Some failures (permissions udev hasn't applied yet,
+/// another grabber, EIO) go away without `/dev/input` changing, so the
+/// wait doubles from [`REATTACH_BACKOFF`] up to this rather than relying on
+/// a directory event alone.
+const RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// Spawn a task that keeps `device_desc` attached: it opens the device when
+/// it shows up, reads it until it disappears, and waits for it to come back,
+/// trying again whenever `/dev/input` changes and on a growing backoff.
+/// `hotplug` sees a new value whenever `/dev/input` changes (see
+/// [`crate::hotplug::spawn_watcher`]); `states` tracks where each device stands.
+///
+/// Scan devices assemble keystrokes into IDs according to the device's scan
+/// options and push each one as an `InputEvent::Evdev`; devices with `keys`
+/// push `InputEvent::Key` instead.
+///
+/// Aborting the returned task closes the device, releasing its grab.
+pub fn spawn_evdev_reader(
+    device_desc: String,
+    device: &ConfigInputDevice,
+    tx: Sender<InputEvent>,
+    mut hotplug: watch::Receiver<()>,
+    states: DeviceStates,
+) -> JoinHandle<()> {
+    let device = device.clone();
     tokio::spawn(async move {
-        let mut stream = match reader.device.into_event_stream() {
-            Ok(s) => s,
-            Err(e) => {
-                warn!(
-                    "could not turn {:?} into event stream: {e}",
-                    reader.device_desc
-                );
-                return;
-            }
-        };
-        let mut buf = String::new();
+        let mut retry = REATTACH_BACKOFF;
         loop {
-            let ev = match stream.next_event().await {
-                Ok(ev) => ev,
+            // Mark what we've seen before looking, so a device appearing
+            // while we look still wakes us up.
+            hotplug.borrow_and_update();
+            match Input::new(&device_desc, device.grab) {
+                Ok(reader) => {
+                    retry = REATTACH_BACKOFF;
+                    states.set(&device_desc, DeviceState::Attached(reader.path.clone()));
+                    let end = if device.keys.is_empty() {
+                        read_scans(reader, &device, &tx).await
+                    } else {
+                        read_keys(reader, &device, &tx).await
+                    };
+                    if let ReadEnd::Closed = end {
+                        return;
+                    }
+                    states.set(&device_desc, DeviceState::Disconnected);
+                    tokio::time::sleep(REATTACH_BACKOFF).await;
+                    continue;
+                }
                 Err(e) => {
-                    warn!("evdev read error on {:?}: {e}", reader.device_desc);
-                    return;
+                    debug!("{device_desc:?} not available: {e}");
+                    if states.get(&device_desc).is_none() {
+                        warn!("{e}");
+                        states.set(&device_desc, DeviceState::Waiting);
+                    }
+                }
+            }
+            tokio::select! {
+                changed = hotplug.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
+                _ = tokio::time::sleep(retry) => retry = (retry * 2).min(RETRY_MAX),
+                _ = tx.closed() => return,
+            }
+        }
+    })
+}
+
+/// Scan mode: decode keystrokes and assemble them into IDs.
+async fn read_scans(reader: Input, device: &ConfigInputDevice, tx: &Sender<InputEvent>) -> ReadEnd {
+    let mut decoder = KeyDecoder::new(device.charset, device.layout);
+    let mut scan = ScanBuffer::new(device);
+    let mut presence = (device.removal_ms > 0)
+        .then(|| RepeatPresence::new(Duration::from_millis(device.removal_ms)));
+    let mut stream = match reader.device.into_event_stream() {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(
+                "could not turn {:?} into event stream: {e}",
+                reader.device_desc
+            );
+            return ReadEnd::Disconnected;
+        }
+    };
+    loop {
+        let deadline = scan.deadline();
+        let ev = tokio::select! {
+            ev = stream.next_event() => ev,
+            _ = sleep_until_some(deadline) => {
+                let Some(scanned) = scan.expire(Instant::now()) else {
+                    continue;
+                };
+                if !send_scan(tx, &reader.device_desc, scanned, presence.as_mut()).await {
+                    return ReadEnd::Closed;
                 }
-            };
-            // value 1 == key down; ignore key-up and key-repeat to avoid duplicates
-            if ev.value() != 1 {
                 continue;
             }
-            let EventSummary::Key(_, code, _) = ev.destructure() else {
+            _ = sleep_until_some(presence.as_ref().and_then(RepeatPresence::deadline)) => {
+                let Some(uid) = presence.as_mut().and_then(|p| p.expire(Instant::now())) else {
+                    continue;
+                };
+                if !send_removal(tx, &reader.device_desc, uid).await {
+                    return ReadEnd::Closed;
+                }
                 continue;
-            };
-            match code {
-                KeyCode::KEY_0 => buf.push('0'),
-                KeyCode::KEY_1 => buf.push('1'),
-                KeyCode::KEY_2 => buf.push('2'),
-                KeyCode::KEY_3 => buf.push('3'),
-                KeyCode::KEY_4 => buf.push('4'),
-                KeyCode::KEY_5 => buf.push('5'),
-                KeyCode::KEY_6 => buf.push('6'),
-                KeyCode::KEY_7 => buf.push('7'),
-                KeyCode::KEY_8 => buf.push('8'),
-                KeyCode::KEY_9 => buf.push('9'),
-                KeyCode::KEY_ENTER => {
-                    if buf.is_empty() {
-                        continue;
-                    }
-                    debug!("Input event on {:?}: {buf:?}", reader.device_desc);
-                    let event = InputEvent::Evdev {
-                        device: reader.device_desc.clone(),
-                        scanned: buf.clone(),
-                    };
-                    if tx.send(event).await.is_err() {
-                        return;
+            }
+        };
+        let ev = match ev {
+            Ok(ev) => ev,
+            Err(e) => {
+                warn!("evdev read error on {:?}: {e}", reader.device_desc);
+                return ReadEnd::Disconnected;
+            }
+        };
+        let value = ev.value();
+        let EventSummary::Key(_, code, _) = ev.destructure() else {
+            continue;
+        };
+        let now = Instant::now();
+        // A stale partial from before this key must not prefix it.
+        let flushed = scan.expire(now);
+        let ch = decoder.feed(code, value);
+        let completed = scan.feed(code, value, ch, now);
+        for scanned in flushed.into_iter().chain(completed) {
+            if !send_scan(tx, &reader.device_desc, scanned, presence.as_mut()).await {
+                return ReadEnd::Closed;
+            }
+        }
+    }
+}
+
+/// Key mode: every key is a button. Presses go through a [`PressDetector`]
+/// per key and out as `InputEvent::Key`. Keys without a mapping still send
+/// short presses, so they show up in the log when setting up a new remote.
+async fn read_keys(reader: Input, device: &ConfigInputDevice, tx: &Sender<InputEvent>) -> ReadEnd {
+    let buttons = &device.keys;
+    let device_desc = reader.device_desc;
+    let mut stream = match reader.device.into_event_stream() {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("could not turn {device_desc:?} into event stream: {e}");
+            return ReadEnd::Disconnected;
+        }
+    };
+    let start = Instant::now();
+    let mut detectors: HashMap<KeyCode, PressDetector> = HashMap::new();
+    loop {
+        let deadline = detectors
+            .values()
+            .filter_map(PressDetector::deadline)
+            .min()
+            .map(|at| start + at);
+        let presses: Vec<(KeyCode, Press)> = tokio::select! {
+            ev = stream.next_event() => {
+                let ev = match ev {
+                    Ok(ev) => ev,
+                    Err(e) => {
+                        warn!("evdev read error on {device_desc:?}: {e}");
+                        return ReadEnd::Disconnected;
                     }
-                    buf.clear();
+                };
+                let value = ev.value();
+                let EventSummary::Key(_, code, _) = ev.destructure() else {
+                    continue;
+                };
+                // 2 is the kernel's autorepeat; held keys repeat via
+                // repeat_ms instead.
+                if value == 2 {
+                    continue;
                 }
-                _ => {}
+                let detector = detectors.entry(code).or_insert_with(|| {
+                    buttons
+                        .get(&code)
+                        .map_or_else(PressDetector::immediate, PressDetector::new)
+                });
+                detector
+                    .edge(value == 1, start.elapsed())
+                    .into_iter()
+                    .map(|press| (code, press))
+                    .collect()
+            }
+            _ = sleep_until_some(deadline) => {
+                let now = start.elapsed();
+                detectors
+                    .iter_mut()
+                    .flat_map(|(code, d)| d.tick(now).into_iter().map(|press| (*code, press)))
+                    .collect()
+            }
+        };
+        for (code, press) in presses {
+            debug!("{press:?} press of {code:?} on {device_desc:?}");
+            let event = InputEvent::Key {
+                device: device_desc.clone(),
+                code,
+                press,
+            };
+            if tx.send(event).await.is_err() {
+                return ReadEnd::Closed;
             }
         }
-    });
+    }
 }
 
-/// Open a GPIO line and return an async event handle ready to be consumed.
+/// Sleep until `deadline`, or forever if there is none.
+async fn sleep_until_some(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Push a scanned ID into the channel. If removals are tracked, repeats of
+/// the card lying on the reader are dropped, and a new card comes after the
+/// removal of the one it replaced. Returns `false` once the receiver is
+/// gone and the reader should stop.
+async fn send_scan(
+    tx: &Sender<InputEvent>,
+    device: &str,
+    scanned: String,
+    presence: Option<&mut RepeatPresence>,
+) -> bool {
+    let replaced = match presence.map(|p| p.seen(&scanned, Instant::now())) {
+        Some(Scan::Repeat) => {
+            debug!("Repeat of {scanned:?} on {device:?}");
+            return true;
+        }
+        Some(Scan::Arrived { replaced }) => replaced,
+        None => None,
+    };
+    if let Some(uid) = replaced {
+        if !send_removal(tx, device, uid).await {
+            return false;
+        }
+    }
+    debug!("Input event on {device:?}: {scanned:?}");
+    let event = InputEvent::Evdev {
+        device: device.to_string(),
+        scanned,
+    };
+    tx.send(event).await.is_ok()
+}
+
+/// Report that the reader stopped repeating a card, like [`send_scan`].
+async fn send_removal(tx: &Sender<InputEvent>, device: &str, uid: String) -> bool {
+    debug!("Card {uid:?} gone from {device:?}");
+    let event = InputEvent::CardRemoved {
+        reader: device.to_string(),
+        uid,
+    };
+    tx.send(event).await.is_ok()
+}
+
+/// Open a GPIO line and return its edge events, ready to be consumed.
 ///
 /// All the failure-prone setup (chip open, line lookup, event subscription,
 /// AsyncFd registration) happens here so that callers can fail fast at startup
 /// rather than discovering broken config inside a spawned task.
-pub fn setup_gpio_line(chip_path: &str, line: u32) -> Result<AsyncLineEventHandle, ReaderError> {
+///
+/// The line is requested through the v2 uAPI so the kernel debounces it. On
+/// kernels without v2 this falls back to a v1 request, and
+/// `spawn_gpio_reader` debounces in software.
+pub fn setup_gpio_line(
+    chip_path: &str,
+    line: u32,
+    settings: &LineSettings,
+) -> Result<GpioEdges, ReaderError> {
+    match GpioEdges::request(chip_path, line, settings) {
+        Ok(edges) => return Ok(edges),
+        Err(e) if gpio::is_unsupported(&e) => {
+            info!("GPIO v2 uAPI unavailable for {chip_path:?}/{line} ({e}), using v1");
+        }
+        Err(source) => {
+            return Err(ReaderError::GpioRequest {
+                path: chip_path.to_string(),
+                line,
+                source,
+            });
+        }
+    }
+
     let mut chip = Chip::new(chip_path).map_err(|source| ReaderError::GpioChip {
         path: chip_path.to_string(),
         source,
@@ -197,8 +523,8 @@ pub fn setup_gpio_line(chip_path: &str, line: u32) -> Result<AsyncLineEventHandl
         })?;
     let events = chip_line
         .events(
-            LineRequestFlags::INPUT,
-            EventRequestFlags::FALLING_EDGE,
+            legacy_flags(settings),
+            EventRequestFlags::BOTH_EDGES,
             "soundkid",
         )
         .map_err(|source| ReaderError::GpioEvents {
@@ -206,35 +532,211 @@ pub fn setup_gpio_line(chip_path: &str, line: u32) -> Result<AsyncLineEventHandl
             line,
             source,
         })?;
-    AsyncLineEventHandle::new(events).map_err(|source| ReaderError::GpioAsync {
-        path: chip_path.to_string(),
-        line,
-        source,
-    })
+    AsyncLineEventHandle::new(events)
+        .map(|events| GpioEdges::Legacy { events, line })
+        .map_err(|source| ReaderError::GpioAsync {
+            path: chip_path.to_string(),
+            line,
+            source,
+        })
 }
 
-/// Spawn a task that consumes async GPIO events and forwards them as
-/// `InputEvent::Gpio` into the channel.
+/// v1 handle flags for a line. gpio-cdev predates the bias flags (Linux
+/// 5.5), so those are passed as raw bits.
+fn legacy_flags(settings: &LineSettings) -> LineRequestFlags {
+    const GPIOHANDLE_REQUEST_BIAS_PULL_UP: u32 = 1 << 5;
+    const GPIOHANDLE_REQUEST_BIAS_PULL_DOWN: u32 = 1 << 6;
+    const GPIOHANDLE_REQUEST_BIAS_DISABLE: u32 = 1 << 7;
+
+    let mut flags = LineRequestFlags::INPUT;
+    if settings.active_low {
+        flags |= LineRequestFlags::ACTIVE_LOW;
+    }
+    flags
+        | LineRequestFlags::from_bits_retain(match settings.bias {
+            Bias::AsIs => 0,
+            Bias::PullUp => GPIOHANDLE_REQUEST_BIAS_PULL_UP,
+            Bias::PullDown => GPIOHANDLE_REQUEST_BIAS_PULL_DOWN,
+            Bias::Disabled => GPIOHANDLE_REQUEST_BIAS_DISABLE,
+        })
+}
+
+/// Spawn a task that consumes GPIO edges, debounces them unless the kernel
+/// already did, turns them into presses with a [`PressDetector`] and
+/// forwards those as `InputEvent::Gpio` into the channel.
 pub fn spawn_gpio_reader(
     chip_path: String,
     line: u32,
-    mut events: AsyncLineEventHandle,
+    conf: &ConfigGpioLine,
+    edges: GpioEdges,
+    tx: Sender<InputEvent>,
+) {
+    let label = format!("{chip_path:?}/{line}");
+    spawn_button(label, conf, edges, tx, move |press| InputEvent::Gpio {
+        chip: chip_path.clone(),
+        line,
+        press,
+    });
+}
+
+fn spawn_button<F>(
+    label: String,
+    conf: &ConfigGpioLine,
+    mut edges: GpioEdges,
+    tx: Sender<InputEvent>,
+    event: F,
+) where
+    F: Fn(Press) -> InputEvent + Send + 'static,
+{
+    let mut detector = PressDetector::new(&conf.button);
+    let window = if edges.kernel_debounced() {
+        Duration::ZERO
+    } else {
+        Duration::from_millis(conf.debounce_ms)
+    };
+    let mut filter = EdgeFilter::new(window);
+    tokio::spawn(async move {
+        info!("Watching GPIO button {label}");
+        // Kernel timestamp of the latest edge and when we saw it, to turn
+        // the deadlines into timers and back.
+        let mut anchor = (Duration::ZERO, Instant::now());
+        loop {
+            let deadline = [filter.deadline(), detector.deadline()]
+                .into_iter()
+                .flatten()
+                .min()
+                .map(|at| anchor.1 + at.saturating_sub(anchor.0));
+            let presses = tokio::select! {
+                edge = edges.next() => {
+                    let edge = match edge {
+                        Ok(edge) => edge,
+                        Err(e) => {
+                            warn!("GPIO event error on {label}: {e}");
+                            return;
+                        }
+                    };
+                    debug!("GPIO event on {label}: {edge:?}");
+                    anchor = (edge.timestamp, Instant::now());
+                    match filter.edge(edge.pressed, edge.timestamp) {
+                        Some(pressed) => detector.edge(pressed, edge.timestamp),
+                        None => vec![],
+                    }
+                }
+                _ = sleep_until_some(deadline) => {
+                    let now = anchor.0 + anchor.1.elapsed();
+                    let mut presses = match filter.tick(now) {
+                        Some((pressed, at)) => detector.edge(pressed, at),
+                        None => vec![],
+                    };
+                    presses.extend(detector.tick(now));
+                    presses
+                }
+            };
+            for press in presses {
+                debug!("{press:?} press on {label}");
+                if tx.send(event(press)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// The requested lines of one `rotary:` encoder.
+pub struct RotaryLines {
+    quadrature: PairedEdges,
+    switch: Option<GpioEdges>,
+}
+
+/// Request an encoder's A/B lines and its switch, if it has one. Like
+/// [`setup_gpio_line`], this fails at startup rather than in the task.
+pub fn setup_rotary(conf: &ConfigRotary) -> Result<RotaryLines, ReaderError> {
+    // The quadrature decoder copes with bounce itself, and which level
+    // counts as active doesn't change the direction.
+    let settings = LineSettings {
+        debounce_ms: 0,
+        active_low: false,
+        bias: conf.bias,
+    };
+    // One request for both lines, so their edges can't change places on
+    // the way to the decoder.
+    let quadrature = match GpioEdges::request_lines(&conf.chip, &[conf.a, conf.b], &settings) {
+        Ok(edges) => PairedEdges::Shared(edges),
+        Err(e) if gpio::is_unsupported(&e) => PairedEdges::separate(
+            setup_gpio_line(&conf.chip, conf.a, &settings)?,
+            setup_gpio_line(&conf.chip, conf.b, &settings)?,
+        ),
+        Err(source) => {
+            return Err(ReaderError::GpioRequest {
+                path: conf.chip.clone(),
+                line: conf.a,
+                source,
+            });
+        }
+    };
+    Ok(RotaryLines {
+        quadrature,
+        switch: conf
+            .switch
+            .as_ref()
+            .map(|s| setup_gpio_line(&conf.chip, s.line, &(&s.button).into()))
+            .transpose()?,
+    })
+}
+
+/// Spawn a task that decodes an encoder's A/B edges into
+/// `InputEvent::RotaryCw`/`RotaryCcw`, plus a button task for its switch
+/// that sends `InputEvent::RotaryPress`.
+pub fn spawn_rotary_reader(
+    encoder: String,
+    conf: &ConfigRotary,
+    lines: RotaryLines,
     tx: Sender<InputEvent>,
 ) {
+    let RotaryLines {
+        mut quadrature,
+        switch,
+    } = lines;
+    let a = conf.a;
+    if let (Some(edges), Some(switch)) = (switch, &conf.switch) {
+        let name = encoder.clone();
+        spawn_button(
+            format!("{encoder:?} switch"),
+            &switch.button,
+            edges,
+            tx.clone(),
+            move |press| InputEvent::RotaryPress {
+                encoder: name.clone(),
+                press,
+            },
+        );
+    }
+    let mut decoder = QuadratureDecoder::new(conf.steps_per_detent);
     tokio::spawn(async move {
-        info!("Watching GPIO line {line} on {chip_path}");
-        while let Some(event) = events.next().await {
-            match event {
-                Ok(ev) => debug!("GPIO event on {chip_path:?}/{line}: {ev:?}"),
+        info!("Watching rotary encoder {encoder:?}");
+        loop {
+            let edge = match quadrature.next().await {
+                Ok(edge) => edge,
                 Err(e) => {
-                    warn!("GPIO event error on {chip_path:?}/{line}: {e}");
+                    warn!("GPIO event error on rotary encoder {encoder:?}: {e}");
                     return;
                 }
-            }
-            let event = InputEvent::Gpio {
-                chip: chip_path.clone(),
-                line,
             };
+            let channel = if edge.line == a {
+                Channel::A
+            } else {
+                Channel::B
+            };
+            let event = match decoder.edge(channel, edge.pressed) {
+                Some(Step::Clockwise) => InputEvent::RotaryCw {
+                    encoder: encoder.clone(),
+                },
+                Some(Step::CounterClockwise) => InputEvent::RotaryCcw {
+                    encoder: encoder.clone(),
+                },
+                None => continue,
+            };
+            debug!("Rotary step {event:?}");
             if tx.send(event).await.is_err() {
                 return;
             }
@@ -242,11 +744,323 @@ pub fn spawn_gpio_reader(
     });
 }
 
+/// Polls in a row a card must be missing from an RFID reader before it
+/// counts as removed.
+const REMOVAL_GRACE_POLLS: u32 = 3;
+
+/// Open an MFRC522 and check it answers. Like [`setup_gpio_line`], this
+/// fails at startup rather than in the task.
+pub fn setup_mfrc522(conf: &ConfigRfid) -> Result<Mfrc522<Spidev>, ReaderError> {
+    let rfid_error = |source: Mfrc522Error| ReaderError::Rfid {
+        path: conf.path.clone(),
+        source: source.into(),
+    };
+    let spi = Spidev::open(&conf.path).map_err(|e| rfid_error(e.into()))?;
+    let mut chip = Mfrc522::new(spi);
+    let version = chip.init().map_err(rfid_error)?;
+    info!("MFRC522 version {version:#04x} on {:?}", conf.path);
+    Ok(chip)
+}
+
+/// Spawn a task that polls an MFRC522 for cards and sends
+/// `InputEvent::Card` when one is put on it and `InputEvent::CardRemoved`
+/// when it is taken off.
+pub fn spawn_mfrc522_reader(
+    reader: String,
+    conf: &ConfigRfid,
+    mut chip: Mfrc522<Spidev>,
+    tx: Sender<InputEvent>,
+) {
+    let poll = Duration::from_millis(conf.poll_ms.max(1));
+    let ndef = conf.ndef;
+    spawn_tag_poller(reader, tx, poll, move || chip.read_tag(ndef));
+}
+
+/// Open a PN532 and configure it. Like [`setup_gpio_line`], this fails at
+/// startup rather than in the task.
+pub fn setup_pn532(conf: &ConfigRfid) -> Result<Pn532<Box<dyn Link + Send>>, ReaderError> {
+    let rfid_error = |source: Pn532Error| ReaderError::Rfid {
+        path: conf.path.clone(),
+        source: source.into(),
+    };
+    let mut chip = pn532::open(&conf.path).map_err(|e| rfid_error(e.into()))?;
+    let (version, revision) = chip.init().map_err(rfid_error)?;
+    info!("PN532 firmware {version}.{revision} on {:?}", conf.path);
+    Ok(chip)
+}
+
+/// Spawn a task that polls a PN532 for cards, like
+/// [`spawn_mfrc522_reader`].
+pub fn spawn_pn532_reader(
+    reader: String,
+    conf: &ConfigRfid,
+    mut chip: Pn532<Box<dyn Link + Send>>,
+    tx: Sender<InputEvent>,
+) {
+    let poll = Duration::from_millis(conf.poll_ms.max(1));
+    let ndef = conf.ndef;
+    spawn_tag_poller(reader, tx, poll, move || chip.read_tag(ndef));
+}
+
+/// The shortest an RDM6300 is listened to per poll: a shorter window can
+/// fall between two of the frames it repeats while a tag is on it.
+const RDM6300_MIN_WINDOW: Duration = Duration::from_millis(250);
+
+/// Open the serial port of an RDM6300. The module has nothing to answer
+/// with, so only the port itself is checked at startup.
+pub fn setup_rdm6300(conf: &ConfigRfid) -> Result<Rdm6300, ReaderError> {
+    let rfid_error = |source: std::io::Error| ReaderError::Rfid {
+        path: conf.path.clone(),
+        source: source.into(),
+    };
+    if conf.ndef {
+        return Err(rfid_error(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "EM4100 tags carry no NDEF, only their ID",
+        )));
+    }
+    let baud = conf.baud.unwrap_or(rdm6300::DEFAULT_BAUD);
+    let port = Rdm6300::open(&conf.path, baud).map_err(rfid_error)?;
+    info!("RDM6300 on {:?} at {baud} baud", conf.path);
+    Ok(port)
+}
+
+/// Spawn a task that listens to an RDM6300, like [`spawn_mfrc522_reader`].
+/// A tag counts as taken off once the reader has been quiet for a few
+/// polls.
+pub fn spawn_rdm6300_reader(
+    reader: String,
+    conf: &ConfigRfid,
+    mut port: Rdm6300,
+    tx: Sender<InputEvent>,
+) {
+    let window = Duration::from_millis(conf.poll_ms).max(RDM6300_MIN_WINDOW);
+    // Each read listens for the whole window, so there's nothing to wait
+    // for in between.
+    spawn_tag_poller(reader, tx, Duration::ZERO, move || {
+        port.read_tag(window).map(|tag| {
+            tag.map(|id| Tag {
+                uid: id.to_vec(),
+                ndef: None,
+            })
+        })
+    });
+}
+
+/// Spawn a thread that reads IDs, one per line, from `conf.fifo` or stdin
+/// and sends them as if `conf.device` had scanned them. A FIFO is opened
+/// again whenever its writer closes it; stdin is read until it ends.
+///
+/// A thread of its own rather than `spawn_blocking`: a read from a terminal
+/// can't be cancelled, and the runtime would wait for it on shutdown.
+pub fn spawn_pseudo_reader(conf: &ConfigPseudo, tx: Sender<InputEvent>) {
+    let device = conf.device.clone();
+    let fifo = conf.fifo.clone();
+    std::thread::spawn(move || {
+        let Some(path) = fifo else {
+            info!("Reading IDs for {device:?} from stdin");
+            forward_lines(std::io::stdin().lock(), &device, &tx);
+            info!("stdin closed, no more IDs for {device:?}");
+            return;
+        };
+        info!("Reading IDs for {device:?} from {path:?}");
+        loop {
+            // Blocks until something opens the FIFO for writing.
+            let file = match fs::File::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!("could not open {path:?}: {e}");
+                    return;
+                }
+            };
+            let is_fifo = file.metadata().is_ok_and(|meta| meta.file_type().is_fifo());
+            if !forward_lines(std::io::BufReader::new(file), &device, &tx) || !is_fifo {
+                return;
+            }
+        }
+    });
+}
+
+/// Send each non-empty line as an ID `device` scanned. False once the
+/// receiver is gone or reading fails.
+fn forward_lines(lines: impl BufRead, device: &str, tx: &Sender<InputEvent>) -> bool {
+    for line in lines.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("reading IDs for {device:?} failed: {e}");
+                return false;
+            }
+        };
+        let scanned = line.trim();
+        if scanned.is_empty() {
+            continue;
+        }
+        let event = InputEvent::Evdev {
+            device: device.to_string(),
+            scanned: scanned.to_string(),
+        };
+        debug!("Pseudo input {event:?}");
+        if tx.blocking_send(event).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Call `read_uid` with `pause` in between on a blocking thread (the
+/// readers are driven with blocking I/O) and turn what it sees into card
+/// events. A failed poll neither adds nor removes a card.
+fn spawn_tag_poller<F, E>(reader: String, tx: Sender<InputEvent>, pause: Duration, mut read_uid: F)
+where
+    F: FnMut() -> Result<Option<Tag>, E> + Send + 'static,
+    E: std::fmt::Display,
+{
+    tokio::task::spawn_blocking(move || {
+        info!("Polling RFID reader {reader:?} for cards");
+        let mut presence = TagPresence::new(REMOVAL_GRACE_POLLS);
+        let mut failing = false;
+        loop {
+            match read_uid() {
+                Ok(tag) => {
+                    failing = false;
+                    let (uid, mut message) = tag.map(|tag| (uid_hex(&tag.uid), tag.ndef)).unzip();
+                    for change in presence.poll(uid) {
+                        let event = match change {
+                            TagChange::Arrived(uid) => InputEvent::Card {
+                                reader: reader.clone(),
+                                uid,
+                                uri: message
+                                    .take()
+                                    .flatten()
+                                    .and_then(|m| message_uri(&reader, &m)),
+                            },
+                            TagChange::Removed(uid) => InputEvent::CardRemoved {
+                                reader: reader.clone(),
+                                uid,
+                            },
+                        };
+                        debug!("RFID event {event:?}");
+                        if tx.blocking_send(event).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) if !failing => {
+                    warn!("reading RFID reader {reader:?} failed: {e}");
+                    failing = true;
+                }
+                Err(e) => debug!("reading RFID reader {reader:?} still fails: {e}"),
+            }
+            if tx.is_closed() {
+                return;
+            }
+            std::thread::sleep(pause);
+        }
+    });
+}
+
+/// The URI in a tag's NDEF message. One that doesn't parse is logged and
+/// the card treated as if it carried none.
+fn message_uri(reader: &str, message: &[u8]) -> Option<String> {
+    ndef::message_uri(message).unwrap_or_else(|e| {
+        warn!("ignoring the NDEF message on a card on {reader:?}: {e}");
+        None
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Input;
+    use super::{Input, ReaderError, forward_lines, send_scan, spawn_pseudo_reader};
+    use crate::config::ConfigPseudo;
+    use crate::input::InputEvent;
+    use crate::tag::RepeatPresence;
     use std::fs::File;
+    use std::time::Duration;
     use tempfile::tempdir;
+    use tokio::sync::mpsc;
+
+    fn scanned(device: &str, id: &str) -> InputEvent {
+        InputEvent::Evdev {
+            device: device.into(),
+            scanned: id.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn repeats_of_a_card_lying_on_the_reader_are_sent_once() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut presence = RepeatPresence::new(Duration::from_millis(600));
+        for id in ["0012345678", "0012345678", "0012345678", "0087654321"] {
+            assert!(send_scan(&tx, "kbd", id.into(), Some(&mut presence)).await);
+        }
+        assert_eq!(rx.try_recv().unwrap(), scanned("kbd", "0012345678"));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            InputEvent::CardRemoved {
+                reader: "kbd".into(),
+                uid: "0012345678".into(),
+            }
+        );
+        assert_eq!(rx.try_recv().unwrap(), scanned("kbd", "0087654321"));
+        assert!(rx.try_recv().is_err());
+
+        // Without removal tracking every scan counts.
+        for _ in 0..2 {
+            assert!(send_scan(&tx, "kbd", "0012345678".into(), None).await);
+        }
+        assert_eq!(rx.try_recv().unwrap(), scanned("kbd", "0012345678"));
+        assert_eq!(rx.try_recv().unwrap(), scanned("kbd", "0012345678"));
+    }
+
+    #[test]
+    fn pseudo_lines_are_ids_the_device_scanned() {
+        let (tx, mut rx) = mpsc::channel(8);
+        assert!(forward_lines(
+            &b"0012345678\n\n  04A1B2\r\n"[..],
+            "lid",
+            &tx
+        ));
+        assert_eq!(rx.try_recv().unwrap(), scanned("lid", "0012345678"));
+        assert_eq!(rx.try_recv().unwrap(), scanned("lid", "04A1B2"));
+        assert!(rx.try_recv().is_err());
+        drop(rx);
+        assert!(!forward_lines(&b"0012345678\n"[..], "lid", &tx));
+    }
+
+    #[tokio::test]
+    async fn pseudo_reader_reads_a_plain_file_once() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ids");
+        std::fs::write(&path, "0012345678\n").unwrap();
+        let conf = ConfigPseudo {
+            device: "/dev/input/event0".into(),
+            fifo: Some(path),
+        };
+        let (tx, mut rx) = mpsc::channel(8);
+        spawn_pseudo_reader(&conf, tx);
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            scanned("/dev/input/event0", "0012345678")
+        );
+        // The sender is dropped once the file is read.
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[test]
+    fn by_id_symlink_resolves_to_its_node() {
+        let dir = tempdir().unwrap();
+        let link = dir.path().join("usb-HXGCoLtd_Keyboard-event-kbd");
+        std::os::unix::fs::symlink("/dev/null", &link).unwrap();
+        let path = Input::find_device_path(link.to_str().unwrap()).unwrap();
+        assert_eq!(path, "/dev/null");
+    }
+
+    #[test]
+    fn malformed_selector_is_reported() {
+        let err = Input::find_device_path("usb:12").unwrap_err();
+        assert!(matches!(err, ReaderError::BadSelector(..)), "{err}");
+    }
 
     #[test]
     fn dev_null_is_a_char_device() {
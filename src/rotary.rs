@@ -0,0 +1,177 @@
+/// Which of an encoder's two quadrature lines an edge came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    A,
+    B,
+}
+
+/// One detent of a rotary encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Turns edges on an encoder's A and B lines into steps.
+///
+/// The two lines walk through the Gray code 00 → 01 → 11 → 10 one way and
+/// backwards the other. Each valid transition moves a counter by one; a
+/// step is reported once the counter reaches `steps_per_detent`. Contact
+/// bounce on one line just moves the counter back and forth, and a
+/// transition that flips both lines at once is ignored, so no separate
+/// debounce is needed.
+#[derive(Debug)]
+pub struct QuadratureDecoder {
+    steps_per_detent: i32,
+    a: Option<bool>,
+    b: Option<bool>,
+    count: i32,
+}
+
+impl QuadratureDecoder {
+    pub fn new(steps_per_detent: u8) -> Self {
+        Self {
+            steps_per_detent: steps_per_detent.max(1).into(),
+            a: None,
+            b: None,
+            count: 0,
+        }
+    }
+
+    /// Feed an edge that left `channel` at `level`. Until both lines have
+    /// moved once their levels are unknown, so the first transition or two
+    /// after startup only teach the decoder where it is.
+    pub fn edge(&mut self, channel: Channel, level: bool) -> Option<Step> {
+        let (a, b) = match channel {
+            Channel::A => (Some(level), self.b),
+            Channel::B => (self.a, Some(level)),
+        };
+        // An edge means the line was at the other level just before.
+        let before = match channel {
+            Channel::A => (Some(!level), self.b),
+            Channel::B => (self.a, Some(!level)),
+        };
+        self.a = a;
+        self.b = b;
+        let (Some(from), Some(to)) = (gray_position(before), gray_position((a, b))) else {
+            return None;
+        };
+        self.count += match (to + 4 - from) % 4 {
+            1 => 1,
+            3 => -1,
+            // Same position, or a skipped one we can't tell the direction of.
+            _ => return None,
+        };
+        if self.count >= self.steps_per_detent {
+            self.count = 0;
+            Some(Step::Clockwise)
+        } else if self.count <= -self.steps_per_detent {
+            self.count = 0;
+            Some(Step::CounterClockwise)
+        } else {
+            None
+        }
+    }
+}
+
+/// Position of an (A, B) level pair along the clockwise Gray sequence.
+fn gray_position(levels: (Option<bool>, Option<bool>)) -> Option<i32> {
+    match levels {
+        (Some(false), Some(false)) => Some(0),
+        (Some(false), Some(true)) => Some(1),
+        (Some(true), Some(true)) => Some(2),
+        (Some(true), Some(false)) => Some(3),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Edges for one full clockwise cycle starting from 00.
+    const CW: [(Channel, bool); 4] = [
+        (Channel::B, true),
+        (Channel::A, true),
+        (Channel::B, false),
+        (Channel::A, false),
+    ];
+
+    /// Edges for one full counter-clockwise cycle starting from 00.
+    const CCW: [(Channel, bool); 4] = [
+        (Channel::A, true),
+        (Channel::B, true),
+        (Channel::A, false),
+        (Channel::B, false),
+    ];
+
+    /// A decoder that has already seen both lines settle at 00.
+    fn synced(steps_per_detent: u8) -> QuadratureDecoder {
+        let mut d = QuadratureDecoder::new(steps_per_detent);
+        d.edge(Channel::A, false);
+        d.edge(Channel::B, false);
+        d.count = 0;
+        d
+    }
+
+    fn feed(d: &mut QuadratureDecoder, edges: &[(Channel, bool)]) -> Vec<Step> {
+        edges.iter().filter_map(|&(c, l)| d.edge(c, l)).collect()
+    }
+
+    #[test]
+    fn full_cycle_clockwise_is_one_step() {
+        let mut d = synced(4);
+        assert_eq!(feed(&mut d, &CW), vec![Step::Clockwise]);
+    }
+
+    #[test]
+    fn full_cycle_counter_clockwise_is_one_step() {
+        let mut d = synced(4);
+        assert_eq!(feed(&mut d, &CCW), vec![Step::CounterClockwise]);
+    }
+
+    #[test]
+    fn half_detent_encoders_step_twice_per_cycle() {
+        let mut d = synced(2);
+        assert_eq!(feed(&mut d, &CW), vec![Step::Clockwise, Step::Clockwise]);
+    }
+
+    #[test]
+    fn bounce_on_one_line_cancels_out() {
+        let mut d = synced(4);
+        let bouncy = [
+            (Channel::B, true),
+            (Channel::B, false),
+            (Channel::B, true),
+            (Channel::A, true),
+            (Channel::A, false),
+            (Channel::A, true),
+            (Channel::B, false),
+            (Channel::A, false),
+        ];
+        assert_eq!(feed(&mut d, &bouncy), vec![Step::Clockwise]);
+    }
+
+    #[test]
+    fn reversing_midway_emits_nothing() {
+        let mut d = synced(4);
+        assert_eq!(
+            feed(&mut d, &[(Channel::B, true), (Channel::A, true)]),
+            vec![]
+        );
+        assert_eq!(
+            feed(&mut d, &[(Channel::A, false), (Channel::B, false)]),
+            vec![]
+        );
+        assert_eq!(feed(&mut d, &CCW), vec![Step::CounterClockwise]);
+    }
+
+    #[test]
+    fn unknown_levels_at_start_are_learned() {
+        let mut d = QuadratureDecoder::new(1);
+        // B's level is unknown, so A's first edge can't be placed.
+        assert_eq!(d.edge(Channel::A, true), None);
+        // A is known now: B going high moves from 10 to 11, counter-clockwise.
+        assert_eq!(d.edge(Channel::B, true), Some(Step::CounterClockwise));
+    }
+}
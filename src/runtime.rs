@@ -1,10 +1,16 @@
+use std::collections::HashSet;
+use std::time::Duration;
 use tokio::process::Command;
 use tokio::sync::mpsc::Receiver;
+use tokio::time::Instant;
 use tracing::{debug, info, warn};
 
-use crate::config::{Action, Config};
-use crate::input::{InputEvent, lookup_action};
+use crate::cards::CardStore;
+use crate::config::{Action, Config, OnRemove};
+use crate::input::{Debouncer, InputEvent, lookup_action, on_remove};
 use crate::player::{PlayerControl, PlayerError};
+use crate::status::{PlayState, SeenCard, StatusBoard};
+use crate::uri::canonicalize_uri;
 
 /// Drive the dispatch loop: pull events off the channel, look up their
 /// configured action, and invoke the player or amixer accordingly.
@@ -12,45 +18,273 @@ use crate::player::{PlayerControl, PlayerError};
 /// Returns when the channel closes (all senders dropped) or when a player
 /// command fails — typically because the player task has died.
 ///
+/// Cards the config doesn't know are looked up in `cards`. Cards read are
+/// noted on `status`, and so are ones without an action.
+///
+/// After a LEARN action, the next card without one is given what learn
+/// mode was waiting to hand out, and saved to `cards`.
+///
 /// Generic over `PlayerControl` so tests can substitute a fake.
 pub async fn handle_input<P: PlayerControl>(
     conf: Config,
     mut events_rx: Receiver<InputEvent>,
     player: P,
+    status: StatusBoard,
+    cards: CardStore,
 ) -> Result<(), PlayerError> {
     info!("Input receiver started");
-    while let Some(event) = events_rx.recv().await {
+    let mut debouncer = Debouncer::new(&conf);
+    // The card (reader, ID) that started what is playing, and whether it
+    // was taken off and playback paused until it comes back.
+    let mut playing_card: Option<(String, String)> = None;
+    let mut lifted = false;
+    let mut player_state = status.subscribe();
+    let mut learning: Option<Learning> = None;
+    // The URI last played through a control interface, for LEARN to give
+    // to a card.
+    let mut last_pushed: Option<String> = None;
+    loop {
+        let received = match learning.as_ref().and_then(|l| l.until) {
+            Some(until) => tokio::time::timeout_at(until, events_rx.recv()).await,
+            None => Ok(events_rx.recv().await),
+        };
+        let event = match received {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(_) => {
+                info!("No new card in time, leaving learn mode");
+                learning = None;
+                status.update(|s| s.learn = None);
+                continue;
+            }
+        };
         debug!("Received {event:?}");
-        let Some(action) = lookup_action(&conf, &event) else {
-            warn!("no action configured for {event:?}");
+        if !debouncer.accept(&event, Instant::now()) {
+            debug!("Debounced repeat of {event:?}");
+            continue;
+        }
+        let card = event
+            .card()
+            .map(|card| (event.source().to_string(), card.to_string()));
+        if let InputEvent::CardRemoved { reader, uid } = &event {
+            info!("Card {uid} taken off {reader:?}");
+            if lifted || card != playing_card {
+                continue;
+            }
+            match on_remove(&conf, reader) {
+                OnRemove::Nothing => {}
+                OnRemove::Pause => {
+                    player.pause().await?;
+                    lifted = true;
+                }
+                OnRemove::Stop => {
+                    player.stop().await?;
+                    playing_card = None;
+                }
+            }
             continue;
+        }
+        let seen = card.as_ref().map(|(reader, id)| SeenCard {
+            reader: reader.clone(),
+            id: id.clone(),
+        });
+        if seen.is_some() {
+            status.update(|s| s.last_card = seen.clone());
+        }
+        // Playback ended while the card was off (the idle stop, say), so
+        // there is nothing to resume: putting it back starts it again.
+        if player_state.has_changed().unwrap_or(false)
+            && player_state.borrow_and_update().state == PlayState::Stopped
+            && lifted
+        {
+            lifted = false;
+            playing_card = None;
+        }
+        if lifted && card.is_some() && card == playing_card {
+            info!("Card put back, resuming");
+            player.resume().await?;
+            lifted = false;
+            continue;
+        }
+        let fallback;
+        let action = match lookup_action(&conf, &event) {
+            Some(action) => action,
+            None => match assigned_action(&cards, &event)
+                .await
+                .or_else(|| tag_uri_action(&event))
+            {
+                Some(action) => {
+                    fallback = action;
+                    &fallback
+                }
+                None => match (&seen, learning.take()) {
+                    (Some(new), Some(learned)) => {
+                        // Played even if it can't be kept.
+                        if let Err(e) = cards
+                            .assign(&new.reader, &new.id, learned.action.clone())
+                            .await
+                        {
+                            warn!("could not keep the learned card: {e}");
+                        }
+                        status.update(|s| s.learn = None);
+                        fallback = learned.action;
+                        &fallback
+                    }
+                    (_, still_learning) => {
+                        learning = still_learning;
+                        warn!("no action configured for {event:?}");
+                        if seen.is_some() {
+                            status.update(|s| s.last_unknown = seen);
+                        }
+                        continue;
+                    }
+                },
+            },
         };
         info!("Dispatching {action:?} from {event:?}");
+        if let (InputEvent::Control { .. }, Action::Play(uri)) = (&event, action) {
+            last_pushed = Some(uri.clone());
+        }
+        match action {
+            Action::Play(_) => {
+                playing_card = card;
+                lifted = false;
+            }
+            Action::Stop => {
+                playing_card = None;
+                lifted = false;
+            }
+            _ => {}
+        }
+        let mixer = &conf.alsa.control;
         match action {
-            Action::VolumeIncrease => amixer(&conf.alsa.control, "5%+").await,
-            Action::VolumeDecrease => amixer(&conf.alsa.control, "5%-").await,
+            Action::VolumeIncrease => amixer(mixer, "5%+", &status).await,
+            Action::VolumeDecrease => amixer(mixer, "5%-", &status).await,
             Action::Pause => player.pause().await?,
             Action::Resume => player.resume().await?,
+            Action::PlayPause => player.toggle_pause().await?,
+            Action::Stop => player.stop().await?,
+            Action::Next => player.next().await?,
+            Action::Previous => player.previous().await?,
             Action::Play(uri) => player.play(uri.clone()).await?,
+            Action::Volume(percent) => {
+                let change = format!("{}%{}", percent.abs(), if *percent < 0 { '-' } else { '+' });
+                amixer(mixer, &change, &status).await
+            }
+            Action::SetVolume(percent) => amixer(mixer, &format!("{percent}%"), &status).await,
+            Action::Learn(None) if learning.is_some() => {
+                info!("Leaving learn mode");
+                learning = None;
+                status.update(|s| s.learn = None);
+            }
+            Action::Learn(uri) => {
+                let target = match uri {
+                    Some(uri) => Some(uri.clone()),
+                    None => next_unassigned(&conf, &cards)
+                        .await
+                        .or_else(|| last_pushed.clone()),
+                };
+                match target {
+                    Some(uri) => {
+                        info!("Learn mode: the next new card plays {uri}");
+                        let action = Action::Play(uri);
+                        status.update(|s| s.learn = Some(action.clone()));
+                        learning = Some(Learning {
+                            action,
+                            until: Instant::now()
+                                .checked_add(Duration::from_secs(conf.learn.timeout_secs)),
+                        });
+                    }
+                    None => warn!(
+                        "nothing to learn: learn.queue is used up and nothing was played \
+                         through a control interface"
+                    ),
+                }
+            }
         }
     }
     Ok(())
 }
 
-async fn amixer(control: &str, change: &str) {
+/// Learn mode: what the next new card gets, if it comes in time.
+struct Learning {
+    action: Action,
+    /// None if the timeout is too long to represent, so never.
+    until: Option<Instant>,
+}
+
+/// The first URI in `learn.queue` no card plays yet.
+async fn next_unassigned(conf: &Config, cards: &CardStore) -> Option<String> {
+    let assigned = cards.all().await;
+    let configured = conf
+        .input
+        .values()
+        .map(|device| &device.cards)
+        .chain(conf.rfid.values().map(|reader| &reader.cards))
+        .flat_map(|cards| cards.values());
+    let played: HashSet<&str> = configured
+        .chain(assigned.values().flat_map(|cards| cards.values()))
+        .filter_map(|action| match action {
+            Action::Play(uri) => Some(uri.as_str()),
+            _ => None,
+        })
+        .collect();
+    conf.learn
+        .queue
+        .iter()
+        .find(|uri| !played.contains(uri.as_str()))
+        .cloned()
+}
+
+/// A card the config doesn't know may have been assigned an action since.
+async fn assigned_action(cards: &CardStore, event: &InputEvent) -> Option<Action> {
+    let id = event.card()?;
+    cards.get(event.source(), id).await
+}
+
+/// A card without an entry in the config or the card store plays the URI
+/// written on it, if it carries one.
+fn tag_uri_action(event: &InputEvent) -> Option<Action> {
+    let InputEvent::Card { uri: Some(uri), .. } = event else {
+        return None;
+    };
+    match canonicalize_uri(uri) {
+        Ok(uri) => Some(Action::Play(uri)),
+        Err(e) => {
+            warn!("card carries {uri:?}, which can't be played: {e}");
+            None
+        }
+    }
+}
+
+/// Change the volume and note where it ended up, as amixer reports it.
+async fn amixer(control: &str, change: &str, status: &StatusBoard) {
     match Command::new("amixer")
         .args(["set", control, change])
         .output()
         .await
     {
-        Ok(_) => info!("Adjusted volume for {control} by {change}"),
+        Ok(output) => {
+            info!("Adjusted volume for {control} by {change}");
+            if let Some(level) = reported_volume(&String::from_utf8_lossy(&output.stdout)) {
+                status.update(|s| s.volume = Some(level));
+            }
+        }
         Err(e) => warn!("amixer set {control} {change} failed: {e}"),
     }
 }
 
+/// The first channel's level in amixer's `Front Left: Playback 26214 [40%] [on]`.
+fn reported_volume(output: &str) -> Option<u8> {
+    output
+        .split('[')
+        .find_map(|part| part.split_once("%]")?.0.parse().ok())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::input::Press;
     use crate::player::PlayerControl;
     use std::sync::{Arc, Mutex};
     use tokio::sync::mpsc;
@@ -69,6 +303,9 @@ mod tests {
         Stop,
         Pause,
         Resume,
+        TogglePause,
+        Next,
+        Previous,
     }
 
     impl FakePlayer {
@@ -109,6 +346,21 @@ mod tests {
             self.record(Cmd::Resume);
             Ok(())
         }
+
+        async fn toggle_pause(&self) -> Result<(), PlayerError> {
+            self.record(Cmd::TogglePause);
+            Ok(())
+        }
+
+        async fn next(&self) -> Result<(), PlayerError> {
+            self.record(Cmd::Next);
+            Ok(())
+        }
+
+        async fn previous(&self) -> Result<(), PlayerError> {
+            self.record(Cmd::Previous);
+            Ok(())
+        }
     }
 
     const TRACK: &str = "6rqhFgbbKwnb9MLmUQDhG6";
@@ -123,10 +375,13 @@ input:
     "PLAY_CARD": "spotify:track:{TRACK}"
     "PAUSE_CARD": "PAUSE"
     "RESUME_CARD": "RESUME"
+    "TOGGLE_CARD": "PLAY_PAUSE"
+    "STOP_CARD": "STOP"
     "VOL_UP_CARD": "VOLUME_INCREASE"
 gpio:
   /dev/gpiochip0:
     17: "PAUSE"
+    22: {{ short: NEXT, long: PREVIOUS }}
 "#
         )
     }
@@ -143,18 +398,34 @@ gpio:
     }
 
     fn gpio(line: u32) -> InputEvent {
+        gpio_press(line, Press::Short)
+    }
+
+    fn gpio_press(line: u32, press: Press) -> InputEvent {
         InputEvent::Gpio {
             chip: "/dev/gpiochip0".into(),
             line,
+            press,
         }
     }
 
+    /// A card store nothing gets assigned to, so it never writes.
+    fn no_cards() -> CardStore {
+        CardStore::new(std::path::Path::new("/nonexistent"))
+    }
+
     /// Drive handle_input to completion: send the events, drop the sender,
     /// await the dispatch loop.
     async fn run(events: Vec<InputEvent>, fake: FakePlayer) -> Result<(), PlayerError> {
         let conf = build_config();
         let (tx, rx) = mpsc::channel(8);
-        let task = tokio::spawn(handle_input(conf, rx, fake));
+        let task = tokio::spawn(handle_input(
+            conf,
+            rx,
+            fake,
+            StatusBoard::default(),
+            no_cards(),
+        ));
         for ev in events {
             tx.send(ev).await.unwrap();
         }
@@ -162,6 +433,18 @@ gpio:
         task.await.unwrap()
     }
 
+    #[test]
+    fn reads_the_volume_amixer_reports() {
+        let output = "Simple mixer control 'Master',0\n  Limits: Playback 0 - 65536\n  \
+                      Front Left: Playback 26214 [40%] [on]\n  \
+                      Front Right: Playback 26214 [40%] [on]\n";
+        assert_eq!(reported_volume(output), Some(40));
+        assert_eq!(
+            reported_volume("amixer: Unable to find simple control"),
+            None
+        );
+    }
+
     #[tokio::test]
     async fn evdev_play_card_dispatches_play() {
         let fake = FakePlayer::default();
@@ -186,6 +469,20 @@ gpio:
         assert_eq!(fake.commands(), vec![Cmd::Resume]);
     }
 
+    #[tokio::test]
+    async fn evdev_toggle_card_dispatches_toggle_pause() {
+        let fake = FakePlayer::default();
+        run(vec![evdev("TOGGLE_CARD")], fake.clone()).await.unwrap();
+        assert_eq!(fake.commands(), vec![Cmd::TogglePause]);
+    }
+
+    #[tokio::test]
+    async fn evdev_stop_card_dispatches_stop() {
+        let fake = FakePlayer::default();
+        run(vec![evdev("STOP_CARD")], fake.clone()).await.unwrap();
+        assert_eq!(fake.commands(), vec![Cmd::Stop]);
+    }
+
     #[tokio::test]
     async fn volume_action_does_not_touch_player() {
         // amixer is unlikely to exist in CI, so it'll warn-and-continue;
@@ -211,6 +508,18 @@ gpio:
         assert_eq!(fake.commands(), vec![Cmd::Pause]);
     }
 
+    #[tokio::test]
+    async fn gpio_short_and_long_press_dispatch_their_actions() {
+        let fake = FakePlayer::default();
+        run(
+            vec![gpio_press(22, Press::Short), gpio_press(22, Press::Long)],
+            fake.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fake.commands(), vec![Cmd::Next, Cmd::Previous]);
+    }
+
     #[tokio::test]
     async fn multiple_events_dispatch_in_order() {
         let fake = FakePlayer::default();
@@ -240,7 +549,13 @@ gpio:
         fake.arm_play_failure();
         let conf = build_config();
         let (tx, rx) = mpsc::channel(8);
-        let task = tokio::spawn(handle_input(conf, rx, fake.clone()));
+        let task = tokio::spawn(handle_input(
+            conf,
+            rx,
+            fake.clone(),
+            StatusBoard::default(),
+            no_cards(),
+        ));
         // Send a Play event that the fake will reject.
         tx.send(evdev("PLAY_CARD")).await.unwrap();
         // Even though we haven't dropped tx, the loop should exit on the
@@ -0,0 +1,275 @@
+use evdev::KeyCode;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::config::{ConfigInputDevice, OnTimeout, Terminator};
+
+/// Collects decoded characters from a card reader into scanned IDs.
+///
+/// An ID is complete when the terminator key arrives or, with a fixed
+/// `length`, when that many characters are in. A partial ID that sits for
+/// longer than the inter-key timeout is stale (the reader dropped its
+/// terminator) and gets discarded or flushed, so it can't corrupt the next
+/// scan. Time is passed in, which keeps this testable without a device.
+#[derive(Debug)]
+pub struct ScanBuffer {
+    terminator: Terminator,
+    length: Option<usize>,
+    timeout: Option<Duration>,
+    on_timeout: OnTimeout,
+    max_length: usize,
+    buf: String,
+    /// Characters in `buf`; layouts like de/fr type ones longer than a byte.
+    chars: usize,
+    last_key: Option<Instant>,
+}
+
+impl ScanBuffer {
+    pub fn new(device: &ConfigInputDevice) -> Self {
+        Self {
+            terminator: device.terminator,
+            length: device.length,
+            timeout: (device.timeout_ms > 0).then(|| Duration::from_millis(device.timeout_ms)),
+            on_timeout: device.on_timeout,
+            max_length: device.max_length,
+            buf: String::new(),
+            chars: 0,
+            last_key: None,
+        }
+    }
+
+    /// Feed one key event along with the character it decoded to, if any.
+    /// Returns a complete ID when this key finished one.
+    pub fn feed(
+        &mut self,
+        code: KeyCode,
+        value: i32,
+        ch: Option<char>,
+        now: Instant,
+    ) -> Option<String> {
+        // Only key-down counts; releases and autorepeat never end an ID.
+        if value != 1 {
+            return None;
+        }
+        if self.terminator.matches(code) {
+            return self.take();
+        }
+        let c = ch?;
+        self.buf.push(c);
+        self.chars += 1;
+        self.last_key = Some(now);
+        if self.length.is_some_and(|len| self.chars >= len) {
+            return self.take();
+        }
+        if self.chars > self.max_length {
+            warn!(
+                "discarding {} characters without a terminator, longer than max_length {}",
+                self.chars, self.max_length
+            );
+            self.clear();
+        }
+        None
+    }
+
+    /// When the partial ID goes stale, or `None` if there is nothing to
+    /// time out.
+    pub fn deadline(&self) -> Option<Instant> {
+        match (self.timeout, self.last_key) {
+            (Some(timeout), Some(last)) if !self.buf.is_empty() => Some(last + timeout),
+            _ => None,
+        }
+    }
+
+    /// Deal with a stale partial ID. Returns it when `on_timeout` is flush;
+    /// otherwise it is dropped. Does nothing before the deadline.
+    pub fn expire(&mut self, now: Instant) -> Option<String> {
+        if self.deadline().is_none_or(|deadline| now < deadline) {
+            return None;
+        }
+        match self.on_timeout {
+            OnTimeout::Flush => {
+                debug!("flushing partial scan {:?} after timeout", self.buf);
+                self.take()
+            }
+            OnTimeout::Discard => {
+                debug!("discarding partial scan {:?} after timeout", self.buf);
+                self.clear();
+                None
+            }
+        }
+    }
+
+    fn take(&mut self) -> Option<String> {
+        self.last_key = None;
+        self.chars = 0;
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.buf))
+    }
+
+    fn clear(&mut self) {
+        self.buf.clear();
+        self.chars = 0;
+        self.last_key = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOWN: i32 = 1;
+    const UP: i32 = 0;
+
+    fn device(yaml: &str) -> ConfigInputDevice {
+        serde_yaml_ng::from_str(yaml).expect("test device config must parse")
+    }
+
+    fn default_device() -> ConfigInputDevice {
+        device("cards: {}")
+    }
+
+    /// Feed `s` as one key-down per character, all at `at`, and collect any
+    /// IDs that completed along the way.
+    fn type_str(scan: &mut ScanBuffer, s: &str, at: Instant) -> Vec<String> {
+        s.chars()
+            .filter_map(|c| scan.feed(KeyCode::KEY_A, DOWN, Some(c), at))
+            .collect()
+    }
+
+    #[test]
+    fn enter_completes_id() {
+        let mut scan = ScanBuffer::new(&default_device());
+        let now = Instant::now();
+        assert!(type_str(&mut scan, "1234", now).is_empty());
+        assert_eq!(
+            scan.feed(KeyCode::KEY_ENTER, DOWN, None, now),
+            Some("1234".into())
+        );
+    }
+
+    #[test]
+    fn keypad_enter_also_completes_by_default() {
+        let mut scan = ScanBuffer::new(&default_device());
+        let now = Instant::now();
+        type_str(&mut scan, "42", now);
+        assert_eq!(
+            scan.feed(KeyCode::KEY_KPENTER, DOWN, None, now),
+            Some("42".into())
+        );
+    }
+
+    #[test]
+    fn terminator_on_empty_buffer_is_ignored() {
+        let mut scan = ScanBuffer::new(&default_device());
+        assert_eq!(
+            scan.feed(KeyCode::KEY_ENTER, DOWN, None, Instant::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn key_up_does_not_terminate() {
+        let mut scan = ScanBuffer::new(&default_device());
+        let now = Instant::now();
+        type_str(&mut scan, "1", now);
+        assert_eq!(scan.feed(KeyCode::KEY_ENTER, UP, None, now), None);
+    }
+
+    #[test]
+    fn custom_terminator_key() {
+        let mut scan = ScanBuffer::new(&device("terminator: KEY_TAB\ncards: {}"));
+        let now = Instant::now();
+        type_str(&mut scan, "99", now);
+        assert_eq!(scan.feed(KeyCode::KEY_ENTER, DOWN, None, now), None);
+        assert_eq!(
+            scan.feed(KeyCode::KEY_TAB, DOWN, None, now),
+            Some("99".into())
+        );
+    }
+
+    #[test]
+    fn fixed_length_without_terminator() {
+        let mut scan = ScanBuffer::new(&device("terminator: none\nlength: 4\ncards: {}"));
+        let now = Instant::now();
+        assert_eq!(type_str(&mut scan, "12345678", now), vec!["1234", "5678"]);
+        assert_eq!(scan.feed(KeyCode::KEY_ENTER, DOWN, None, now), None);
+    }
+
+    #[test]
+    fn lengths_count_characters_not_bytes() {
+        let mut scan = ScanBuffer::new(&device("terminator: none\nlength: 4\ncards: {}"));
+        let now = Instant::now();
+        assert_eq!(type_str(&mut scan, "12§4é678", now), vec!["12§4", "é678"]);
+
+        let mut scan = ScanBuffer::new(&device("max_length: 3\ncards: {}"));
+        type_str(&mut scan, "§é1", now);
+        assert_eq!(
+            scan.feed(KeyCode::KEY_ENTER, DOWN, None, now),
+            Some("§é1".into())
+        );
+    }
+
+    #[test]
+    fn overlong_buffer_is_discarded() {
+        let mut scan = ScanBuffer::new(&device("max_length: 3\ncards: {}"));
+        let now = Instant::now();
+        type_str(&mut scan, "1234", now);
+        type_str(&mut scan, "56", now);
+        assert_eq!(
+            scan.feed(KeyCode::KEY_ENTER, DOWN, None, now),
+            Some("56".into())
+        );
+    }
+
+    #[test]
+    fn stale_partial_is_discarded_by_default() {
+        let mut scan = ScanBuffer::new(&default_device());
+        let start = Instant::now();
+        type_str(&mut scan, "12", start);
+        let deadline = scan.deadline().unwrap();
+        assert_eq!(deadline, start + Duration::from_millis(1000));
+        assert_eq!(scan.expire(deadline - Duration::from_millis(1)), None);
+        assert_eq!(scan.expire(deadline), None);
+        assert_eq!(scan.deadline(), None);
+        type_str(&mut scan, "34", deadline);
+        assert_eq!(
+            scan.feed(KeyCode::KEY_ENTER, DOWN, None, deadline),
+            Some("34".into())
+        );
+    }
+
+    #[test]
+    fn stale_partial_is_flushed_when_configured() {
+        let mut scan = ScanBuffer::new(&device("timeout_ms: 200\non_timeout: flush\ncards: {}"));
+        let start = Instant::now();
+        type_str(&mut scan, "12", start);
+        assert_eq!(
+            scan.expire(start + Duration::from_millis(200)),
+            Some("12".into())
+        );
+    }
+
+    #[test]
+    fn each_key_pushes_the_deadline_back() {
+        let mut scan = ScanBuffer::new(&device("timeout_ms: 200\ncards: {}"));
+        let start = Instant::now();
+        type_str(&mut scan, "1", start);
+        let later = start + Duration::from_millis(150);
+        type_str(&mut scan, "2", later);
+        assert_eq!(scan.expire(start + Duration::from_millis(250)), None);
+        assert_eq!(
+            scan.feed(KeyCode::KEY_ENTER, DOWN, None, later),
+            Some("12".into())
+        );
+    }
+
+    #[test]
+    fn zero_timeout_never_expires() {
+        let mut scan = ScanBuffer::new(&device("timeout_ms: 0\ncards: {}"));
+        type_str(&mut scan, "1", Instant::now());
+        assert_eq!(scan.deadline(), None);
+    }
+}
@@ -0,0 +1,359 @@
+use std::fmt;
+use std::str::FromStr;
+use tracing::warn;
+
+/// How an `input:` entry picks its device. The entry's key is one of:
+///
+/// - a path, like `/dev/input/event3` or a `/dev/input/by-id/...` symlink,
+///   which stays the same across reboots and USB ports;
+/// - `usb:VVVV:PPPP`, the USB vendor and product ID in hex (`lsusb`), with
+///   `/inputN` after it to pick one interface of a composite device;
+/// - `phys:...`, the physical path, which pins a device to a USB port;
+/// - `uniq:...`, the unique ID (serial number) some devices report;
+/// - anything else is the device name, as `evtest` shows it. Several
+///   devices can share a name; the first one is taken, as it always was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    Path(String),
+    Usb {
+        vendor: u16,
+        product: u16,
+        /// The end of `phys` after its last `/`, like `input1`.
+        interface: Option<String>,
+    },
+    Phys(String),
+    Uniq(String),
+    Name(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(ids) = s.strip_prefix("usb:") {
+            let (ids, interface) = match ids.split_once('/') {
+                Some((ids, interface)) => (ids, Some(non_empty(interface, s)?.to_string())),
+                None => (ids, None),
+            };
+            let hex = |id: &str| {
+                (id.len() == 4)
+                    .then(|| u16::from_str_radix(id, 16).ok())
+                    .flatten()
+            };
+            return match ids.split_once(':') {
+                Some((v, p)) => match (hex(v), hex(p)) {
+                    (Some(vendor), Some(product)) => Ok(Self::Usb {
+                        vendor,
+                        product,
+                        interface,
+                    }),
+                    _ => Err(usb_format_error(s)),
+                },
+                None => Err(usb_format_error(s)),
+            };
+        }
+        if let Some(phys) = s.strip_prefix("phys:") {
+            return non_empty(phys, s).map(|p| Self::Phys(p.to_string()));
+        }
+        if let Some(uniq) = s.strip_prefix("uniq:") {
+            return non_empty(uniq, s).map(|u| Self::Uniq(u.to_string()));
+        }
+        if s.starts_with('/') {
+            return Ok(Self::Path(s.to_string()));
+        }
+        Ok(Self::Name(s.to_string()))
+    }
+}
+
+fn usb_format_error(s: &str) -> String {
+    format!(
+        "{s:?} is not a USB ID: expected usb:VVVV:PPPP in hex, like usb:1a86:e026, \
+         optionally with an interface like usb:1a86:e026/input0"
+    )
+}
+
+fn non_empty<'a>(value: &'a str, s: &str) -> Result<&'a str, String> {
+    if value.is_empty() {
+        Err(format!("{s:?} is missing the value after the colon"))
+    } else {
+        Ok(value)
+    }
+}
+
+impl DeviceSelector {
+    /// Whether `device` is the one meant. A path never matches here; it is
+    /// opened directly instead.
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        match self {
+            Self::Path(_) => false,
+            Self::Usb {
+                vendor,
+                product,
+                interface,
+            } => {
+                device.vendor == *vendor
+                    && device.product == *product
+                    && interface
+                        .as_deref()
+                        .is_none_or(|i| device.interface() == Some(i))
+            }
+            Self::Phys(phys) => device.phys.as_deref() == Some(phys.as_str()),
+            Self::Uniq(uniq) => device.uniq.as_deref() == Some(uniq.as_str()),
+            Self::Name(name) => device.name.as_deref() == Some(name.as_str()),
+        }
+    }
+
+    /// Pick the one device among `devices` this selects. A name shared by
+    /// several devices picks the first; any other selector matching more
+    /// than one is ambiguous.
+    pub fn select(&self, devices: Vec<DeviceInfo>) -> Result<DeviceInfo, Selection> {
+        let (mut matching, others): (Vec<_>, Vec<_>) =
+            devices.into_iter().partition(|d| self.matches(d));
+        match matching.len() {
+            0 => Err(Selection::None(others)),
+            1 => Ok(matching.remove(0)),
+            _ if matches!(self, Self::Name(_)) => {
+                let first = matching.remove(0);
+                warn!(
+                    "using {first}, the first of several devices with this name. To pick \
+                     another, select it by one of these instead.\n{}",
+                    list_candidates(&matching)
+                );
+                Ok(first)
+            }
+            _ => Err(Selection::Ambiguous(matching)),
+        }
+    }
+}
+
+/// Why [`DeviceSelector::select`] found no single device, with the devices
+/// worth showing the user: everything there is, or all that matched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    None(Vec<DeviceInfo>),
+    Ambiguous(Vec<DeviceInfo>),
+}
+
+/// What identifies an evdev device, for matching and for listing candidates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub path: String,
+    pub name: Option<String>,
+    pub vendor: u16,
+    pub product: u16,
+    pub phys: Option<String>,
+    pub uniq: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Which interface of its USB device this is, like `input1`: the end of
+    /// `phys` after its last `/`.
+    pub fn interface(&self) -> Option<&str> {
+        self.phys
+            .as_deref()?
+            .rsplit_once('/')
+            .map(|(_, interface)| interface)
+            .filter(|i| !i.is_empty())
+    }
+}
+
+/// One line per device, written as selectors so they can be pasted into the
+/// config.
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:?} usb:{:04x}:{:04x}",
+            self.path,
+            self.name.as_deref().unwrap_or(""),
+            self.vendor,
+            self.product
+        )?;
+        if let Some(interface) = self.interface() {
+            write!(f, "/{interface}")?;
+        }
+        if let Some(phys) = self.phys.as_deref().filter(|p| !p.is_empty()) {
+            write!(f, " phys:{phys}")?;
+        }
+        if let Some(uniq) = self.uniq.as_deref().filter(|u| !u.is_empty()) {
+            write!(f, " uniq:{uniq}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Format `devices` as an indented list for an error message.
+pub fn list_candidates(devices: &[DeviceInfo]) -> String {
+    if devices.is_empty() {
+        return "No input devices found.".to_string();
+    }
+    let mut out = "Candidates:".to_string();
+    for device in devices {
+        out.push_str("\n  ");
+        out.push_str(&device.to_string());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(path: &str, phys: &str) -> DeviceInfo {
+        DeviceInfo {
+            path: path.to_string(),
+            name: Some("HXGCoLtd Keyboard".to_string()),
+            vendor: 0x1a86,
+            product: 0xe026,
+            phys: Some(phys.to_string()),
+            uniq: None,
+        }
+    }
+
+    fn remote() -> DeviceInfo {
+        DeviceInfo {
+            path: "/dev/input/event7".to_string(),
+            name: Some("Media Remote".to_string()),
+            vendor: 0x046d,
+            product: 0xc52b,
+            phys: Some("usb-0000:00:14.0-2/input1".to_string()),
+            uniq: Some("4F2A-0001".to_string()),
+        }
+    }
+
+    #[test]
+    fn parses_every_form() {
+        assert_eq!(
+            "usb:1a86:E026".parse(),
+            Ok(DeviceSelector::Usb {
+                vendor: 0x1a86,
+                product: 0xe026,
+                interface: None,
+            })
+        );
+        assert_eq!(
+            "usb:1a86:e026/input1".parse(),
+            Ok(DeviceSelector::Usb {
+                vendor: 0x1a86,
+                product: 0xe026,
+                interface: Some("input1".into()),
+            })
+        );
+        assert_eq!(
+            "phys:usb-0000:00:14.0-1/input0".parse(),
+            Ok(DeviceSelector::Phys("usb-0000:00:14.0-1/input0".into()))
+        );
+        assert_eq!(
+            "uniq:4F2A-0001".parse(),
+            Ok(DeviceSelector::Uniq("4F2A-0001".into()))
+        );
+        assert_eq!(
+            "/dev/input/by-id/usb-HXGCoLtd_Keyboard-event-kbd".parse(),
+            Ok(DeviceSelector::Path(
+                "/dev/input/by-id/usb-HXGCoLtd_Keyboard-event-kbd".into()
+            ))
+        );
+        assert_eq!(
+            "HXGCoLtd Keyboard".parse(),
+            Ok(DeviceSelector::Name("HXGCoLtd Keyboard".into()))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_selectors() {
+        for bad in [
+            "usb:1a86",
+            "usb:1a86:e02",
+            "usb:1a86:xyz1",
+            "usb:1a86:e026/",
+            "phys:",
+            "uniq:",
+        ] {
+            assert!(bad.parse::<DeviceSelector>().is_err(), "{bad} parsed");
+        }
+    }
+
+    #[test]
+    fn matches_by_each_field() {
+        let d = remote();
+        for selector in [
+            "usb:046d:c52b",
+            "usb:046d:c52b/input1",
+            "phys:usb-0000:00:14.0-2/input1",
+            "uniq:4F2A-0001",
+            "Media Remote",
+        ] {
+            let selector: DeviceSelector = selector.parse().unwrap();
+            assert!(selector.matches(&d), "{selector:?} should match");
+        }
+        for other in ["usb:046d:c52c", "usb:046d:c52b/input0"] {
+            let other: DeviceSelector = other.parse().unwrap();
+            assert!(!other.matches(&d), "{other:?} should not match");
+        }
+    }
+
+    #[test]
+    fn select_picks_the_single_match() {
+        let a = reader("/dev/input/event3", "usb-0000:00:14.0-1/input0");
+        let b = reader("/dev/input/event5", "usb-0000:00:14.0-3/input0");
+        let selector: DeviceSelector = "phys:usb-0000:00:14.0-3/input0".parse().unwrap();
+        assert_eq!(selector.select(vec![a, b.clone(), remote()]), Ok(b));
+    }
+
+    #[test]
+    fn identical_readers_are_ambiguous() {
+        let a = reader("/dev/input/event3", "usb-0000:00:14.0-1/input0");
+        let b = reader("/dev/input/event5", "usb-0000:00:14.0-3/input0");
+        let selector: DeviceSelector = "usb:1a86:e026".parse().unwrap();
+        assert_eq!(
+            selector.select(vec![a.clone(), remote(), b.clone()]),
+            Err(Selection::Ambiguous(vec![a, b]))
+        );
+    }
+
+    #[test]
+    fn an_interface_picks_one_part_of_a_composite_reader() {
+        let keys = reader("/dev/input/event3", "usb-0000:00:14.0-1/input0");
+        let extra = reader("/dev/input/event4", "usb-0000:00:14.0-1/input1");
+        let all = || vec![keys.clone(), extra.clone(), remote()];
+        let bare: DeviceSelector = "usb:1a86:e026".parse().unwrap();
+        assert_eq!(
+            bare.select(all()),
+            Err(Selection::Ambiguous(vec![keys.clone(), extra.clone()]))
+        );
+        let second: DeviceSelector = "usb:1a86:e026/input1".parse().unwrap();
+        assert_eq!(second.select(all()), Ok(extra.clone()));
+    }
+
+    #[test]
+    fn a_shared_name_picks_the_first_device() {
+        let a = reader("/dev/input/event3", "usb-0000:00:14.0-1/input0");
+        let b = reader("/dev/input/event5", "usb-0000:00:14.0-3/input0");
+        let selector: DeviceSelector = "HXGCoLtd Keyboard".parse().unwrap();
+        assert_eq!(selector.select(vec![remote(), a.clone(), b]), Ok(a));
+    }
+
+    #[test]
+    fn no_match_returns_everything_there_is() {
+        let selector: DeviceSelector = "Nope".parse().unwrap();
+        assert_eq!(
+            selector.select(vec![remote()]),
+            Err(Selection::None(vec![remote()]))
+        );
+    }
+
+    #[test]
+    fn candidates_are_listed_as_selectors() {
+        let list = list_candidates(&[
+            reader("/dev/input/event3", "usb-0000:00:14.0-1/input0"),
+            remote(),
+        ]);
+        assert_eq!(
+            list,
+            "Candidates:\n  \
+             /dev/input/event3: \"HXGCoLtd Keyboard\" usb:1a86:e026/input0 phys:usb-0000:00:14.0-1/input0\n  \
+             /dev/input/event7: \"Media Remote\" usb:046d:c52b/input1 phys:usb-0000:00:14.0-2/input1 uniq:4F2A-0001"
+        );
+        assert_eq!(list_candidates(&[]), "No input devices found.");
+    }
+}
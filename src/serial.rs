@@ -0,0 +1,148 @@
+//! Raw serial ports for the readers that hang off a UART: 8N1, no flow
+//! control, no line discipline, reads with a timeout.
+
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub struct Serial {
+    fd: OwnedFd,
+}
+
+impl Serial {
+    /// Open `path` raw at `baud`.
+    pub fn open(path: &str, baud: u32) -> io::Result<Self> {
+        let speed = speed(baud).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported baud rate {baud}"),
+            )
+        })?;
+        let c_path = CString::new(Path::new(path).as_os_str().as_bytes())?;
+        // SAFETY: plain open(2) of a NUL-terminated path; checked below.
+        let fd = unsafe {
+            libc::open(
+                c_path.as_ptr(),
+                libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is owned by nobody else.
+        let port = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        // SAFETY: termios is plain data, filled in by tcgetattr before use.
+        let mut tio: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: valid fd and a termios to fill or read.
+        unsafe {
+            if libc::tcgetattr(port.fd.as_raw_fd(), &mut tio) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut tio);
+            tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+            tio.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+            if libc::cfsetspeed(&mut tio, speed) < 0
+                || libc::tcsetattr(port.fd.as_raw_fd(), libc::TCSANOW, &tio) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            libc::tcflush(port.fd.as_raw_fd(), libc::TCIOFLUSH);
+        }
+        Ok(port)
+    }
+
+    pub fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            // SAFETY: writes from a live slice of the given length.
+            let n = unsafe { libc::write(self.fd.as_raw_fd(), buf.as_ptr().cast(), buf.len()) };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            buf = &buf[n as usize..];
+        }
+        Ok(())
+    }
+
+    /// Read whatever arrives within `timeout`, up to `buf.len()` bytes.
+    /// Returns 0 if nothing did.
+    pub fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let mut pfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ms = left.as_millis().min(i32::MAX as u128) as i32;
+            // SAFETY: one valid pollfd.
+            let ready = unsafe { libc::poll(&mut pfd, 1, ms) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if ready == 0 {
+                return Ok(0);
+            }
+            if pfd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0
+                && pfd.revents & libc::POLLIN == 0
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "serial port went away",
+                ));
+            }
+            // SAFETY: reads into a live buffer of the given length.
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            return Ok(n as usize);
+        }
+    }
+}
+
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn odd_baud_rate_is_rejected() {
+        let err = Serial::open("/dev/null", 12345).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn missing_port_is_not_found() {
+        let err = Serial::open("/dev/ttyDOESNOTEXIST", 9600).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
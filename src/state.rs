@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
+
+/// File name of the playback snapshot inside the cache dir.
+const STATE_FILE: &str = "playback.yaml";
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("could not serialise playback state: {0}")]
+    Serialize(#[from] serde_yaml_ng::Error),
+    #[error("could not write playback state to {path:?}: {source}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// What the player was doing, in enough detail to pick up where it left off
+/// after a restart or power loss.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SavedPlayback {
+    /// Canonical `spotify:` URI of the card that was playing.
+    pub uri: String,
+    /// Index into the URI's resolved track list.
+    pub idx: usize,
+    /// Offset into that track.
+    pub position_ms: u32,
+}
+
+/// Reads and writes the `SavedPlayback` snapshot in the cache dir.
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    pub fn new(cache_dir: &Path) -> Self {
+        Self {
+            path: cache_dir.join(STATE_FILE),
+        }
+    }
+
+    /// Return the saved snapshot, or `None` if there is none. A corrupt file
+    /// is logged and treated as absent; it will be overwritten by the next
+    /// save.
+    pub async fn load(&self) -> Option<SavedPlayback> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(c) => c,
+            Err(e) => {
+                debug!("no playback state at {:?}: {e}", self.path);
+                return None;
+            }
+        };
+        match serde_yaml_ng::from_str(&contents) {
+            Ok(saved) => Some(saved),
+            Err(e) => {
+                warn!("ignoring unreadable playback state {:?}: {e}", self.path);
+                None
+            }
+        }
+    }
+
+    /// Write the snapshot with [`replace_file`], so a power cut leaves
+    /// either the old or the new state, never half of one.
+    pub async fn save(&self, saved: &SavedPlayback) -> Result<(), StateError> {
+        let contents = serde_yaml_ng::to_string(saved)?;
+        replace_file(&self.path, &contents)
+            .await
+            .map_err(|(path, source)| StateError::Write { path, source })
+    }
+
+    /// Forget the snapshot. A missing file is not an error.
+    pub async fn clear(&self) -> Result<(), StateError> {
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(source) => Err(StateError::Write {
+                path: self.path.clone(),
+                source,
+            }),
+        }
+    }
+}
+
+/// Replace `path` with `contents` via a synced temporary file and a rename,
+/// then sync the directory so the rename itself survives a power cut. On
+/// failure, returns the path that couldn't be written along with the error.
+pub async fn replace_file(path: &Path, contents: &str) -> Result<(), (PathBuf, std::io::Error)> {
+    let tmp = path.with_extension("yaml.tmp");
+    let at = |path: &Path| {
+        let path = path.to_path_buf();
+        move |e| (path, e)
+    };
+    let mut file = tokio::fs::File::create(&tmp).await.map_err(at(&tmp))?;
+    file.write_all(contents.as_bytes())
+        .await
+        .map_err(at(&tmp))?;
+    file.sync_all().await.map_err(at(&tmp))?;
+    drop(file);
+    tokio::fs::rename(&tmp, path).await.map_err(at(path))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir_file = tokio::fs::File::open(dir).await.map_err(at(dir))?;
+    dir_file.sync_all().await.map_err(at(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn saved() -> SavedPlayback {
+        SavedPlayback {
+            uri: "spotify:album:7LQhG0xSDjFiKJnziyB3Zj".into(),
+            idx: 3,
+            position_ms: 61_500,
+        }
+    }
+
+    #[tokio::test]
+    async fn save_then_load_roundtrips() {
+        let dir = tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        store.save(&saved()).await.unwrap();
+        assert_eq!(store.load().await, Some(saved()));
+    }
+
+    #[tokio::test]
+    async fn save_leaves_no_temporary_file() {
+        let dir = tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        store.save(&saved()).await.unwrap();
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![STATE_FILE]);
+    }
+
+    #[tokio::test]
+    async fn load_missing_file_is_none() {
+        let dir = tempdir().unwrap();
+        assert_eq!(StateStore::new(dir.path()).load().await, None);
+    }
+
+    #[tokio::test]
+    async fn load_corrupt_file_is_none() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join(STATE_FILE), ": not :: yaml").unwrap();
+        assert_eq!(StateStore::new(dir.path()).load().await, None);
+    }
+
+    #[tokio::test]
+    async fn clear_removes_snapshot_and_tolerates_absence() {
+        let dir = tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        store.save(&saved()).await.unwrap();
+        store.clear().await.unwrap();
+        assert_eq!(store.load().await, None);
+        store.clear().await.unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! What soundkid is up to, pieced together by the tasks that know (player,
+//! dispatch loop) for the control interfaces to report.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::config::Action;
+use crate::hotplug::DeviceState;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayState {
+    #[default]
+    Stopped,
+    Playing,
+    Paused,
+}
+
+/// A card as some reader read it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SeenCard {
+    pub reader: String,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub state: PlayState,
+    /// The card's URI being played, while not stopped.
+    pub uri: Option<String>,
+    /// Position in its track list, from 0.
+    pub track: Option<usize>,
+    /// In percent, as amixer last reported it.
+    pub volume: Option<u8>,
+    pub last_card: Option<SeenCard>,
+    /// The last card read that nothing is configured for, ready to be
+    /// given an action.
+    pub last_unknown: Option<SeenCard>,
+    /// In learn mode, what the next new card will be given.
+    pub learn: Option<Action>,
+    /// Where each configured input device stands.
+    pub devices: BTreeMap<String, DeviceState>,
+}
+
+/// The current [`Status`], shared between whoever updates and whoever
+/// reports it. Clones share the same status.
+#[derive(Debug, Clone)]
+pub struct StatusBoard {
+    tx: Arc<watch::Sender<Status>>,
+}
+
+impl Default for StatusBoard {
+    fn default() -> Self {
+        Self {
+            tx: Arc::new(watch::Sender::new(Status::default())),
+        }
+    }
+}
+
+impl StatusBoard {
+    pub fn get(&self) -> Status {
+        self.tx.borrow().clone()
+    }
+
+    /// A receiver that sees every change.
+    pub fn subscribe(&self) -> watch::Receiver<Status> {
+        self.tx.subscribe()
+    }
+
+    /// Change the status in place. Subscribers only hear about it if it
+    /// actually changed.
+    pub fn update(&self, change: impl FnOnce(&mut Status)) {
+        self.tx.send_if_modified(|status| {
+            let before = status.clone();
+            change(status);
+            *status != before
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_real_changes_reach_subscribers() {
+        let board = StatusBoard::default();
+        let mut rx = board.subscribe();
+        board.update(|s| s.state = PlayState::Stopped);
+        assert!(!rx.has_changed().unwrap());
+        board.clone().update(|s| s.state = PlayState::Playing);
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow_and_update().state, PlayState::Playing);
+        assert_eq!(board.get().state, PlayState::Playing);
+    }
+
+    #[test]
+    fn serializes_for_clients() {
+        let status = Status {
+            state: PlayState::Paused,
+            uri: Some("spotify:album:x".into()),
+            track: Some(2),
+            volume: Some(40),
+            last_card: None,
+            last_unknown: Some(SeenCard {
+                reader: "lid".into(),
+                id: "04A1".into(),
+            }),
+            learn: Some(Action::Play("spotify:album:y".into())),
+            devices: BTreeMap::from([
+                (
+                    "lid".into(),
+                    DeviceState::Attached("/dev/input/event3".into()),
+                ),
+                ("remote".into(), DeviceState::Waiting),
+            ]),
+        };
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"state":"paused","uri":"spotify:album:x","track":2,"volume":40,"last_card":null,"last_unknown":{"reader":"lid","id":"04A1"},"learn":"spotify:album:y","devices":{"lid":"attached at /dev/input/event3","remote":"waiting"}}"#
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Card presence for readers that can tell whether a card is still there:
+//! ones that are polled (MFRC522 and friends), and HID readers that keep
+//! typing the ID while the card lies on them.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What one poll of a reader found on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub uid: Vec<u8>,
+    /// The tag's NDEF message, if it was asked for and there is one.
+    pub ndef: Option<Vec<u8>>,
+}
+
+/// A card showing up on a reader or leaving it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagChange {
+    Arrived(String),
+    Removed(String),
+}
+
+/// Turns a reader's polls ("this UID is on me" or "nothing is") into arrivals
+/// and removals. A card is only reported once while it stays on the reader,
+/// and only counts as gone after `grace` polls in a row miss it: reads of a
+/// card lying at the edge of the field fail now and then.
+#[derive(Debug)]
+pub struct TagPresence {
+    grace: u32,
+    current: Option<String>,
+    misses: u32,
+}
+
+impl TagPresence {
+    pub fn new(grace: u32) -> Self {
+        Self {
+            grace: grace.max(1),
+            current: None,
+            misses: 0,
+        }
+    }
+
+    /// The card on the reader, if any.
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// Feed the result of one poll.
+    pub fn poll(&mut self, seen: Option<String>) -> Vec<TagChange> {
+        match seen {
+            Some(uid) => {
+                self.misses = 0;
+                if self.current.as_ref() == Some(&uid) {
+                    return vec![];
+                }
+                let mut changes: Vec<_> = self
+                    .current
+                    .take()
+                    .map(TagChange::Removed)
+                    .into_iter()
+                    .collect();
+                self.current = Some(uid.clone());
+                changes.push(TagChange::Arrived(uid));
+                changes
+            }
+            None if self.current.is_some() => {
+                self.misses += 1;
+                if self.misses < self.grace {
+                    return vec![];
+                }
+                self.misses = 0;
+                self.current
+                    .take()
+                    .map(TagChange::Removed)
+                    .into_iter()
+                    .collect()
+            }
+            None => vec![],
+        }
+    }
+}
+
+/// What one scan on a repeating reader means.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scan {
+    /// The card lying on the reader, sent again.
+    Repeat,
+    /// A card put on the reader. `replaced` is the card it took the place
+    /// of, if another was still counted as present.
+    Arrived { replaced: Option<String> },
+}
+
+/// Presence for HID readers that re-send a card's ID while it lies on
+/// them: the card is gone once the repeats have stopped for `timeout`.
+/// Time is passed in, like [`ScanBuffer`](crate::scan::ScanBuffer).
+#[derive(Debug)]
+pub struct RepeatPresence {
+    timeout: Duration,
+    current: Option<(String, Instant)>,
+}
+
+impl RepeatPresence {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            current: None,
+        }
+    }
+
+    /// The reader sent `id`: a repeat of the card present, or a new one.
+    pub fn seen(&mut self, id: &str, now: Instant) -> Scan {
+        match self.current.replace((id.to_string(), now)) {
+            Some((prev, _)) if prev == id => Scan::Repeat,
+            previous => Scan::Arrived {
+                replaced: previous.map(|(prev, _)| prev),
+            },
+        }
+    }
+
+    /// When the current card will count as gone, unless it is sent again.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.current.as_ref().map(|(_, at)| *at + self.timeout)
+    }
+
+    /// The card that is gone by `now`, if any.
+    pub fn expire(&mut self, now: Instant) -> Option<String> {
+        if self.deadline().is_some_and(|deadline| now >= deadline) {
+            return self.current.take().map(|(id, _)| id);
+        }
+        None
+    }
+}
+
+/// A UID as the cards table spells it: uppercase hex, no separators.
+pub fn uid_hex(uid: &[u8]) -> String {
+    uid.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(uid: &str) -> Option<String> {
+        Some(uid.to_string())
+    }
+
+    #[test]
+    fn card_is_reported_once_while_it_stays() {
+        let mut p = TagPresence::new(2);
+        assert_eq!(
+            p.poll(some("04A1")),
+            vec![TagChange::Arrived("04A1".into())]
+        );
+        assert_eq!(p.poll(some("04A1")), vec![]);
+        assert_eq!(p.current(), Some("04A1"));
+    }
+
+    #[test]
+    fn removal_waits_out_the_grace_polls() {
+        let mut p = TagPresence::new(3);
+        p.poll(some("04A1"));
+        assert_eq!(p.poll(None), vec![]);
+        assert_eq!(p.poll(None), vec![]);
+        assert_eq!(p.poll(None), vec![TagChange::Removed("04A1".into())]);
+        assert_eq!(p.current(), None);
+        assert_eq!(p.poll(None), vec![]);
+    }
+
+    #[test]
+    fn a_flaky_read_does_not_remove() {
+        let mut p = TagPresence::new(2);
+        p.poll(some("04A1"));
+        assert_eq!(p.poll(None), vec![]);
+        assert_eq!(p.poll(some("04A1")), vec![]);
+        assert_eq!(p.poll(None), vec![]);
+        assert_eq!(p.current(), Some("04A1"));
+    }
+
+    #[test]
+    fn swapping_cards_removes_the_old_one_first() {
+        let mut p = TagPresence::new(2);
+        p.poll(some("04A1"));
+        assert_eq!(
+            p.poll(some("BEEF")),
+            vec![
+                TagChange::Removed("04A1".into()),
+                TagChange::Arrived("BEEF".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_ids_keep_the_card_present() {
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        let mut p = RepeatPresence::new(ms(500));
+        assert_eq!(p.deadline(), None);
+        assert_eq!(p.seen("0012345678", t0), Scan::Arrived { replaced: None });
+        assert_eq!(p.seen("0012345678", t0 + ms(300)), Scan::Repeat);
+        assert_eq!(p.expire(t0 + ms(600)), None);
+        assert_eq!(p.deadline(), Some(t0 + ms(800)));
+        assert_eq!(p.expire(t0 + ms(800)), Some("0012345678".into()));
+        assert_eq!(p.deadline(), None);
+    }
+
+    #[test]
+    fn another_id_replaces_the_card() {
+        let t0 = Instant::now();
+        let mut p = RepeatPresence::new(Duration::from_millis(500));
+        p.seen("0012345678", t0);
+        assert_eq!(
+            p.seen("0087654321", t0),
+            Scan::Arrived {
+                replaced: Some("0012345678".into())
+            }
+        );
+        assert_eq!(
+            p.expire(t0 + Duration::from_secs(1)),
+            Some("0087654321".into())
+        );
+    }
+
+    #[test]
+    fn uid_is_uppercase_hex() {
+        assert_eq!(uid_hex(&[0x04, 0xa1, 0x0b, 0xff]), "04A10BFF");
+    }
+}
@@ -0,0 +1,258 @@
+//! Card management in the browser, for whoever would rather not edit YAML
+//! over SSH: the cards soundkid knows, and a form to give the last unknown
+//! card an open.spotify.com link. Served by the HTTP API when `ui` is set;
+//! assignments go to the [`CardStore`](crate::cards::CardStore).
+
+use axum::Router;
+use axum::extract::{Form, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+use std::fmt::Write;
+use tracing::warn;
+
+use crate::config::Action;
+use crate::http::{Api, Mapping, source};
+use crate::status::SeenCard;
+use crate::uri::canonicalize_uri;
+
+pub(crate) fn routes() -> Router<Api> {
+    Router::new()
+        .route("/", get(index))
+        .route("/cards", post(assign))
+}
+
+async fn index(State(api): State<Api>) -> Html<String> {
+    Html(render(&api, None).await)
+}
+
+#[derive(Deserialize)]
+struct Assignment {
+    device: String,
+    id: String,
+    link: String,
+}
+
+async fn assign(State(api): State<Api>, Form(form): Form<Assignment>) -> Response {
+    if source(&api.conf, &form.device).is_none() {
+        let message = format!("{:?} is not a reader in the config.", form.device);
+        return failed(&api, StatusCode::BAD_REQUEST, &message).await;
+    }
+    let uri = match canonicalize_uri(&form.link) {
+        Ok(uri) => uri,
+        Err(e) => return failed(&api, StatusCode::BAD_REQUEST, &e.to_string()).await,
+    };
+    if let Err(e) = api
+        .cards
+        .assign(&form.device, &form.id, Action::Play(uri))
+        .await
+    {
+        warn!("{e}");
+        return failed(&api, StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()).await;
+    }
+    let assigned = SeenCard {
+        reader: form.device,
+        id: form.id,
+    };
+    api.status.update(|s| {
+        if s.last_unknown.as_ref() == Some(&assigned) {
+            s.last_unknown = None;
+        }
+    });
+    Redirect::to("/").into_response()
+}
+
+/// The page again, with what went wrong on top.
+async fn failed(api: &Api, code: StatusCode, message: &str) -> Response {
+    (code, Html(render(api, Some(message)).await)).into_response()
+}
+
+async fn render(api: &Api, message: Option<&str>) -> String {
+    let unknown = api.status.get().last_unknown;
+    let unknown = unknown
+        .as_ref()
+        .map(|card| (card, source(&api.conf, &card.reader).is_some()));
+    page(&api.mappings().await, unknown, message)
+}
+
+/// The whole page. `unknown` is the last unknown card, and whether its
+/// reader is in the config (so it can be assigned).
+fn page(cards: &[Mapping], unknown: Option<(&SeenCard, bool)>, message: Option<&str>) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>soundkid</title><style>\
+         body{font-family:sans-serif;max-width:50em;margin:auto;padding:1em}\
+         table{border-collapse:collapse;width:100%}\
+         td,th{text-align:left;padding:.3em;border-bottom:1px solid #ccc;word-break:break-all}\
+         input[type=url]{width:100%;box-sizing:border-box;padding:.4em}\
+         .error{color:#b00}</style></head><body>\n<h1>soundkid</h1>\n",
+    );
+    if let Some(message) = message {
+        let _ = writeln!(html, "<p class=\"error\">{}</p>", escape(message));
+    }
+    html.push_str("<h2>New card</h2>\n");
+    match unknown {
+        Some((card, true)) => {
+            let _ = writeln!(
+                html,
+                "<p>Card <code>{id}</code> on <code>{reader}</code> doesn't play anything yet. \
+                 Paste a link from Spotify to give it one:</p>\n\
+                 <form method=\"post\" action=\"/cards\">\
+                 <input type=\"hidden\" name=\"device\" value=\"{reader}\">\
+                 <input type=\"hidden\" name=\"id\" value=\"{id}\">\
+                 <p><input type=\"url\" name=\"link\" required \
+                 placeholder=\"https://open.spotify.com/album/...\"></p>\
+                 <p><button>Assign</button></p></form>",
+                id = escape(&card.id),
+                reader = escape(&card.reader),
+            );
+        }
+        Some((card, false)) => {
+            let _ = writeln!(
+                html,
+                "<p>Card <code>{}</code> was read by <code>{}</code>, which is not a reader in \
+                 the config.</p>",
+                escape(&card.id),
+                escape(&card.reader),
+            );
+        }
+        None => html.push_str("<p>Hold a new card to a reader, then reload this page.</p>\n"),
+    }
+    html.push_str("<h2>Cards</h2>\n");
+    if cards.is_empty() {
+        html.push_str("<p>None yet.</p>\n");
+    } else {
+        html.push_str("<table><tr><th>Reader</th><th>Card</th><th>Does</th></tr>\n");
+        for card in cards {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td><code>{}</code></td><td>{}{}</td></tr>",
+                escape(&card.device),
+                escape(&card.id),
+                escape(&card.action.to_string()),
+                if card.assigned {
+                    " (assigned here)"
+                } else {
+                    ""
+                },
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::CardStore;
+    use crate::http::tests::{ALBUM, raw_request, serve_with};
+
+    const FORM: &str = "Content-Type: application/x-www-form-urlencoded\r\n";
+
+    fn unknown(reader: &str, id: &str) -> SeenCard {
+        SeenCard {
+            reader: reader.into(),
+            id: id.into(),
+        }
+    }
+
+    #[test]
+    fn escapes_what_it_shows() {
+        assert_eq!(
+            escape(r#"<a href="x">&'"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+        let card = unknown("<script>", "1");
+        assert!(page(&[], Some((&card, false)), None).contains("<code>&lt;script&gt;</code>"));
+    }
+
+    #[tokio::test]
+    async fn assigns_a_link_to_the_last_unknown_card() {
+        let dir = tempfile::tempdir().unwrap();
+        let cards = CardStore::new(dir.path());
+        let (addr, _rx, status) =
+            serve_with("{ bind: \"127.0.0.1:0\", ui: true }", cards.clone()).await;
+
+        let (code, _, body) = raw_request(addr, "GET", "/", "", "").await;
+        assert_eq!(code, 200);
+        assert!(body.contains("Hold a new card"), "{body}");
+        assert!(
+            body.contains("<code>04A1B2</code></td><td>PAUSE</td>"),
+            "{body}"
+        );
+
+        status.update(|s| s.last_unknown = Some(unknown("lid", "04C3D4")));
+        let (_, _, body) = raw_request(addr, "GET", "/", "", "").await;
+        assert!(body.contains(r#"name="id" value="04C3D4""#), "{body}");
+
+        let link = format!("https%3A%2F%2Fopen.spotify.com%2Falbum%2F{ALBUM}%3Fsi%3Dx");
+        let form = format!("device=lid&id=04C3D4&link={link}");
+        let (code, head, _) = raw_request(addr, "POST", "/cards", FORM, &form).await;
+        assert_eq!(code, 303, "{head}");
+        assert!(head.contains("location: /"), "{head}");
+        let album = Action::Play(format!("spotify:album:{ALBUM}"));
+        assert_eq!(cards.get("lid", "04C3D4").await, Some(album.clone()));
+        assert_eq!(
+            CardStore::load(dir.path()).await.get("lid", "04C3D4").await,
+            Some(album)
+        );
+        assert_eq!(status.get().last_unknown, None);
+
+        let (_, _, body) = raw_request(addr, "GET", "/", "", "").await;
+        assert!(body.contains("(assigned here)"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn rejects_links_that_are_not_spotify() {
+        let dir = tempfile::tempdir().unwrap();
+        let cards = CardStore::new(dir.path());
+        let (addr, _rx, _) = serve_with("{ bind: \"127.0.0.1:0\", ui: true }", cards.clone()).await;
+        let form = "device=lid&id=04C3D4&link=https%3A%2F%2Fexample.com%2Falbum%2Fx";
+        let (code, _, body) = raw_request(addr, "POST", "/cards", FORM, form).await;
+        assert_eq!(code, 400);
+        assert!(body.contains("class=\"error\""), "{body}");
+        let form = format!("device=shelf&id=1&link=spotify%3Aalbum%3A{ALBUM}");
+        let (code, _, body) = raw_request(addr, "POST", "/cards", FORM, &form).await;
+        assert_eq!(code, 400);
+        assert!(body.contains("not a reader in the config"), "{body}");
+        assert!(cards.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forms_posted_from_other_sites_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let cards = CardStore::new(dir.path());
+        let (addr, _rx, _) = serve_with("{ bind: \"127.0.0.1:0\", ui: true }", cards.clone()).await;
+        let form = format!("device=lid&id=04C3D4&link=spotify%3Aalbum%3A{ALBUM}");
+        let cross = format!("{FORM}Origin: http://evil.example\r\n");
+        let (code, _, _) = raw_request(addr, "POST", "/cards", &cross, &form).await;
+        assert_eq!(code, 403);
+        assert!(cards.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_ui_is_off_unless_configured() {
+        let cards = CardStore::new(std::path::Path::new("/nonexistent"));
+        let (addr, _rx, _) = serve_with("{ bind: \"127.0.0.1:0\" }", cards).await;
+        assert_eq!(raw_request(addr, "GET", "/", "", "").await.0, 404);
+    }
+}
@@ -13,6 +13,9 @@ pub enum Cmd {
     Stop,
     Pause,
     Resume,
+    TogglePause,
+    Next,
+    Previous,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -64,4 +67,19 @@ impl PlayerControl for FakePlayer {
         self.record(Cmd::Resume);
         Ok(())
     }
+
+    async fn toggle_pause(&self) -> Result<(), PlayerError> {
+        self.record(Cmd::TogglePause);
+        Ok(())
+    }
+
+    async fn next(&self) -> Result<(), PlayerError> {
+        self.record(Cmd::Next);
+        Ok(())
+    }
+
+    async fn previous(&self) -> Result<(), PlayerError> {
+        self.record(Cmd::Previous);
+        Ok(())
+    }
 }
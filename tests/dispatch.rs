@@ -4,11 +4,21 @@
 mod common;
 
 use common::{Cmd, FakePlayer};
-use soundkid::{config::Config, input::InputEvent, runtime::handle_input};
+use evdev::KeyCode;
+use soundkid::{
+    cards::CardStore,
+    config::{Action, Config},
+    input::{InputEvent, Press},
+    player::PlayerError,
+    runtime::handle_input,
+    status::{PlayState, SeenCard, StatusBoard},
+};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempfile::NamedTempFile;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
 const TRACK: &str = "6rqhFgbbKwnb9MLmUQDhG6";
 const ALBUM: &str = "7LQhG0xSDjFiKJnziyB3Zj";
@@ -37,18 +47,34 @@ fn gpio(line: u32) -> InputEvent {
     InputEvent::Gpio {
         chip: "/dev/gpiochip0".into(),
         line,
+        press: Press::Short,
     }
 }
 
+/// A card store nothing gets assigned to, so it never writes.
+fn no_cards() -> CardStore {
+    CardStore::new(Path::new("/nonexistent"))
+}
+
 /// Drive handle_input to completion: spawn it, send events, drop the sender,
 /// await.
 async fn run_dispatch(
     conf: Config,
     fake: FakePlayer,
     events: Vec<InputEvent>,
+) -> Result<(), soundkid::player::PlayerError> {
+    run_dispatch_with(conf, fake, events, StatusBoard::default(), no_cards()).await
+}
+
+async fn run_dispatch_with(
+    conf: Config,
+    fake: FakePlayer,
+    events: Vec<InputEvent>,
+    status: StatusBoard,
+    cards: CardStore,
 ) -> Result<(), soundkid::player::PlayerError> {
     let (tx, rx) = mpsc::channel(8);
-    let task = tokio::spawn(handle_input(conf, rx, fake));
+    let task = tokio::spawn(handle_input(conf, rx, fake, status, cards));
     for ev in events {
         tx.send(ev).await.unwrap();
     }
@@ -246,6 +272,118 @@ gpio:
     assert_eq!(fake.commands(), vec![Cmd::Pause]);
 }
 
+#[tokio::test]
+async fn key_mode_device_dispatches_by_key_and_press() {
+    let yaml = r#"
+alsa: {}
+spotify: {}
+input:
+  "Media Remote":
+    keys:
+      KEY_PLAYPAUSE: PLAY_PAUSE
+      KEY_NEXTSONG: { short: NEXT, long: STOP }
+"#;
+    let conf = load_yaml(yaml).await;
+    let fake = FakePlayer::new();
+    let key = |code, press| InputEvent::Key {
+        device: "Media Remote".into(),
+        code,
+        press,
+    };
+    run_dispatch(
+        conf,
+        fake.clone(),
+        vec![
+            key(KeyCode::KEY_PLAYPAUSE, Press::Short),
+            key(KeyCode::KEY_NEXTSONG, Press::Short),
+            key(KeyCode::KEY_NEXTSONG, Press::Long),
+            key(KeyCode::KEY_VOLUMEUP, Press::Short),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![Cmd::TogglePause, Cmd::Next, Cmd::Stop]
+    );
+}
+
+#[tokio::test]
+async fn rotary_steps_and_switch_dispatch_via_rotary_table() {
+    let yaml = r#"
+alsa: {}
+spotify: {}
+rotary:
+  tracks:
+    chip: /dev/gpiochip0
+    a: 5
+    b: 6
+    cw: NEXT
+    ccw: PREVIOUS
+    switch: { line: 13, short: PLAY_PAUSE }
+"#;
+    let conf = load_yaml(yaml).await;
+    let fake = FakePlayer::new();
+    let encoder = || "tracks".to_string();
+    run_dispatch(
+        conf,
+        fake.clone(),
+        vec![
+            InputEvent::RotaryCw { encoder: encoder() },
+            InputEvent::RotaryCw { encoder: encoder() },
+            InputEvent::RotaryCcw { encoder: encoder() },
+            InputEvent::RotaryPress {
+                encoder: encoder(),
+                press: Press::Short,
+            },
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![Cmd::Next, Cmd::Next, Cmd::Previous, Cmd::TogglePause]
+    );
+}
+
+#[tokio::test]
+async fn repeated_scan_within_debounce_window_dispatches_once() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+input:
+  /dev/input/event0:
+    debounce_ms: 60000
+    cards:
+      "PLAY": "spotify:track:{TRACK}"
+      "PAUSE_IT": "PAUSE"
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let fake = FakePlayer::new();
+    run_dispatch(
+        conf,
+        fake.clone(),
+        vec![
+            evdev("PLAY"),
+            evdev("PLAY"),
+            evdev("PAUSE_IT"),
+            evdev("PLAY"),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+            Cmd::Pause,
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+        ]
+    );
+}
+
 #[tokio::test]
 async fn player_failure_short_circuits_dispatch() {
     let yaml = format!(
@@ -263,7 +401,13 @@ input:
     fake.arm_play_failure();
 
     let (tx, rx) = mpsc::channel(8);
-    let task = tokio::spawn(handle_input(conf, rx, fake.clone()));
+    let task = tokio::spawn(handle_input(
+        conf,
+        rx,
+        fake.clone(),
+        StatusBoard::default(),
+        no_cards(),
+    ));
     tx.send(evdev("PLAY")).await.unwrap();
     // Don't drop tx; the loop should exit on the play() error before the
     // second event even gets dispatched.
@@ -302,3 +446,496 @@ fn error_chain(err: &dyn std::error::Error) -> String {
     }
     messages.join(" / ")
 }
+
+#[tokio::test]
+async fn rfid_cards_dispatch_via_rfid_table_and_removal_is_ignored() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+rfid:
+  lid:
+    driver: mfrc522
+    path: /dev/spidev0.0
+    cards:
+      "04A1B2C3D4E5F6": "spotify:album:{ALBUM}"
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let fake = FakePlayer::new();
+    let uid = || "04A1B2C3D4E5F6".to_string();
+    run_dispatch(
+        conf,
+        fake.clone(),
+        vec![
+            InputEvent::Card {
+                reader: "lid".into(),
+                uid: uid(),
+                uri: None,
+            },
+            InputEvent::CardRemoved {
+                reader: "lid".into(),
+                uid: uid(),
+            },
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![Cmd::Play(format!("spotify:album:{ALBUM}"))]
+    );
+}
+
+#[tokio::test]
+async fn ndef_uris_play_unless_the_card_is_mapped() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+rfid:
+  shelf:
+    driver: pn532
+    path: /dev/i2c-1
+    ndef: true
+    cards:
+      "04A1B2C3D4E5F6": "spotify:album:{ALBUM}"
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let fake = FakePlayer::new();
+    let card = |uid: &str, uri: &str| InputEvent::Card {
+        reader: "shelf".into(),
+        uid: uid.into(),
+        uri: Some(uri.into()),
+    };
+    run_dispatch(
+        conf,
+        fake.clone(),
+        vec![
+            card(
+                "04112233445566",
+                &format!("https://open.spotify.com/track/{TRACK}?si=x"),
+            ),
+            // The config entry wins over what the tag says.
+            card("04A1B2C3D4E5F6", &format!("spotify:track:{TRACK}")),
+            card("04778899AABBCC", "https://example.com/"),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+            Cmd::Play(format!("spotify:album:{ALBUM}")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn lifting_the_playing_card_pauses_until_it_comes_back() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+input:
+  /dev/input/event0:
+    removal_ms: 500
+    on_remove: pause
+    cards:
+      "12345": "spotify:track:{TRACK}"
+      "67890": "spotify:album:{ALBUM}"
+      "VOL": VOLUME_INCREASE
+rfid:
+  lid:
+    driver: mfrc522
+    path: /dev/spidev0.0
+    on_remove: stop
+    cards:
+      "04A1B2C3D4E5F6": "spotify:album:{ALBUM}"
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let fake = FakePlayer::new();
+    let removed = |reader: &str, uid: &str| InputEvent::CardRemoved {
+        reader: reader.into(),
+        uid: uid.into(),
+    };
+    let card = || InputEvent::Card {
+        reader: "lid".into(),
+        uid: "04A1B2C3D4E5F6".into(),
+        uri: None,
+    };
+    let hid = "/dev/input/event0";
+    run_dispatch(
+        conf,
+        fake.clone(),
+        vec![
+            evdev("12345"),
+            // Another card's removal leaves playback alone.
+            removed(hid, "VOL"),
+            removed(hid, "12345"),
+            evdev("12345"),
+            removed(hid, "12345"),
+            // A different card starts fresh, and the first no longer resumes.
+            evdev("67890"),
+            evdev("12345"),
+            // The RFID reader stops instead.
+            card(),
+            removed("lid", "04A1B2C3D4E5F6"),
+            card(),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+            Cmd::Pause,
+            Cmd::Resume,
+            Cmd::Pause,
+            Cmd::Play(format!("spotify:album:{ALBUM}")),
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+            Cmd::Play(format!("spotify:album:{ALBUM}")),
+            Cmd::Stop,
+            Cmd::Play(format!("spotify:album:{ALBUM}")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_card_put_back_after_playback_stopped_plays_again() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+input:
+  /dev/input/event0:
+    removal_ms: 500
+    on_remove: pause
+    cards:
+      "12345": "spotify:track:{TRACK}"
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let fake = FakePlayer::new();
+    let status = StatusBoard::default();
+    let (tx, rx) = mpsc::channel(8);
+    let task = tokio::spawn(handle_input(
+        conf,
+        rx,
+        fake.clone(),
+        status.clone(),
+        no_cards(),
+    ));
+    tx.send(evdev("12345")).await.unwrap();
+    tx.send(InputEvent::CardRemoved {
+        reader: "/dev/input/event0".into(),
+        uid: "12345".into(),
+    })
+    .await
+    .unwrap();
+    while fake.commands().len() < 2 {
+        tokio::task::yield_now().await;
+    }
+    // The player paused, then gave up waiting (stop_on_idle_minutes).
+    status.update(|s| s.state = PlayState::Paused);
+    status.update(|s| s.state = PlayState::Stopped);
+    tx.send(evdev("12345")).await.unwrap();
+    drop(tx);
+    task.await.unwrap().unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+            Cmd::Pause,
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn control_actions_dispatch_and_cards_are_noted_on_the_status() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+input:
+  /dev/input/event0:
+    "12345": "spotify:track:{TRACK}"
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let fake = FakePlayer::new();
+    let status = StatusBoard::default();
+    let control = |action| InputEvent::Control {
+        client: "socket".into(),
+        action,
+    };
+    run_dispatch_with(
+        conf,
+        fake.clone(),
+        vec![
+            evdev("99999"),
+            evdev("12345"),
+            control(Action::Pause),
+            control(Action::Play(format!("spotify:album:{ALBUM}"))),
+        ],
+        status.clone(),
+        no_cards(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+            Cmd::Pause,
+            Cmd::Play(format!("spotify:album:{ALBUM}")),
+        ]
+    );
+    let seen = |id: &str| {
+        Some(SeenCard {
+            reader: "/dev/input/event0".into(),
+            id: id.into(),
+        })
+    };
+    let status = status.get();
+    assert_eq!(status.last_card, seen("12345"));
+    assert_eq!(status.last_unknown, seen("99999"));
+}
+
+#[tokio::test]
+async fn assigned_cards_play_unless_the_config_maps_them() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+input:
+  /dev/input/event0:
+    "12345": "spotify:track:{TRACK}"
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let dir = tempfile::tempdir().unwrap();
+    let cards = CardStore::new(dir.path());
+    let album = Action::Play(format!("spotify:album:{ALBUM}"));
+    cards
+        .assign("/dev/input/event0", "99999", album.clone())
+        .await
+        .unwrap();
+    cards
+        .assign("/dev/input/event0", "12345", Action::Stop)
+        .await
+        .unwrap();
+    let fake = FakePlayer::new();
+    let status = StatusBoard::default();
+    run_dispatch_with(
+        conf,
+        fake.clone(),
+        vec![evdev("99999"), evdev("12345"), evdev("55555")],
+        status.clone(),
+        cards,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![
+            Cmd::Play(format!("spotify:album:{ALBUM}")),
+            Cmd::Play(format!("spotify:track:{TRACK}")),
+        ]
+    );
+    assert_eq!(
+        status.get().last_unknown,
+        Some(SeenCard {
+            reader: "/dev/input/event0".into(),
+            id: "55555".into(),
+        })
+    );
+}
+
+fn control(action: Action) -> InputEvent {
+    InputEvent::Control {
+        client: "socket".into(),
+        action,
+    }
+}
+
+#[tokio::test]
+async fn learn_mode_hands_out_the_queue_to_new_cards() {
+    let yaml = format!(
+        r#"
+alsa: {{}}
+spotify: {{}}
+input:
+  /dev/input/event0:
+    "12345": "spotify:track:{TRACK}"
+    "ADMIN": LEARN
+learn:
+  queue:
+    - spotify:track:{TRACK}
+    - https://open.spotify.com/album/{ALBUM}
+    - spotify:track:{ALBUM}
+"#
+    );
+    let conf = load_yaml(&yaml).await;
+    let dir = tempfile::tempdir().unwrap();
+    let cards = CardStore::new(dir.path());
+    let fake = FakePlayer::new();
+    let status = StatusBoard::default();
+    run_dispatch_with(
+        conf,
+        fake.clone(),
+        vec![
+            evdev("ADMIN"),
+            evdev("55555"),
+            evdev("ADMIN"),
+            evdev("66666"),
+            evdev("77777"),
+            // The queue is used up, so this one has nothing to learn.
+            evdev("ADMIN"),
+            evdev("88888"),
+        ],
+        status.clone(),
+        cards.clone(),
+    )
+    .await
+    .unwrap();
+    // The first URI in the queue is taken by the config already.
+    let album = Action::Play(format!("spotify:album:{ALBUM}"));
+    let second = Action::Play(format!("spotify:track:{ALBUM}"));
+    assert_eq!(
+        fake.commands(),
+        vec![
+            Cmd::Play(format!("spotify:album:{ALBUM}")),
+            Cmd::Play(format!("spotify:track:{ALBUM}")),
+        ]
+    );
+    let learned = CardStore::load(dir.path()).await;
+    assert_eq!(learned.get("/dev/input/event0", "55555").await, Some(album));
+    assert_eq!(
+        learned.get("/dev/input/event0", "66666").await,
+        Some(second)
+    );
+    assert_eq!(learned.get("/dev/input/event0", "77777").await, None);
+    assert_eq!(
+        status.get().last_unknown,
+        Some(SeenCard {
+            reader: "/dev/input/event0".into(),
+            id: "88888".into(),
+        })
+    );
+    assert_eq!(status.get().learn, None);
+}
+
+#[tokio::test]
+async fn learn_mode_gives_cards_what_was_last_played_remotely() {
+    let conf = load_yaml("alsa: {}\nspotify: {}\n").await;
+    let dir = tempfile::tempdir().unwrap();
+    let cards = CardStore::new(dir.path());
+    let fake = FakePlayer::new();
+    let status = StatusBoard::default();
+    let album = format!("spotify:album:{ALBUM}");
+    run_dispatch_with(
+        conf,
+        fake.clone(),
+        vec![
+            control(Action::Play(album.clone())),
+            control(Action::Learn(None)),
+            evdev("55555"),
+            // LEARN again leaves learn mode.
+            control(Action::Learn(None)),
+            control(Action::Learn(None)),
+            evdev("66666"),
+            control(Action::Learn(Some(format!("spotify:track:{TRACK}")))),
+        ],
+        status.clone(),
+        cards.clone(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![Cmd::Play(album.clone()), Cmd::Play(album.clone())]
+    );
+    assert_eq!(
+        cards.get("/dev/input/event0", "55555").await,
+        Some(Action::Play(album))
+    );
+    assert_eq!(cards.get("/dev/input/event0", "66666").await, None);
+    assert_eq!(
+        status.get().learn,
+        Some(Action::Play(format!("spotify:track:{TRACK}")))
+    );
+}
+
+/// Enter learn mode with `learn.timeout_secs` and wait until the dispatch
+/// loop has taken it up.
+async fn start_learning(
+    timeout_secs: u64,
+    status: &StatusBoard,
+    cards: &CardStore,
+) -> (
+    mpsc::Sender<InputEvent>,
+    JoinHandle<Result<(), PlayerError>>,
+    FakePlayer,
+) {
+    let conf = load_yaml(&format!(
+        "alsa: {{}}\nspotify: {{}}\nlearn: {{ timeout_secs: {timeout_secs} }}\n"
+    ))
+    .await;
+    let mut learn = status.subscribe();
+    let (tx, rx) = mpsc::channel(8);
+    let fake = FakePlayer::new();
+    let task = tokio::spawn(handle_input(
+        conf,
+        rx,
+        fake.clone(),
+        status.clone(),
+        cards.clone(),
+    ));
+    let album = format!("spotify:album:{ALBUM}");
+    tx.send(control(Action::Learn(Some(album)))).await.unwrap();
+    learn.wait_for(|s| s.learn.is_some()).await.unwrap();
+    (tx, task, fake)
+}
+
+#[tokio::test(start_paused = true)]
+async fn learn_mode_ends_when_no_card_comes() {
+    let dir = tempfile::tempdir().unwrap();
+    let cards = CardStore::new(dir.path());
+    let status = StatusBoard::default();
+    let (tx, task, fake) = start_learning(1, &status, &cards).await;
+    let mut learn = status.subscribe();
+    tokio::time::advance(Duration::from_millis(990)).await;
+    tokio::task::yield_now().await;
+    assert!(status.get().learn.is_some());
+    tokio::time::advance(Duration::from_millis(20)).await;
+    learn.wait_for(|s| s.learn.is_none()).await.unwrap();
+    tx.send(evdev("55555")).await.unwrap();
+    drop(tx);
+    task.await.unwrap().unwrap();
+    assert!(fake.commands().is_empty());
+    assert_eq!(cards.get("/dev/input/event0", "55555").await, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn learn_mode_with_an_endless_timeout_waits() {
+    let dir = tempfile::tempdir().unwrap();
+    let cards = CardStore::new(dir.path());
+    let status = StatusBoard::default();
+    let (tx, task, fake) = start_learning(u64::MAX, &status, &cards).await;
+    tokio::time::advance(Duration::from_secs(365 * 24 * 3600)).await;
+    tokio::task::yield_now().await;
+    assert!(status.get().learn.is_some());
+    tx.send(evdev("55555")).await.unwrap();
+    drop(tx);
+    task.await.unwrap().unwrap();
+    assert_eq!(
+        fake.commands(),
+        vec![Cmd::Play(format!("spotify:album:{ALBUM}"))]
+    );
+}